@@ -21,7 +21,9 @@ CREATE TABLE IF NOT EXISTS [label_set] (
 
 CREATE TABLE IF NOT EXISTS [cluster_group] (
    id INTEGER PRIMARY KEY AUTOINCREMENT,
-   config TEXT NOT NULL
+   eps REAL NOT NULL,
+   min_pts INTEGER NOT NULL,
+   min_cluster_size INTEGER NOT NULL
 );
 
 CREATE TABLE IF NOT EXISTS [cluster] (
@@ -35,7 +37,7 @@ CREATE TABLE IF NOT EXISTS [cluster] (
 CREATE TABLE IF NOT EXISTS [monitoring_data] (
    timestamp DATETIME NOT NULL,
    label_set_id INTEGER NOT NULL,
-   cluster_id INTEGER NOT NULL,
+   cluster_id INTEGER,  -- NULL for noise, which isn't in any cluster
    count INTEGER NOT NULL,
 
    PRIMARY KEY (timestamp, label_set_id),
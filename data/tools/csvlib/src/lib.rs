@@ -21,11 +21,11 @@ use flate2::read::GzDecoder;
 use serde::{Deserialize, Serialize};
 use std::{
     fs::File,
-    io::{BufReader, Error, Read, Write},
+    io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write},
 };
 
 /// A record used to store a single time series.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Value {
     pub timestamp_secs: i64,
     pub timestamp_nanos: i32,
@@ -51,22 +51,64 @@ impl AsRef<Fraction> for Fraction {
     }
 }
 
-/// Opens a file for reading, automatically decompressing it if it ends in ".gz".
+#[cfg(feature = "zstd")]
+fn zstd_decoder(f: File) -> Result<Box<dyn Read>, Error> {
+    Ok(Box::new(zstd::stream::read::Decoder::new(f)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder(_: File) -> Result<Box<dyn Read>, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "reading .zst files requires the zstd feature",
+    ))
+}
+
+#[cfg(feature = "bzip2")]
+fn bzip2_decoder(f: File) -> Result<Box<dyn Read>, Error> {
+    Ok(Box::new(bzip2::read::BzDecoder::new(f)))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn bzip2_decoder(_: File) -> Result<Box<dyn Read>, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "reading .bz2 files requires the bzip2 feature",
+    ))
+}
+
+/// Opens a file for reading, automatically decompressing it based on its extension.
+///
+/// Files ending in ".gz" are always supported, while ".zst" and ".bz2" need
+/// the `zstd` and `bzip2` features. Any other file is read as is.
 pub fn open_gzip_or_regular_file(path: &str) -> Result<BufReader<Box<dyn Read>>, Error> {
     let f = File::open(path)?;
-    Ok(if path.ends_with(".gz") {
-        BufReader::new(Box::new(GzDecoder::new(f)))
+    let reader: Box<dyn Read> = if path.ends_with(".gz") {
+        Box::new(GzDecoder::new(f))
+    } else if path.ends_with(".zst") {
+        zstd_decoder(f)?
+    } else if path.ends_with(".bz2") {
+        bzip2_decoder(f)?
     } else {
-        BufReader::new(Box::new(f))
-    })
+        Box::new(f)
+    };
+    Ok(BufReader::new(reader))
 }
 
-/// Reads a time series samples from a CSV file.
-pub fn read_values<R: Read>(reader: R) -> Vec<Value> {
+/// Lazily reads time series samples from a CSV file, one record at a time.
+pub fn stream_values<R: Read>(reader: R) -> impl Iterator<Item = Result<Value, csv::Error>> {
     csv::ReaderBuilder::new()
         .has_headers(true)
         .from_reader(reader)
-        .deserialize::<Value>()
+        .into_deserialize::<Value>()
+}
+
+/// Reads a time series samples from a CSV file.
+///
+/// Malformed records are logged and skipped. Use [try_read_values] when
+/// missing samples would go unnoticed.
+pub fn read_values<R: Read>(reader: R) -> Vec<Value> {
+    stream_values(reader)
         .filter_map(|r| {
             if let Ok(v) = r {
                 Some(v)
@@ -78,6 +120,37 @@ pub fn read_values<R: Read>(reader: R) -> Vec<Value> {
         .collect()
 }
 
+/// Reads a time series samples from a CSV file.
+///
+/// Unlike [read_values], this fails on the first malformed record.
+pub fn try_read_values<R: Read>(reader: R) -> Result<Vec<Value>, csv::Error> {
+    stream_values(reader).collect()
+}
+
+/// Lazily reads points of an ECDF from a CSV file, one record at a time.
+pub fn stream_fractions<R: Read>(reader: R) -> impl Iterator<Item = Result<Fraction, csv::Error>> {
+    csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(reader)
+        .into_deserialize::<Fraction>()
+}
+
+/// Reads points of an ECDF from a CSV file.
+///
+/// Malformed records are logged and skipped.
+pub fn read_fractions<R: Read>(reader: R) -> Vec<Fraction> {
+    stream_fractions(reader)
+        .filter_map(|r| {
+            if let Ok(f) = r {
+                Some(f)
+            } else {
+                warn!("{:?}", r.unwrap_err());
+                None
+            }
+        })
+        .collect()
+}
+
 /// Writes time series samples to a CSV file.
 pub fn write_values<W, I, V>(writer: W, values: I) -> Result<(), Error>
 where
@@ -111,3 +184,351 @@ where
     writer.flush()?;
     Ok(())
 }
+
+/// How to write the rows produced by [RowWriter].
+///
+/// With the `clap` feature, this can be used as a command-line argument.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Format {
+    /// Comma separated values, with a header.
+    Csv,
+    /// One JSON object per line. This needs the `json` feature.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+/// The area difference calculated for a single row.
+#[derive(Serialize)]
+pub struct Row<'a> {
+    pub timestamp: &'a str,
+    pub area_difference: f64,
+}
+
+/// Writes a record for every row as it is processed.
+pub enum RowWriter {
+    Csv(csv::Writer<Box<dyn Write>>),
+    #[cfg(feature = "json")]
+    Json(Box<dyn Write>),
+}
+
+impl RowWriter {
+    pub fn new(writer: Box<dyn Write>, format: Format) -> RowWriter {
+        match format {
+            Format::Csv => RowWriter::Csv(csv::Writer::from_writer(writer)),
+            #[cfg(feature = "json")]
+            Format::Json => RowWriter::Json(writer),
+        }
+    }
+
+    /// Opens `path` for writing, or standard output if it is "-".
+    pub fn create(path: &str, format: Format) -> Result<RowWriter, Error> {
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        Ok(RowWriter::new(writer, format))
+    }
+
+    pub fn write(&mut self, timestamp: &str, area_difference: f64) -> Result<(), Error> {
+        let row = Row {
+            timestamp,
+            area_difference,
+        };
+        match self {
+            RowWriter::Csv(w) => w.serialize(row)?,
+            #[cfg(feature = "json")]
+            RowWriter::Json(w) => {
+                serde_json::to_writer(&mut *w, &row)?;
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            RowWriter::Csv(w) => w.flush(),
+            #[cfg(feature = "json")]
+            RowWriter::Json(w) => w.flush(),
+        }
+    }
+}
+
+/// The Parquet schema for [Value] records.
+#[cfg(feature = "parquet")]
+const VALUE_SCHEMA: &str = "
+    message value {
+        REQUIRED INT64 timestamp_secs;
+        REQUIRED INT32 timestamp_nanos;
+        REQUIRED DOUBLE value;
+    }
+";
+
+/// The maximum number of values written to each Parquet row group.
+#[cfg(feature = "parquet")]
+const ROW_GROUP_SIZE: usize = 1 << 20;
+
+#[cfg(feature = "parquet")]
+fn parquet_error(e: parquet::errors::ParquetError) -> Error {
+    Error::new(ErrorKind::Other, e)
+}
+
+/// Writes time series samples to a Parquet file.
+///
+/// This is much more compact than CSV for large archives, but needs the
+/// `parquet` feature.
+#[cfg(feature = "parquet")]
+pub fn write_values_parquet<W, I, V>(writer: W, values: I) -> Result<(), Error>
+where
+    W: Write + Send,
+    V: AsRef<Value>,
+    I: IntoIterator<Item = V>,
+{
+    use parquet::data_type::{DoubleType, Int32Type, Int64Type};
+    use parquet::file::{properties::WriterProperties, writer::SerializedFileWriter};
+    use parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    let schema = Arc::new(parse_message_type(VALUE_SCHEMA).map_err(parquet_error)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer =
+        SerializedFileWriter::new(writer, schema, properties).map_err(parquet_error)?;
+
+    let mut secs = Vec::new();
+    let mut nanos = Vec::new();
+    let mut vals = Vec::new();
+    let mut values = values.into_iter().peekable();
+    while values.peek().is_some() {
+        secs.clear();
+        nanos.clear();
+        vals.clear();
+        for v in values.by_ref().take(ROW_GROUP_SIZE) {
+            let v = v.as_ref();
+            secs.push(v.timestamp_secs);
+            nanos.push(v.timestamp_nanos);
+            vals.push(v.value);
+        }
+
+        let mut row_group = writer.next_row_group().map_err(parquet_error)?;
+        let mut column = row_group
+            .next_column()
+            .map_err(parquet_error)?
+            .expect("timestamp_secs column");
+        column
+            .typed::<Int64Type>()
+            .write_batch(&secs, None, None)
+            .map_err(parquet_error)?;
+        column.close().map_err(parquet_error)?;
+        let mut column = row_group
+            .next_column()
+            .map_err(parquet_error)?
+            .expect("timestamp_nanos column");
+        column
+            .typed::<Int32Type>()
+            .write_batch(&nanos, None, None)
+            .map_err(parquet_error)?;
+        column.close().map_err(parquet_error)?;
+        let mut column = row_group
+            .next_column()
+            .map_err(parquet_error)?
+            .expect("value column");
+        column
+            .typed::<DoubleType>()
+            .write_batch(&vals, None, None)
+            .map_err(parquet_error)?;
+        column.close().map_err(parquet_error)?;
+        row_group.close().map_err(parquet_error)?;
+    }
+    writer.close().map_err(parquet_error)?;
+    Ok(())
+}
+
+/// Reads time series samples from a Parquet file written by
+/// [write_values_parquet].
+#[cfg(feature = "parquet")]
+pub fn read_values_parquet<R>(reader: R) -> Result<Vec<Value>, Error>
+where
+    R: parquet::file::reader::ChunkReader + 'static,
+{
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    let reader = SerializedFileReader::new(reader).map_err(parquet_error)?;
+    let rows = reader.get_row_iter(None).map_err(parquet_error)?;
+    rows.map(|row| {
+        let row = row.map_err(parquet_error)?;
+        Ok(Value {
+            timestamp_secs: row.get_long(0).map_err(parquet_error)?,
+            timestamp_nanos: row.get_int(1).map_err(parquet_error)?,
+            value: row.get_double(2).map_err(parquet_error)?,
+        })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generates an endless CSV file, one row per read.
+    struct Endless {
+        header: bool,
+        row: i64,
+    }
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let line = if self.header {
+                self.header = false;
+                "timestamp_secs,timestamp_nanos,value\n".to_string()
+            } else {
+                self.row += 1;
+                format!("{},0,1.5\n", self.row)
+            };
+            let n = line.len().min(buf.len());
+            buf[..n].copy_from_slice(&line.as_bytes()[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn stream_is_lazy() {
+        let reader = Endless {
+            header: true,
+            row: 0,
+        };
+        let values: Vec<Value> = stream_values(reader).take(3).map(Result::unwrap).collect();
+        let timestamps: Vec<i64> = values.iter().map(|v| v.timestamp_secs).collect();
+        assert_eq!(timestamps, [1, 2, 3]);
+    }
+
+    #[test]
+    fn stream_surfaces_errors() {
+        let csv = "timestamp_secs,timestamp_nanos,value\n1,0,1.5\n2,0,oops\n3,0,2.5\n";
+        let results: Vec<_> = stream_values(csv.as_bytes()).collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn strict_and_lenient_reads() {
+        let csv = "timestamp_secs,timestamp_nanos,value\n1,0,1.5\n2,0,oops\n3,0,2.5\n";
+        assert!(try_read_values(csv.as_bytes()).is_err());
+        let values = read_values(csv.as_bytes());
+        let timestamps: Vec<i64> = values.iter().map(|v| v.timestamp_secs).collect();
+        assert_eq!(timestamps, [1, 3]);
+    }
+
+    /// Writes `data` to a temporary file, compressed with `compress`, and
+    /// reads it back.
+    fn round_trip(extension: &str, compress: impl FnOnce(&[u8]) -> Vec<u8>) {
+        let data = b"timestamp_secs,timestamp_nanos,value\n1,0,1.5\n";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("values.csv{}", extension));
+        std::fs::write(&path, compress(data)).unwrap();
+        let mut got = Vec::new();
+        open_gzip_or_regular_file(path.to_str().unwrap())
+            .and_then(|mut r| r.read_to_end(&mut got))
+            .unwrap();
+        assert_eq!(got, data);
+    }
+
+    #[test]
+    fn open_plain() {
+        round_trip("", |data| data.to_vec());
+    }
+
+    #[test]
+    fn open_gzip() {
+        round_trip(".gz", |data| {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        });
+    }
+
+    #[test]
+    fn open_zstd() {
+        round_trip(".zst", |data| zstd::encode_all(data, 0).unwrap());
+    }
+
+    #[test]
+    fn open_bzip2() {
+        round_trip(".bz2", |data| {
+            let mut encoder =
+                bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        });
+    }
+
+    #[test]
+    fn fractions_round_trip() {
+        let fractions = vec![
+            Fraction {
+                value: -1.5,
+                fraction: 0.25,
+            },
+            Fraction {
+                value: 3.0,
+                fraction: 1.0,
+            },
+        ];
+        let mut buffer = Vec::new();
+        write_fractions(&mut buffer, &fractions).unwrap();
+        let got = read_fractions(buffer.as_slice());
+        assert_eq!(got.len(), fractions.len());
+        for (got, want) in got.iter().zip(fractions.iter()) {
+            assert_eq!(got.value, want.value);
+            assert_eq!(got.fraction, want.fraction);
+        }
+    }
+
+    #[test]
+    fn values_parquet_round_trip() {
+        let values: Vec<Value> = (0..5)
+            .map(|i| Value {
+                timestamp_secs: 1_700_000_000 + i,
+                timestamp_nanos: (i * 250_000_000) as i32,
+                value: i as f64 * -1.5,
+            })
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("values.parquet");
+        write_values_parquet(File::create(&path).unwrap(), &values).unwrap();
+        let got = read_values_parquet(File::open(&path).unwrap()).unwrap();
+        assert_eq!(got, values);
+
+        // An empty file is still valid.
+        let mut buffer = Vec::new();
+        write_values_parquet(&mut buffer, Vec::<Value>::new()).unwrap();
+        assert!(!buffer.is_empty());
+    }
+
+    #[test]
+    fn row_writer() {
+        let cases = [
+            (Format::Csv, "timestamp,area_difference\n1,0.5\n2,0.25\n"),
+            (
+                Format::Json,
+                "{\"timestamp\":\"1\",\"area_difference\":0.5}\n{\"timestamp\":\"2\",\"area_difference\":0.25}\n",
+            ),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        for (format, want) in cases {
+            let path = dir.path().join(format!("rows-{:?}", format));
+            let path = path.to_str().unwrap();
+            let mut out = RowWriter::create(path, format).unwrap();
+            out.write("1", 0.5).unwrap();
+            out.write("2", 0.25).unwrap();
+            out.flush().unwrap();
+            drop(out);
+            assert_eq!(std::fs::read_to_string(path).unwrap(), want);
+        }
+    }
+}
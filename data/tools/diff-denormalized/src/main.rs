@@ -17,115 +17,109 @@
 // limitations under the License.
 
 use clap::Parser;
+use csvlib::{Format, RowWriter};
 use env_logger::Env;
 use mumble::ecdf::ECDF;
-use mumble_prometheus::{histogram_to_ecdf, parse_histogram};
+use mumble_prometheus::{histogram_to_ecdf, is_native, parse_histogram, HistogramError};
+use statlib::MinMeanMax;
 
-use std::fmt::{self, Display};
-
-struct MinMeanMax {
-    samples: Vec<f64>,
-    sum: f64,
-}
-
-impl MinMeanMax {
-    fn new() -> Self {
-        Self {
-            samples: Vec::new(),
-            sum: 0.0,
-        }
-    }
-
-    fn update(&mut self, x: f64) {
-        self.samples.push(x);
-        self.sum += x;
-    }
+#[derive(Parser)]
+struct Cli {
+    /// The path to the input database.
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    input_database: String,
 
-    fn min(&self) -> f64 {
-        self.samples
-            .iter()
-            .cloned()
-            .reduce(|a, b| if b < a { b } else { a })
-            .unwrap_or(0.0)
-    }
+    /// Report rows that fail to deserialize instead of aborting.
+    #[arg(long)]
+    validate: bool,
 
-    fn max(&self) -> f64 {
-        self.samples
-            .iter()
-            .cloned()
-            .reduce(|a, b| if b > a { b } else { a })
-            .unwrap_or(0.0)
-    }
+    /// Where to write the area difference of every row, or "-" for standard
+    /// output. The summary is always written to standard error.
+    #[arg(short, long)]
+    output: Option<String>,
 
-    fn mean(&self) -> f64 {
-        if self.samples.is_empty() {
-            return 0.0;
-        }
-        self.sum / self.samples.len() as f64
-    }
+    /// The format of the rows written to --output.
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+}
 
-    fn lo_stdev(&self, mean: f64) -> f64 {
-        if self.samples.is_empty() {
-            return 0.0;
-        }
-        let mut sum = 0.0;
-        let mut count = 0;
-        for &x in self.samples.iter() {
-            if x > mean {
-                continue;
-            }
-            let diff = mean - x;
-            sum += diff * diff;
-            count += 1;
-        }
-        if count == 0 {
-            return 0.0;
-        }
-        mean - (sum / count as f64).sqrt()
+/// Calculates the area difference between a full sample and its histogram.
+fn row_difference(full: &[u8], histogram: &[u8]) -> Result<f64, String> {
+    let full: ECDF<f64> =
+        rmp_serde::from_slice(full).map_err(|e| format!("deserialize full sample: {}", e))?;
+    let h = parse_histogram(histogram).map_err(|e| format!("parse histogram: {}", e))?;
+    // Classic buckets, or float counts, would trip the asserts in histogram_to_ecdf.
+    if !is_native(&h) {
+        return Err(format!(
+            "convert histogram: {}",
+            HistogramError::Unsupported
+        ));
     }
+    let other = histogram_to_ecdf(&h);
+    Ok(full.interpolate().area_difference(&other))
+}
 
-    fn hi_stdev(&self, mean: f64) -> f64 {
-        if self.samples.is_empty() {
-            return 0.0;
-        }
-        let mut sum = 0.0;
-        let mut count = 0;
-        for &x in self.samples.iter() {
-            if x < mean {
-                continue;
+/// Accumulates area difference stats over `(timestamp, full sample, histogram)` rows.
+///
+/// When `validate` is set, rows that can't be decoded are skipped and returned
+/// along with their errors. Otherwise the first such row causes a panic. The
+/// difference of every other row is written to `out`, if given.
+fn analyze<I>(
+    rows: I,
+    validate: bool,
+    mut out: Option<&mut RowWriter>,
+) -> (MinMeanMax, Vec<(String, String)>)
+where
+    I: IntoIterator<Item = (String, Vec<u8>, Vec<u8>)>,
+{
+    let mut err = MinMeanMax::new();
+    let mut errors = Vec::new();
+    for (timestamp, full, histogram) in rows {
+        match row_difference(&full, &histogram) {
+            Ok(diff) => {
+                err.update(diff);
+                if let Some(out) = out.as_deref_mut() {
+                    out.write(&timestamp, diff).expect("write output row");
+                }
             }
-            let diff = x - mean;
-            sum += diff * diff;
-            count += 1;
+            Err(e) if validate => errors.push((timestamp, e)),
+            Err(e) => panic!("row {}: {}", timestamp, e),
         }
-        if count == 0 {
-            return 0.0;
-        }
-        mean + (sum / count as f64).sqrt()
     }
+    (err, errors)
 }
 
-impl Display for MinMeanMax {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mean = self.mean();
-        write!(
-            f,
-            "{:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {}, ",
-            self.min(),
-            self.lo_stdev(mean),
-            mean,
-            self.hi_stdev(mean),
-            self.max(),
-            self.samples.len()
+/// Compares every row of the input database, writing the rows to --output.
+fn run(args: &Cli) -> (MinMeanMax, Vec<(String, String)>) {
+    let mut out = args
+        .output
+        .as_deref()
+        .map(|path| RowWriter::create(path, args.format).expect("create output"));
+
+    // Open the input database
+    let connection = sqlite::open(&args.input_database).expect("open output database");
+
+    // Iterate over all samples, calculating the area difference with the histogram.
+    let mut statement = connection
+        .prepare(
+            "SELECT md.timestamp, f.data, md.data
+            FROM monitoring_data md
+            INNER JOIN full_sample f ON f.timestamp = md.timestamp;",
         )
+        .expect("prepare input query");
+    let rows = statement.iter().map(|row| {
+        let row = row.expect("read input row");
+        (
+            row.read::<&str, _>(0).to_string(),
+            row.read::<&[u8], _>(1).to_vec(),
+            row.read::<&[u8], _>(2).to_vec(),
+        )
+    });
+    let result = analyze(rows, args.validate, out.as_mut());
+    if let Some(out) = out.as_mut() {
+        out.flush().expect("flush output");
     }
-}
-
-#[derive(Parser)]
-struct Cli {
-    /// The path to the input database.
-    #[arg(value_hint = clap::ValueHint::FilePath)]
-    input_database: String,
+    result
 }
 
 fn main() {
@@ -135,28 +129,114 @@ fn main() {
     // Initialize logging
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let mut err = MinMeanMax::new();
-
-    // Open the input database
-    let connection = sqlite::open(/*&args.*/ args.input_database).expect("open output database");
+    let (err, errors) = run(&args);
+    for (timestamp, e) in errors.iter() {
+        log::warn!("row {}: {}", timestamp, e);
+    }
+    if args.validate {
+        log::info!("{} rows failed validation", errors.len());
+    }
+    eprintln!("{}", &err);
+}
 
-    // Iterate over all samples, calculating the area difference with the histogram.
-    for row in connection
-        .prepare(
-            "SELECT md.timestamp, f.data, md.data
-            FROM monitoring_data md
-            INNER JOIN full_sample f ON f.timestamp = md.timestamp;",
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mumble_prometheus::Histogram;
+    use protobuf::Message;
+
+    fn valid_row(timestamp: &str) -> (String, Vec<u8>, Vec<u8>) {
+        let full = ECDF::from(vec![0.0, 0.0005, 0.001]);
+        let mut h = Histogram::new();
+        h.set_zero_threshold(1e-3);
+        h.set_zero_count(3);
+        (
+            timestamp.to_string(),
+            rmp_serde::to_vec(&full).unwrap(),
+            h.write_to_bytes().unwrap(),
         )
-        .expect("prepare input query")
-        .iter()
-        .map(|row| row.expect("read input row"))
-    {
-        // let timestamp = row.read::<&str, _>(0);
-        let full: ECDF<f64> =
-            rmp_serde::from_slice(row.read::<&[u8], _>(1)).expect("deserialize full sample");
-        let h = parse_histogram(row.read::<&[u8], _>(2)).expect("parse histogram");
-        let other = histogram_to_ecdf(&h);
-        err.update(full.interpolate().area_difference(&other));
     }
-    println!("{}", &err);
+
+    #[test]
+    fn validate_skips_corrupt_rows() {
+        let mut corrupt = valid_row("2");
+        corrupt.1 = vec![0xc1];
+        let rows = vec![valid_row("1"), corrupt, valid_row("3")];
+        let (err, errors) = analyze(rows, true, None);
+        assert_eq!(err.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "2");
+    }
+
+    #[test]
+    fn validate_skips_classic_rows() {
+        let mut classic = Histogram::new();
+        classic.bucket.push(Default::default());
+        let mut mixed = Histogram::new();
+        mixed.set_zero_count(3);
+        mixed.bucket.push(Default::default());
+        let rows = [classic, mixed].into_iter().map(|h| {
+            let mut row = valid_row("2");
+            row.2 = h.write_to_bytes().unwrap();
+            row
+        });
+        let (err, errors) = analyze(rows, true, None);
+        assert_eq!(err.len(), 0);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn corrupt_row_panics() {
+        let mut corrupt = valid_row("1");
+        corrupt.2 = vec![0xff, 0xff, 0xff];
+        analyze(vec![corrupt], false, None);
+    }
+
+    #[test]
+    fn output_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("input.db");
+        let database = database.to_str().unwrap();
+        let connection = sqlite::open(database).unwrap();
+        connection
+            .execute(include_str!("../../../denormalized.sql"))
+            .unwrap();
+        connection
+            .execute(include_str!("../../../full.sql"))
+            .unwrap();
+        for timestamp in ["1", "2", "3"] {
+            let (timestamp, full, histogram) = valid_row(timestamp);
+            let mut statement = connection
+                .prepare("INSERT INTO monitoring_data VALUES (?, 1, ?)")
+                .unwrap();
+            statement.bind((1, timestamp.as_str())).unwrap();
+            statement.bind((2, &histogram as &[u8])).unwrap();
+            statement.next().unwrap();
+            let mut statement = connection
+                .prepare("INSERT INTO full_sample VALUES (?, ?)")
+                .unwrap();
+            statement.bind((1, timestamp.as_str())).unwrap();
+            statement.bind((2, &full as &[u8])).unwrap();
+            statement.next().unwrap();
+        }
+        drop(connection);
+
+        for (format, lines) in [("csv", 4), ("json", 3)] {
+            let output = dir.path().join(format!("output.{}", format));
+            let output = output.to_str().unwrap();
+            let args = Cli::parse_from([
+                "diff-denormalized",
+                database,
+                "--output",
+                output,
+                "--format",
+                format,
+            ]);
+            let (err, _) = run(&args);
+            assert_eq!(err.len(), 3);
+            let written = std::fs::read_to_string(output).unwrap();
+            assert_eq!(written.lines().count(), lines, "{}", written);
+        }
+    }
 }
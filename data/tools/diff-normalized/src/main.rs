@@ -17,127 +17,41 @@
 // limitations under the License.
 
 use clap::Parser;
+use csvlib::{Format, RowWriter};
 use env_logger::Env;
 use mumble::ecdf::{InterpolatedECDF, ECDF};
-
-use std::fmt::{self, Display};
-
-struct MinMeanMax {
-    samples: Vec<f64>,
-    sum: f64,
-}
-
-impl MinMeanMax {
-    fn new() -> Self {
-        Self {
-            samples: Vec::new(),
-            sum: 0.0,
-        }
-    }
-
-    fn update(&mut self, x: f64) {
-        self.samples.push(x);
-        self.sum += x;
-    }
-
-    fn min(&self) -> f64 {
-        self.samples
-            .iter()
-            .cloned()
-            .reduce(|a, b| if b < a { b } else { a })
-            .unwrap_or(0.0)
-    }
-
-    fn max(&self) -> f64 {
-        self.samples
-            .iter()
-            .cloned()
-            .reduce(|a, b| if b > a { b } else { a })
-            .unwrap_or(0.0)
-    }
-
-    fn mean(&self) -> f64 {
-        if self.samples.is_empty() {
-            return 0.0;
-        }
-        self.sum / self.samples.len() as f64
-    }
-
-    fn lo_stdev(&self, mean: f64) -> f64 {
-        if self.samples.is_empty() {
-            return 0.0;
-        }
-        let mut sum = 0.0;
-        let mut count = 0;
-        for &x in self.samples.iter() {
-            if x > mean {
-                continue;
-            }
-            let diff = mean - x;
-            sum += diff * diff;
-            count += 1;
-        }
-        if count == 0 {
-            return 0.0;
-        }
-        mean - (sum / count as f64).sqrt()
-    }
-
-    fn hi_stdev(&self, mean: f64) -> f64 {
-        if self.samples.is_empty() {
-            return 0.0;
-        }
-        let mut sum = 0.0;
-        let mut count = 0;
-        for &x in self.samples.iter() {
-            if x < mean {
-                continue;
-            }
-            let diff = x - mean;
-            sum += diff * diff;
-            count += 1;
-        }
-        if count == 0 {
-            return 0.0;
-        }
-        mean + (sum / count as f64).sqrt()
-    }
-}
-
-impl Display for MinMeanMax {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mean = self.mean();
-        write!(
-            f,
-            "{:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {}, ",
-            self.min(),
-            self.lo_stdev(mean),
-            mean,
-            self.hi_stdev(mean),
-            self.max(),
-            self.samples.len()
-        )
-    }
-}
+use statlib::MinMeanMax;
 
 #[derive(Parser)]
 struct Cli {
     /// The path to the input database.
     #[arg(value_hint = clap::ValueHint::FilePath)]
     input_database: String,
-}
 
-fn main() {
-    // Parse command-line arguments
-    let args = Cli::parse();
+    /// Where to write the area difference of every row, or "-" for standard
+    /// output. The summary is always written to standard error.
+    #[arg(short, long)]
+    output: Option<String>,
 
-    // Initialize logging
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    /// The format of the rows written to --output.
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+}
 
+/// Compares every sample with its centroid, writing the rows to --output.
+///
+/// Samples recorded as noise have no centroid to compare with, so they are
+/// counted rather than included in the rows or the summary. Returns the
+/// number of clusters and of noise samples, along with the summary.
+fn run(args: &Cli) -> (i64, i64, MinMeanMax) {
+    let mut out = args
+        .output
+        .as_deref()
+        .map(|path| RowWriter::create(path, args.format).expect("create output"));
     let mut err = MinMeanMax::new();
 
     // Open the input database
-    let connection = sqlite::open(args.input_database).expect("open database");
+    let connection = sqlite::open(&args.input_database).expect("open database");
 
     // Count the number of known clusters.
     let count = connection
@@ -147,7 +61,15 @@ fn main() {
         .map(|row| row.expect("read input row").read::<i64, _>(0))
         .next()
         .expect("read count");
-    println!("cluster count: {count}");
+
+    // Count the samples that aren't in any cluster.
+    let noise = connection
+        .prepare("SELECT COUNT(*) FROM monitoring_data WHERE cluster_id IS NULL;")
+        .expect("prepare noise query")
+        .iter()
+        .map(|row| row.expect("read input row").read::<i64, _>(0))
+        .next()
+        .expect("read noise count");
 
     // Iterate over all samples, calculating the area difference with the centroid it is mapped to.
     for row in connection
@@ -161,12 +83,100 @@ fn main() {
         .iter()
         .map(|row| row.expect("read input row"))
     {
-        // let timestamp = row.read::<&str, _>(0);
+        let timestamp = row.read::<&str, _>(0);
         let full: ECDF<f64> =
             rmp_serde::from_slice(row.read::<&[u8], _>(1)).expect("deserialize full sample");
         let centroid: InterpolatedECDF<f64> =
             rmp_serde::from_slice(row.read::<&[u8], _>(2)).expect("deserialize centroid");
-        err.update(full.interpolate().area_difference(&centroid));
+        let diff = full.interpolate().area_difference(&centroid);
+        err.update(diff);
+        if let Some(out) = out.as_mut() {
+            out.write(timestamp, diff).expect("write output row");
+        }
+    }
+    if let Some(out) = out.as_mut() {
+        out.flush().expect("flush output");
     }
-    println!("error: {}", &err);
-}
\ No newline at end of file
+    (count, noise, err)
+}
+
+fn main() {
+    // Parse command-line arguments
+    let args = Cli::parse();
+
+    // Initialize logging
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let (count, noise, err) = run(&args);
+    eprintln!("cluster count: {count}");
+    eprintln!("noise count: {noise}");
+    eprintln!("error: {}", &err);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("input.db");
+        let database = database.to_str().unwrap();
+        let connection = sqlite::open(database).unwrap();
+        connection
+            .execute(include_str!("../../../normalized.sql"))
+            .unwrap();
+        connection
+            .execute(include_str!("../../../full.sql"))
+            .unwrap();
+        let centroid = ECDF::from(vec![1.0, 2.0, 3.0]).interpolate();
+        let mut statement = connection
+            .prepare("INSERT INTO cluster (id, group_id, centroid) VALUES (0, 1, ?)")
+            .unwrap();
+        statement
+            .bind((1, &rmp_serde::to_vec(&centroid).unwrap() as &[u8]))
+            .unwrap();
+        statement.next().unwrap();
+        for timestamp in ["1", "2", "3"] {
+            connection
+                .execute(format!(
+                    "INSERT INTO monitoring_data VALUES ('{}', 1, 0, 3)",
+                    timestamp
+                ))
+                .unwrap();
+            let full = ECDF::from(vec![1.0, 2.0, 4.0]);
+            let mut statement = connection
+                .prepare("INSERT INTO full_sample VALUES (?, ?)")
+                .unwrap();
+            statement.bind((1, timestamp)).unwrap();
+            statement
+                .bind((2, &rmp_serde::to_vec(&full).unwrap() as &[u8]))
+                .unwrap();
+            statement.next().unwrap();
+        }
+        // Noise isn't compared with any centroid.
+        connection
+            .execute("INSERT INTO monitoring_data VALUES ('4', 1, NULL, 1)")
+            .unwrap();
+        drop(connection);
+
+        for (format, lines) in [("csv", 4), ("json", 3)] {
+            let output = dir.path().join(format!("output.{}", format));
+            let output = output.to_str().unwrap();
+            let args = Cli::parse_from([
+                "diff-normalized",
+                database,
+                "--output",
+                output,
+                "--format",
+                format,
+            ]);
+            let (count, noise, err) = run(&args);
+            assert_eq!(count, 1);
+            assert_eq!(noise, 1);
+            assert_eq!(err.len(), 3);
+            let written = std::fs::read_to_string(output).unwrap();
+            assert_eq!(written.lines().count(), lines, "{}", written);
+        }
+    }
+}
@@ -14,7 +14,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::fs;
+use std::io::Error;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use chrono::{Local, TimeZone};
 use clap::Parser;
@@ -24,10 +28,15 @@ use mumble::ecdf::ECDF;
 #[derive(Parser)]
 struct Cli {
     /// The path to the input data.
-    #[arg(value_hint = clap::ValueHint::FilePath)]
+    ///
+    /// This may also be a directory of files written by partition-input,
+    /// named after their UNIX timestamps. One sample is written for each.
+    #[arg(value_hint = clap::ValueHint::AnyPath)]
     input_path: String,
 
     /// The UNIX timestamp of the sample, in seconds since the epoch.
+    ///
+    /// This is ignored when reading a directory.
     #[arg(short, long, default_value_t = 0)]
     timestamp: i64,
 
@@ -36,29 +45,232 @@ struct Cli {
     output_database: String,
 }
 
-fn main() {
+fn format_timestamp(timestamp: i64) -> String {
+    let t = Local.timestamp_opt(timestamp, 0).unwrap();
+    t.format("%Y-%m-%d %H:%M:%S%:z").to_string()
+}
+
+/// Reads the values in an input file.
+///
+/// Every sample counts towards the ECDF, so rather than skip a bad one,
+/// this fails with a message naming the file and the line it's on.
+fn read_ecdf(path: &Path) -> Result<ECDF<f64>, String> {
+    let reader = csvlib::open_gzip_or_regular_file(path.to_str().expect("UTF-8 path"))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let values = csvlib::try_read_values(reader).map_err(|e| match e.position() {
+        Some(pos) => format!("{}:{}: {}", path.display(), pos.line(), e),
+        None => format!("{}: {}", path.display(), e),
+    })?;
+    Ok(values.into_iter().map(|v| v.value).collect())
+}
+
+/// Checks whether a file name looks like a partition that partition-input
+/// limited with --max-rows, such as "1700000060-3".
+fn is_numbered_partition(stem: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    stem.split_once('-')
+        .is_some_and(|(end, index)| digits(end) && digits(index))
+}
+
+/// Lists the files in `dir` whose names start with a UNIX timestamp, such
+/// as "1700000000.csv.gz", in timestamp order.
+///
+/// Partitions limited with --max-rows can share a timestamp, so they can't
+/// each be a sample. Finding one is an error, rather than skipping it.
+fn partition_files(dir: &Path) -> Result<Vec<(i64, PathBuf)>, String> {
+    let io = |e: Error| format!("{}: {}", dir.display(), e);
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(io)? {
+        let path = entry.map_err(io)?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy();
+        let stem = name.split('.').next().unwrap_or_default();
+        match stem.parse::<i64>() {
+            Ok(timestamp) => files.push((timestamp, path)),
+            Err(_) if is_numbered_partition(stem) => {
+                return Err(format!(
+                    "{}: partitions split by row count share timestamps; use --interval only",
+                    path.display()
+                ))
+            }
+            Err(_) => log::warn!("skipping {}: not named after a timestamp", path.display()),
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Writes the given (timestamp, ECDF) samples, all in one transaction.
+///
+/// If any sample couldn't be read, its error is returned and none are
+/// written, since the transaction is rolled back when the connection closes.
+fn insert<I>(connection: &sqlite::Connection, samples: I) -> Result<usize, String>
+where
+    I: IntoIterator<Item = Result<(i64, ECDF<f64>), String>>,
+{
+    let db = |e: sqlite::Error| format!("write output database: {}", e);
+    connection.execute("BEGIN TRANSACTION").map_err(db)?;
+    let mut statement = connection
+        .prepare("INSERT INTO [full_sample] (timestamp, data) VALUES (?, ?)")
+        .map_err(db)?;
+    let mut count = 0;
+    for sample in samples {
+        let (timestamp, ecdf) = sample?;
+        let rmp = rmp_serde::to_vec(&ecdf).unwrap();
+        statement.reset().map_err(db)?;
+        statement
+            .bind((1, format_timestamp(timestamp).as_str()))
+            .map_err(db)?;
+        statement.bind((2, rmp.deref())).map_err(db)?;
+        statement.next().map_err(db)?;
+        count += 1;
+    }
+    drop(statement);
+    connection.execute("COMMIT").map_err(db)?;
+    Ok(count)
+}
+
+/// Writes a sample for the input file, or one for each file in the input
+/// directory, returning how many were written.
+fn run(args: &Cli) -> Result<usize, String> {
+    let input = Path::new(&args.input_path);
+    let samples: Box<dyn Iterator<Item = Result<(i64, ECDF<f64>), String>>> = if input.is_dir() {
+        let files = partition_files(input)?;
+        Box::new(
+            files
+                .into_iter()
+                .map(|(timestamp, path)| Ok((timestamp, read_ecdf(&path)?))),
+        )
+    } else {
+        Box::new(std::iter::once(
+            read_ecdf(input).map(|ecdf| (args.timestamp, ecdf)),
+        ))
+    };
+
+    // Open the output database
+    let connection = sqlite::open(&args.output_database).expect("open output database");
+    insert(&connection, samples)
+}
+
+fn main() -> ExitCode {
     // Parse command-line arguments
     let args = Cli::parse();
     // Initialize logging
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let t = Local.timestamp_opt(args.timestamp, 0).unwrap();
-    let tstr = t.format("%Y-%m-%d %H:%M:%S%:z").to_string();
+    match run(&args) {
+        Ok(count) => {
+            log::info!("Wrote {} samples", count);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let reader = csvlib::open_gzip_or_regular_file(&args.input_path).expect("open input file");
-    let values = csvlib::read_values(reader)
-        .into_iter()
-        .map(|v| v.value)
-        .collect::<Vec<f64>>();
-    let ecdf = ECDF::from(values);
-    let rmp = rmp_serde::to_vec(&ecdf).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Open the input database
-    let connection = sqlite::open(/*&args.*/ args.output_database).expect("open output database");
-    let mut statement = connection
-        .prepare("INSERT INTO [full_sample] (timestamp, data) VALUES (?, ?)")
+    #[test]
+    fn directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for (name, values) in [("100.csv", "1.0\n2.0"), ("101.csv", "3.0")] {
+            let rows: String = values.lines().map(|v| format!("100,0,{}\n", v)).collect();
+            fs::write(
+                dir.join(name),
+                format!("timestamp_secs,timestamp_nanos,value\n{}", rows),
+            )
+            .unwrap();
+        }
+        fs::write(dir.join("README"), "not a partition").unwrap();
+        let database = dir.join("full.db");
+        let database = database.to_str().unwrap();
+        sqlite::open(database)
+            .unwrap()
+            .execute(include_str!("../../../full.sql"))
+            .unwrap();
+
+        let args = Cli::parse_from(["full-sample", dir.to_str().unwrap(), database]);
+        assert_eq!(run(&args), Ok(2));
+        let rows: Vec<(String, usize)> = sqlite::open(database)
+            .unwrap()
+            .prepare("SELECT timestamp, data FROM full_sample ORDER BY timestamp")
+            .unwrap()
+            .iter()
+            .map(|row| {
+                let row = row.unwrap();
+                let ecdf: ECDF<f64> = rmp_serde::from_slice(row.read::<&[u8], _>(1)).unwrap();
+                (row.read::<&str, _>(0).to_string(), ecdf.len())
+            })
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], (format_timestamp(100), 2));
+        assert_eq!(rows[1], (format_timestamp(101), 1));
+    }
+
+    #[test]
+    fn bad_row() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(
+            dir.join("100.csv"),
+            "timestamp_secs,timestamp_nanos,value\n100,0,1.0\n",
+        )
         .unwrap();
-    statement.bind((1, tstr.as_str())).expect("bind timestamp");
-    statement.bind((2, rmp.deref())).expect("bind data");
-    statement.next().expect("insert");
+        fs::write(
+            dir.join("101.csv"),
+            "timestamp_secs,timestamp_nanos,value\n101,0,2.0\n101,0,oops\n",
+        )
+        .unwrap();
+        let database = dir.join("full.db");
+        let database = database.to_str().unwrap();
+        sqlite::open(database)
+            .unwrap()
+            .execute(include_str!("../../../full.sql"))
+            .unwrap();
+
+        let args = Cli::parse_from(["full-sample", dir.to_str().unwrap(), database]);
+        let err = run(&args).unwrap_err();
+        let count = sqlite::open(database)
+            .unwrap()
+            .prepare("SELECT COUNT(*) FROM full_sample")
+            .unwrap()
+            .iter()
+            .map(|row| row.unwrap().read::<i64, _>(0))
+            .next();
+        assert!(
+            err.starts_with(&format!("{}:3: ", dir.join("101.csv").display())),
+            "{}",
+            err
+        );
+        // The good file isn't written on its own.
+        assert_eq!(count, Some(0));
+    }
+
+    #[test]
+    fn numbered_partitions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for name in ["100-0.csv", "100-1.csv"] {
+            fs::write(
+                dir.join(name),
+                "timestamp_secs,timestamp_nanos,value\n99,0,1.0\n",
+            )
+            .unwrap();
+        }
+        let database = dir.join("full.db");
+        let database = database.to_str().unwrap();
+        let args = Cli::parse_from(["full-sample", dir.to_str().unwrap(), database]);
+        let err = run(&args).unwrap_err();
+        assert!(err.contains("share timestamps"), "{}", err);
+        assert!(is_numbered_partition("100-0"));
+        assert!(!is_numbered_partition("100"));
+        assert!(!is_numbered_partition("-100"));
+    }
 }
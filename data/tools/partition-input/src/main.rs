@@ -1,4 +1,4 @@
-// Breaks input file up by time period.
+// Breaks input file up by time period or number of rows.
 // Copyright (C) 2022, Tony Rippy
 //
 // Licensed under the Apache License, Version 2.0 (the "License");
@@ -28,9 +28,16 @@ struct Cli {
     #[arg(value_hint = clap::ValueHint::FilePath)]
     input_path: String,
 
-    /// Amount of time covered by each partition, in seconds.
-    #[arg(short, long, default_value_t = 1)]
-    interval: u64,
+    /// Amount of time covered by each partition, in seconds. Defaults to 1,
+    /// unless --max-rows is given.
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    interval: Option<u64>,
+
+    /// Maximum number of rows in each partition. Several partitions may end
+    /// at the same time when this is set, so a running index is added to
+    /// their names, e.g. "1700000060-3".
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_rows: Option<u64>,
 
     /// Path to where the partitioned files should be written.
     #[arg(short, long, default_value = ".", value_hint = clap::ValueHint::FilePath) ]
@@ -38,42 +45,162 @@ struct Cli {
 }
 
 impl Cli {
-    fn create_file(&self, timestamp: u64) -> Result<File, Error> {
-        let path = format!("{}/{}.csv", self.output_path, timestamp);
+    fn create_file(&self, name: &str) -> Result<File, Error> {
+        let path = format!("{}/{}.csv", self.output_path, name);
         File::create(path)
     }
-}
 
-fn main() {
-    // Parse command-line arguments
-    let args = Cli::parse();
-    // Initialize logging
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    /// The time covered by each partition, if partitions are split by time.
+    fn interval(&self) -> Option<u64> {
+        match (self.interval, self.max_rows) {
+            (None, None) => Some(1),
+            (interval, _) => interval,
+        }
+    }
+}
 
+/// Splits sorted `values` into partitions, calling `write` with the name and
+/// contents of each one.
+///
+/// A partition ends when the next value falls outside its `interval`, or
+/// once it holds `max_rows` values, whichever comes first. Partitions are
+/// named after the end of their interval, or without one, the end of the
+/// second their last value is in. If `max_rows` is set, several may share
+/// an end, so they are also numbered, e.g. "60-3".
+fn partition<I, F>(values: I, interval: Option<u64>, max_rows: Option<u64>, mut write: F)
+where
+    I: IntoIterator<Item = Value>,
+    F: FnMut(&str, &[Value]),
+{
     let mut start = 0u64;
-    let mut end = start + args.interval;
+    let mut end = interval.unwrap_or(u64::MAX);
     let mut partition: Vec<Value> = Vec::new();
+    let mut index = 0;
+    let mut flush = |partition: &mut Vec<Value>, end: u64| {
+        if partition.is_empty() {
+            return;
+        }
+        let end = match interval {
+            Some(_) => end,
+            None => partition[partition.len() - 1].timestamp_secs as u64 + 1,
+        };
+        let name = match max_rows {
+            Some(_) => {
+                index += 1;
+                format!("{}-{}", end, index - 1)
+            }
+            None => end.to_string(),
+        };
+        write(&name, partition);
+        partition.clear();
+    };
 
-    let reader = csvlib::open_gzip_or_regular_file(&args.input_path).expect("open input file");
-    for v in csvlib::read_values(reader) {
+    for v in values {
         let t = v.timestamp_secs as u64;
         if t < start {
             warn!("input is not sorted; {} comes before {}", t, start);
             continue;
         }
-        if t >= end {
-            if !partition.is_empty() {
-                let f = args.create_file(end).expect("create output file");
-                csvlib::write_values(f, &partition).expect("write values");
-                partition.clear();
+        if let Some(interval) = interval {
+            if t >= end {
+                flush(&mut partition, end);
+                start = t - (t % interval);
+                end = start + interval;
             }
-            start = t - (t % args.interval);
-            end = start + args.interval;
         }
         partition.push(v);
+        if max_rows.is_some_and(|n| partition.len() as u64 >= n) {
+            flush(&mut partition, end);
+        }
+    }
+    flush(&mut partition, end);
+}
+
+fn main() {
+    // Parse command-line arguments
+    let args = Cli::parse();
+    // Initialize logging
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let reader = csvlib::open_gzip_or_regular_file(&args.input_path).expect("open input file");
+    // Stream the input, so only one partition is held in memory at a time.
+    let values = csvlib::stream_values(reader).filter_map(|v| match v {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("{:?}", e);
+            None
+        }
+    });
+    partition(values, args.interval(), args.max_rows, |name, values| {
+        let f = args.create_file(name).expect("create output file");
+        csvlib::write_values(f, values).expect("write values");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Partitions ten values, one per second, returning the name and
+    /// timestamps of each partition.
+    fn boundaries(interval: Option<u64>, max_rows: Option<u64>) -> Vec<(String, Vec<i64>)> {
+        let mut csv = String::from("timestamp_secs,timestamp_nanos,value\n");
+        for t in 0..10 {
+            csv.push_str(&format!("{},0,1.5\n", t));
+        }
+        let values = csvlib::stream_values(csv.as_bytes()).map(Result::unwrap);
+        let mut out = Vec::new();
+        partition(values, interval, max_rows, |name, values| {
+            let timestamps = values.iter().map(|v| v.timestamp_secs).collect();
+            out.push((name.to_string(), timestamps));
+        });
+        out
     }
-    if !partition.is_empty() {
-        let f = args.create_file(end).expect("create output file");
-        csvlib::write_values(f, &partition).expect("write values");
+
+    #[test]
+    fn by_time() {
+        assert_eq!(
+            boundaries(Some(5), None),
+            [
+                ("5".to_string(), vec![0, 1, 2, 3, 4]),
+                ("10".to_string(), vec![5, 6, 7, 8, 9]),
+            ]
+        );
+    }
+
+    #[test]
+    fn by_rows() {
+        assert_eq!(
+            boundaries(None, Some(4)),
+            [
+                ("4-0".to_string(), vec![0, 1, 2, 3]),
+                ("8-1".to_string(), vec![4, 5, 6, 7]),
+                ("10-2".to_string(), vec![8, 9]),
+            ]
+        );
+    }
+
+    #[test]
+    fn by_time_and_rows() {
+        // Whichever limit is hit first ends the partition.
+        assert_eq!(
+            boundaries(Some(5), Some(3)),
+            [
+                ("5-0".to_string(), vec![0, 1, 2]),
+                ("5-1".to_string(), vec![3, 4]),
+                ("10-2".to_string(), vec![5, 6, 7]),
+                ("10-3".to_string(), vec![8, 9]),
+            ]
+        );
+    }
+
+    #[test]
+    fn default_interval() {
+        let args = Cli::parse_from(["partition-input", "in.csv"]);
+        assert_eq!(args.interval(), Some(1));
+        let args = Cli::parse_from(["partition-input", "in.csv", "--max-rows", "10"]);
+        assert_eq!(args.interval(), None);
+        let args = Cli::parse_from(["partition-input", "in.csv", "-i", "60", "--max-rows", "10"]);
+        assert_eq!(args.interval(), Some(60));
     }
 }
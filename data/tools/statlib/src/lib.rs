@@ -0,0 +1,198 @@
+// Summary statistics shared by the analysis tools.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display};
+
+/// Collects values in order to summarize their distribution.
+///
+/// Besides the extremes and the mean, the spread is described separately on
+/// each side of the mean, since the distributions being summarized are
+/// often skewed.
+#[derive(Default)]
+pub struct MinMeanMax {
+    samples: Vec<f64>,
+    sum: f64,
+}
+
+impl MinMeanMax {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of values added.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn update(&mut self, x: f64) {
+        self.samples.push(x);
+        self.sum += x;
+    }
+
+    pub fn min(&self) -> f64 {
+        self.samples
+            .iter()
+            .cloned()
+            .reduce(|a, b| if b < a { b } else { a })
+            .unwrap_or(0.0)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.samples
+            .iter()
+            .cloned()
+            .reduce(|a, b| if b > a { b } else { a })
+            .unwrap_or(0.0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.sum / self.samples.len() as f64
+    }
+
+    /// The mean minus the root mean square deviation of the values no
+    /// larger than `mean`.
+    ///
+    /// Values above the mean are ignored, so this describes the spread of
+    /// the lower half of the distribution without being widened by a long
+    /// upper tail.
+    pub fn lo_stdev(&self, mean: f64) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let mut sum = 0.0;
+        let mut count = 0;
+        for &x in self.samples.iter() {
+            if x > mean {
+                continue;
+            }
+            let diff = mean - x;
+            sum += diff * diff;
+            count += 1;
+        }
+        if count == 0 {
+            return 0.0;
+        }
+        mean - (sum / count as f64).sqrt()
+    }
+
+    /// The mean plus the root mean square deviation of the values no
+    /// smaller than `mean`.
+    ///
+    /// Like [MinMeanMax::lo_stdev], but for the upper half.
+    pub fn hi_stdev(&self, mean: f64) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let mut sum = 0.0;
+        let mut count = 0;
+        for &x in self.samples.iter() {
+            if x < mean {
+                continue;
+            }
+            let diff = x - mean;
+            sum += diff * diff;
+            count += 1;
+        }
+        if count == 0 {
+            return 0.0;
+        }
+        mean + (sum / count as f64).sqrt()
+    }
+}
+
+/// Formats the minimum, low deviation, mean, high deviation, maximum and
+/// number of values, as a row of comma separated values.
+impl Display for MinMeanMax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mean = self.mean();
+        write!(
+            f,
+            "{:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {}, ",
+            self.min(),
+            self.lo_stdev(mean),
+            mean,
+            self.hi_stdev(mean),
+            self.max(),
+            self.samples.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn of(values: &[f64]) -> MinMeanMax {
+        let mut m = MinMeanMax::new();
+        for &x in values {
+            m.update(x);
+        }
+        m
+    }
+
+    #[test]
+    fn empty() {
+        let m = MinMeanMax::new();
+        assert!(m.is_empty());
+        assert_eq!(m.min(), 0.0);
+        assert_eq!(m.max(), 0.0);
+        assert_eq!(m.mean(), 0.0);
+        assert_eq!(m.lo_stdev(0.0), 0.0);
+        assert_eq!(m.hi_stdev(0.0), 0.0);
+    }
+
+    #[test]
+    fn asymmetric_stdev() {
+        let m = of(&[1.0, 2.0, 3.0, 10.0]);
+        assert_eq!(m.len(), 4);
+        assert_eq!(m.min(), 1.0);
+        assert_eq!(m.max(), 10.0);
+        let mean = m.mean();
+        assert_eq!(mean, 4.0);
+        // Only 1, 2 and 3 are at or below the mean: sqrt((9 + 4 + 1) / 3).
+        assert!((m.lo_stdev(mean) - (4.0 - (14.0f64 / 3.0).sqrt())).abs() < 1e-12);
+        // Only 10 is at or above it.
+        assert_eq!(m.hi_stdev(mean), 10.0);
+    }
+
+    #[test]
+    fn values_at_mean() {
+        // Values equal to the mean count on both sides.
+        let m = of(&[2.0, 2.0, 2.0]);
+        assert_eq!(m.lo_stdev(m.mean()), 2.0);
+        assert_eq!(m.hi_stdev(m.mean()), 2.0);
+
+        let m = of(&[0.0, 2.0, 4.0]);
+        assert_eq!(m.lo_stdev(2.0), 2.0 - 2.0f64.sqrt());
+        assert_eq!(m.hi_stdev(2.0), 2.0 + 2.0f64.sqrt());
+    }
+
+    #[test]
+    fn display() {
+        let m = of(&[1.0, 2.0, 3.0, 10.0]);
+        assert_eq!(
+            m.to_string(),
+            "1.0000, 1.8398, 4.0000, 10.0000, 10.0000, 4, "
+        );
+    }
+}
@@ -11,12 +11,144 @@ pub struct DataStore {
 }
 
 impl DataStore {
-    pub fn open(database: &str, eps: f64) -> sqlite::Result<DataStore> {
-        Ok(DataStore {
-            cluster_group: ClusterGroup::new(eps),
-            cluster_max: 0,
-            connection: sqlite::open(database)?,
-        })
+    pub fn open(
+        database: &str,
+        eps: f64,
+        min_pts: usize,
+        min_cluster_size: usize,
+        max_centroid_size: Option<usize>,
+    ) -> sqlite::Result<DataStore> {
+        let connection = sqlite::open(database)?;
+        Self::migrate(&connection)?;
+        // Pick up where the last run left off, so cluster ids stay stable.
+        let cluster_group = ClusterGroup::load(
+            &connection,
+            eps,
+            min_pts,
+            min_cluster_size,
+            max_centroid_size,
+        )?;
+        let ds = DataStore {
+            cluster_max: cluster_group.centroids.len(),
+            cluster_group,
+            connection,
+        };
+        ds.write_config()?;
+        Ok(ds)
+    }
+
+    /// Upgrades tables written by older versions of the collector.
+    fn migrate(connection: &sqlite::Connection) -> sqlite::Result<()> {
+        Self::migrate_cluster_group(connection)?;
+        Self::migrate_monitoring_data(connection)
+    }
+
+    /// Lists the columns of a table by name, along with whether they are
+    /// declared NOT NULL. The list is empty if the table doesn't exist.
+    fn columns(
+        connection: &sqlite::Connection,
+        table: &str,
+    ) -> sqlite::Result<Vec<(String, bool)>> {
+        let mut columns = Vec::new();
+        for row in connection
+            .prepare(format!("PRAGMA table_info({})", table))?
+            .iter()
+        {
+            let row = row?;
+            columns.push((
+                row.read::<&str, _>(1).to_string(),
+                row.read::<i64, _>(3) != 0,
+            ));
+        }
+        Ok(columns)
+    }
+
+    /// Runs `statements` in a single transaction.
+    ///
+    /// If any of them fail, the transaction is rolled back, so the database
+    /// is left as it was and the connection can still be used.
+    fn transaction(connection: &sqlite::Connection, statements: &str) -> sqlite::Result<()> {
+        connection.execute("BEGIN")?;
+        let result = connection
+            .execute(statements)
+            .and_then(|()| connection.execute("COMMIT"));
+        if result.is_err() {
+            // Report the original failure rather than any from the rollback.
+            let _ = connection.execute("ROLLBACK");
+        }
+        result
+    }
+
+    /// Upgrades a cluster_group table that stores its settings in a single
+    /// `config` column, from before they were stored in typed columns.
+    ///
+    /// The old settings don't include everything that is checked now, so
+    /// they are dropped, and the given settings are saved in their place.
+    fn migrate_cluster_group(connection: &sqlite::Connection) -> sqlite::Result<()> {
+        let columns = Self::columns(connection, "cluster_group")?;
+        if !columns.iter().any(|(c, _)| c == "config") {
+            return Ok(());
+        }
+        warn!("Upgrading cluster_group table; saved settings are not checked");
+        // Other tables refer to cluster_group, so it is replaced rather than renamed.
+        Self::transaction(
+            connection,
+            "CREATE TABLE cluster_group_new (
+               id INTEGER PRIMARY KEY AUTOINCREMENT,
+               eps REAL NOT NULL,
+               min_pts INTEGER NOT NULL,
+               min_cluster_size INTEGER NOT NULL
+            );
+            DROP TABLE cluster_group;
+            ALTER TABLE cluster_group_new RENAME TO cluster_group;",
+        )
+    }
+
+    /// Upgrades a monitoring_data table whose cluster_id can't be NULL, from
+    /// when noise was recorded as cluster -1.
+    ///
+    /// There is no cluster -1, so those rows broke the foreign key on
+    /// cluster_id. They are changed to NULL, which is how noise is recorded now.
+    fn migrate_monitoring_data(connection: &sqlite::Connection) -> sqlite::Result<()> {
+        let columns = Self::columns(connection, "monitoring_data")?;
+        if !columns
+            .iter()
+            .any(|(c, not_null)| c == "cluster_id" && *not_null)
+        {
+            return Ok(());
+        }
+        warn!("Upgrading monitoring_data table; noise is now recorded as NULL");
+        // SQLite can't drop a NOT NULL constraint, so the table is copied.
+        Self::transaction(
+            connection,
+            "CREATE TABLE monitoring_data_new (
+               timestamp DATETIME NOT NULL,
+               label_set_id INTEGER NOT NULL,
+               cluster_id INTEGER,
+               count INTEGER NOT NULL,
+
+               PRIMARY KEY (timestamp, label_set_id),
+
+               FOREIGN KEY (label_set_id) REFERENCES [label_set] (id),
+               FOREIGN KEY (cluster_id) REFERENCES [cluster] (id)
+            );
+            INSERT INTO monitoring_data_new
+               SELECT timestamp, label_set_id, NULLIF(cluster_id, -1), count
+               FROM monitoring_data;
+            DROP TABLE monitoring_data;
+            ALTER TABLE monitoring_data_new RENAME TO monitoring_data;",
+        )
+    }
+
+    fn write_config(&self) -> sqlite::Result<()> {
+        let mut statement = self.connection.prepare(
+            "INSERT OR REPLACE INTO cluster_group (id, eps, min_pts, min_cluster_size) VALUES (1, ?, ?, ?)",
+        )?;
+        statement.bind((1, self.cluster_group.eps))?;
+        statement.bind((2, self.cluster_group.min_pts as i64))?;
+        statement.bind((3, self.cluster_group.min_cluster_size as i64))?;
+        statement.next()?;
+        Ok(())
     }
 
     fn write_cluster(&self, id: usize, centroid: &InterpolatedECDF<f64>) -> sqlite::Result<()> {
@@ -30,7 +162,12 @@ impl DataStore {
         Ok(())
     }
 
-    fn write_sample(&self, id: Id, cluster_id: usize, count: usize) -> sqlite::Result<()> {
+    /// Records the cluster a sample belongs to.
+    ///
+    /// Noise, meaning samples in sparse regions that DBSCAN doesn't assign to
+    /// a cluster and members of clusters that are too small, is recorded with
+    /// a NULL cluster_id.
+    fn write_sample(&self, id: Id, cluster_id: Option<usize>, count: usize) -> sqlite::Result<()> {
         let mut statement = self.connection.prepare(
             "INSERT INTO monitoring_data (timestamp, label_set_id, cluster_id, count) VALUES (?, ?, ?, ?)",
         ).expect("prepare insert");
@@ -40,7 +177,7 @@ impl DataStore {
         // TODO: Copy label_sets into the output database
         statement.bind((2, 1)).expect("bind label_set_id");
         statement
-            .bind((3, cluster_id as i64))
+            .bind((3, cluster_id.map(|id| id as i64)))
             .expect("bind cluster_id");
         statement.bind((4, count as i64)).expect("bind count");
         statement.next().expect("execute insert");
@@ -91,17 +228,93 @@ impl Assignment {
     }
 }
 
+/// Describes a problem with the saved cluster group, which SQLite itself
+/// doesn't know about.
+fn load_error(message: String) -> sqlite::Error {
+    sqlite::Error {
+        code: None,
+        message: Some(message),
+    }
+}
+
 struct ClusterGroup {
     centroids: Vec<InterpolatedECDF<f64>>,
     eps: f64,
+    /// The number of samples within `eps`, including itself, that make a
+    /// sample a core point of a cluster.
+    min_pts: usize,
+    /// The members of new clusters smaller than this are recorded as noise.
+    min_cluster_size: usize,
+    /// New centroids with more points than this are compacted.
+    max_centroid_size: Option<usize>,
 }
 
 impl ClusterGroup {
-    pub fn new(eps: f64) -> ClusterGroup {
+    pub fn new(
+        eps: f64,
+        min_pts: usize,
+        min_cluster_size: usize,
+        max_centroid_size: Option<usize>,
+    ) -> ClusterGroup {
         ClusterGroup {
             eps,
+            min_pts,
+            min_cluster_size,
             centroids: Vec::new(),
+            max_centroid_size,
+        }
+    }
+
+    /// Reads back the cluster group previously saved to `connection`.
+    ///
+    /// The existing clusters were formed using the saved settings, so it is
+    /// an error if they differ from the given ones. If nothing was saved,
+    /// this is the same as [ClusterGroup::new].
+    pub fn load(
+        connection: &sqlite::Connection,
+        eps: f64,
+        min_pts: usize,
+        min_cluster_size: usize,
+        max_centroid_size: Option<usize>,
+    ) -> sqlite::Result<ClusterGroup> {
+        let mut group = ClusterGroup::new(eps, min_pts, min_cluster_size, max_centroid_size);
+        let mut statement = connection
+            .prepare("SELECT eps, min_pts, min_cluster_size FROM cluster_group WHERE id = 1")?;
+        if let Some(row) = statement.iter().next() {
+            let row = row?;
+            let saved = (
+                row.read::<f64, _>(0),
+                row.read::<i64, _>(1) as usize,
+                row.read::<i64, _>(2) as usize,
+            );
+            if saved != (eps, min_pts, min_cluster_size) {
+                return Err(load_error(format!(
+                    "cluster group was saved with eps={} min_pts={} min_cluster_size={}, not eps={} min_pts={} min_cluster_size={}",
+                    saved.0, saved.1, saved.2, eps, min_pts, min_cluster_size
+                )));
+            }
+        }
+
+        let mut statement = connection
+            .prepare("SELECT id, centroid FROM cluster WHERE group_id = 1 ORDER BY id")?;
+        for row in statement.iter() {
+            let row = row?;
+            // Cluster ids are indexes into the centroids.
+            let id = row.read::<i64, _>(0);
+            if id != group.centroids.len() as i64 {
+                return Err(load_error(format!(
+                    "expected cluster {}, found cluster {}",
+                    group.centroids.len(),
+                    id
+                )));
+            }
+            let centroid = rmp_serde::from_slice(row.read::<&[u8], _>(1)).map_err(|e| {
+                load_error(format!("deserialize centroid of cluster {}: {}", id, e))
+            })?;
+            group.centroids.push(centroid);
         }
+        info!("Loaded {} clusters", group.centroids.len());
+        Ok(group)
     }
 
     fn find_neighbors<'a>(
@@ -123,85 +336,142 @@ impl ClusterGroup {
             .map(|(idx, _)| idx)
     }
 
+    /// Finds the samples with enough neighbors to be core points.
+    fn find_core(population: &[InterpolatedECDF<f64>], eps: f64, min_pts: usize) -> Vec<bool> {
+        population
+            .iter()
+            .map(|sample| {
+                population
+                    .iter()
+                    .filter(|pt| sample.area_difference(pt) < eps)
+                    .count()
+                    >= min_pts
+            })
+            .collect()
+    }
+
+    /// Adds every sample that is density-reachable from those in `queue`
+    /// to `cluster`. The samples in `queue` must already be assigned to it.
     fn expand_cluster(
         queue: &mut Vec<usize>,
         population: &[InterpolatedECDF<f64>],
+        core: &[bool],
         assignments: &mut [Assignment],
         eps: f64,
         cluster: usize,
-    ) -> bool {
-        if queue.is_empty() {
-            return false;
-        }
+    ) {
         while let Some(idx) = queue.pop() {
-            assignments[idx] = Assignment::Assigned(cluster);
-            let neighbors = Self::find_neighbors(&population[idx], population, assignments, eps);
-            queue.extend(neighbors);
+            // Border points join the cluster, but don't extend it.
+            if !core[idx] {
+                continue;
+            }
+            let start = queue.len();
+            queue.extend(Self::find_neighbors(
+                &population[idx],
+                population,
+                assignments,
+                eps,
+            ));
+            // Assign neighbors as they're found, so they aren't queued twice.
+            for &neighbor in queue[start..].iter() {
+                assignments[neighbor] = Assignment::Assigned(cluster);
+            }
         }
-        true
     }
 
     /// Run a dumb version of DBSCAN on a set of samples.
     fn run(&mut self, samples: &[InterpolatedECDF<f64>]) -> Vec<Assignment> {
         let mut assignments = vec![Assignment::Unassigned; samples.len()];
-        let mut neighbors = Vec::new();
+        let core = Self::find_core(samples, self.eps, self.min_pts);
+        let mut queue = Vec::new();
         let mut cluster = 0;
 
         for centroid in self.centroids.iter() {
             // Seed the run with known clusters
-            neighbors.clear();
-            neighbors.extend(Self::find_neighbors(
+            queue.extend(Self::find_neighbors(
                 centroid,
                 samples,
                 &assignments,
                 self.eps,
             ));
-            for idx in neighbors.iter() {
-                assignments[*idx] = Assignment::Assigned(cluster);
+            for &idx in queue.iter() {
+                assignments[idx] = Assignment::Assigned(cluster);
             }
+            Self::expand_cluster(
+                &mut queue,
+                samples,
+                &core,
+                &mut assignments,
+                self.eps,
+                cluster,
+            );
             cluster += 1;
         }
         for idx in 0..samples.len() {
-            // Scan all remaining samples and ensure they are assigned to new clusters
-            if assignments[idx].is_assigned() {
+            // Start new clusters from the remaining core samples. Whatever
+            // isn't reachable from one is left unassigned, as noise.
+            if assignments[idx].is_assigned() || !core[idx] {
                 continue;
             }
-            neighbors.clear();
-            neighbors.extend(Self::find_neighbors(
-                &samples[idx],
+            assignments[idx] = Assignment::Assigned(cluster);
+            queue.push(idx);
+            Self::expand_cluster(
+                &mut queue,
                 samples,
-                &assignments,
+                &core,
+                &mut assignments,
                 self.eps,
-            ));
-            for idx in neighbors.iter() {
-                assignments[*idx] = Assignment::Assigned(cluster);
-            }
+                cluster,
+            );
             cluster += 1;
         }
         assignments
     }
 
+    /// Combines the members of a cluster into its centroid.
+    fn centroid(
+        &self,
+        ecdfs: &[InterpolatedECDF<f64>],
+        cluster: &[usize],
+    ) -> InterpolatedECDF<f64> {
+        let members: Vec<&InterpolatedECDF<f64>> = cluster.iter().map(|&i| &ecdfs[i]).collect();
+        let mut centroid = InterpolatedECDF::centroid(&members);
+        if let Some(max_size) = self.max_centroid_size {
+            // Every member's points end up in the centroid, so large clusters
+            // would otherwise have very large centroids.
+            centroid.compact(max_size);
+        }
+        centroid
+    }
+
     fn report_clusters(
         &mut self,
         ecdfs: &Vec<InterpolatedECDF<f64>>,
         existing_clusters: Vec<(usize, Vec<usize>)>,
         new_clusters: Vec<Vec<usize>>,
-    ) -> Vec<usize> {
-        let mut cluster_mapping = vec![0usize; ecdfs.len()];
+    ) -> Vec<Option<usize>> {
+        // Samples that aren't in any cluster are left as noise.
+        let mut cluster_mapping = vec![None; ecdfs.len()];
+
+        // Drop clusters that are too small, leaving their members as noise.
+        let (new_clusters, noise): (Vec<_>, Vec<_>) = new_clusters
+            .into_iter()
+            .partition(|c| c.len() >= self.min_cluster_size);
+        let noise: usize = noise.iter().map(Vec::len).sum();
+        if noise > 0 {
+            debug!("Noise from small clusters: size +{}", noise);
+        }
 
         for (cluster_id, cluster) in existing_clusters.into_iter() {
             debug!("Existing cluster {}: size +{}", cluster_id, cluster.len());
             for &j in cluster.iter() {
-                cluster_mapping[j] = cluster_id;
+                cluster_mapping[j] = Some(cluster_id);
             }
         }
 
         let offset = self.centroids.len();
         for new_cluster in new_clusters.iter() {
-            let centroid = new_cluster
-                .iter()
-                .map(|&i| &ecdfs[i])
-                .fold(InterpolatedECDF::default(), |acc, x| acc.merge(x));
+            let centroid = self.centroid(ecdfs, new_cluster);
             // let eps = if new_cluster.len() > 1 {
             //     new_cluster
             //         .iter()
@@ -217,32 +487,33 @@ impl ClusterGroup {
             let cluster_id = i + offset;
             debug!("New cluster {}: size {}", cluster_id, cluster.len());
             for &j in cluster.iter() {
-                cluster_mapping[j] = cluster_id;
+                cluster_mapping[j] = Some(cluster_id);
             }
         }
         cluster_mapping
     }
 
-    pub fn process_batch(&mut self, ecdfs: &Vec<InterpolatedECDF<f64>>) -> Vec<usize> {
+    /// Clusters a batch of samples, returning the cluster of each one, or
+    /// None for noise.
+    pub fn process_batch(&mut self, ecdfs: &Vec<InterpolatedECDF<f64>>) -> Vec<Option<usize>> {
         info!("Processing batch of {} samples... ", ecdfs.len());
-        self.run(ecdfs);
         let mut cluster_map = self
             .run(ecdfs)
             .into_iter()
             .enumerate()
-            .map(|(id, c)| match c {
-                Assignment::Assigned(cluster) => (cluster, id),
-                other => {
-                    panic!("Unexpected classification: {:?}", other);
-                }
+            .filter_map(|(id, c)| match c {
+                Assignment::Assigned(cluster) => Some((cluster, id)),
+                Assignment::Unassigned => None,
             })
             .collect::<Vec<(usize, usize)>>();
         cluster_map.sort_unstable();
 
         let mut existing_clusters = Vec::new();
         let mut new_clusters = Vec::new();
+        let Some(&(mut last_cluster, _)) = cluster_map.first() else {
+            return self.report_clusters(ecdfs, existing_clusters, new_clusters);
+        };
         let mut cluster_ids = Vec::new();
-        let mut last_cluster = cluster_map[0].0;
         for (cluster, id) in cluster_map {
             if cluster != last_cluster {
                 if last_cluster < self.centroids.len() {
@@ -263,3 +534,287 @@ impl ClusterGroup {
         self.report_clusters(ecdfs, existing_clusters, new_clusters)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mumble::ecdf::ECDF;
+    use tempfile::TempDir;
+
+    fn sample(center: f64) -> InterpolatedECDF<f64> {
+        ECDF::from(vec![center - 0.5, center, center + 0.5]).interpolate()
+    }
+
+    #[test]
+    fn min_cluster_size() {
+        let mut group = ClusterGroup::new(1.0, 1, 2, None);
+        // A pair of similar samples, and three singletons far from everything else.
+        let ecdfs = vec![
+            sample(0.0),
+            sample(100.0),
+            sample(0.1),
+            sample(200.0),
+            sample(300.0),
+        ];
+        let mapping = group.process_batch(&ecdfs);
+        let pair = mapping[0].unwrap();
+        assert_eq!(mapping[2], Some(pair));
+        assert_eq!(mapping[1], None);
+        assert_eq!(mapping[3], None);
+        assert_eq!(mapping[4], None);
+        assert_eq!(group.centroids.len(), 1);
+
+        // Later singletons are noise too.
+        let mapping = group.process_batch(&vec![sample(400.0), sample(0.05)]);
+        assert_eq!(mapping, vec![None, Some(pair)]);
+        assert_eq!(group.centroids.len(), 1);
+    }
+
+    #[test]
+    fn transitive_clusters() {
+        let mut group = ClusterGroup::new(1.0, 1, 1, None);
+        // Neighbors are just under eps apart, but the ends of the chain are not.
+        let ecdfs = vec![
+            sample(2.7),
+            sample(0.0),
+            sample(3.6),
+            sample(1.8),
+            sample(0.9),
+        ];
+        let mapping = group.process_batch(&ecdfs);
+        assert!(mapping.iter().all(|&c| c.is_some() && c == mapping[0]));
+        assert_eq!(group.centroids.len(), 1);
+    }
+
+    #[test]
+    fn min_pts() {
+        let mut group = ClusterGroup::new(1.0, 3, 1, None);
+        // A dense core, a border sample just within reach of it, and an
+        // isolated outlier.
+        let ecdfs = vec![
+            sample(0.0),
+            sample(50.0),
+            sample(0.2),
+            sample(0.4),
+            sample(1.3),
+        ];
+        let mapping = group.process_batch(&ecdfs);
+        let core = mapping[0].unwrap();
+        assert_eq!(mapping[2], Some(core));
+        assert_eq!(mapping[3], Some(core));
+        assert_eq!(mapping[4], Some(core));
+        assert_eq!(mapping[1], None);
+        assert_eq!(group.centroids.len(), 1);
+    }
+
+    #[test]
+    fn max_centroid_size() {
+        let ecdfs: Vec<_> = (0..10).map(|i| sample(i as f64 * 0.1)).collect();
+        let mut group = ClusterGroup::new(1.0, 1, 1, None);
+        group.process_batch(&ecdfs);
+        let full = group.centroids[0].clone();
+        assert!(full.point_iter().count() > 8);
+
+        let mut group = ClusterGroup::new(1.0, 1, 1, Some(8));
+        let mapping = group.process_batch(&ecdfs);
+        assert!(mapping.iter().all(|&c| c == Some(0)));
+        let compacted = &group.centroids[0];
+        assert_eq!(compacted.point_iter().count(), 8);
+        assert!(full.area_difference(compacted) < 0.1);
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("collector.db");
+        let path = path.to_str().unwrap();
+        sqlite::open(path)
+            .unwrap()
+            .execute(include_str!("../../../data/normalized.sql"))
+            .unwrap();
+        let id = |t: &str| Id {
+            timestamp: t.to_string(),
+            label_set_id: 1,
+        };
+
+        let mut ds = DataStore::open(path, 1.0, 1, 2, None).unwrap();
+        ds.process_batch(vec![
+            (id("1"), sample(0.0)),
+            (id("2"), sample(0.1)),
+            (id("3"), sample(100.0)),
+        ]);
+        drop(ds);
+
+        // The clusters were formed with different settings.
+        assert!(DataStore::open(path, 2.0, 1, 2, None).is_err());
+        assert!(DataStore::open(path, 1.0, 1, 3, None).is_err());
+
+        let mut ds = DataStore::open(path, 1.0, 1, 2, None).unwrap();
+        assert_eq!(ds.cluster_max, 1);
+        ds.process_batch(vec![
+            (id("4"), sample(0.05)),
+            (id("5"), sample(200.0)),
+            (id("6"), sample(50.0)),
+            (id("7"), sample(50.1)),
+        ]);
+        let clusters: Vec<Option<i64>> = ds
+            .connection
+            .prepare("SELECT cluster_id FROM monitoring_data ORDER BY timestamp")
+            .unwrap()
+            .iter()
+            .map(|row| row.unwrap().read::<Option<i64>, _>(0))
+            .collect();
+        assert_eq!(
+            clusters,
+            [Some(0), Some(0), None, Some(0), None, Some(1), Some(1)]
+        );
+        let count = ds
+            .connection
+            .prepare("SELECT COUNT(*) FROM cluster")
+            .unwrap()
+            .iter()
+            .map(|row| row.unwrap().read::<i64, _>(0))
+            .next();
+        assert_eq!(count, Some(2));
+    }
+
+    /// Creates a database in `dir` with clusters, but no saved cluster group.
+    fn clusters_db(dir: &TempDir, name: &str, clusters: &[(i64, Vec<u8>)]) -> String {
+        let path = dir.path().join(format!("{}.db", name));
+        let path = path.to_str().unwrap().to_string();
+        let connection = sqlite::open(&path).unwrap();
+        connection
+            .execute(include_str!("../../../data/normalized.sql"))
+            .unwrap();
+        for (id, centroid) in clusters {
+            let mut statement = connection
+                .prepare("INSERT INTO cluster (id, group_id, centroid) VALUES (?, 1, ?)")
+                .unwrap();
+            statement.bind((1, *id)).unwrap();
+            statement.bind((2, centroid as &[u8])).unwrap();
+            statement.next().unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn load_damaged() {
+        let dir = tempfile::tempdir().unwrap();
+        let centroid = rmp_serde::to_vec(&sample(0.0)).unwrap();
+        let path = clusters_db(&dir, "gap", &[(0, centroid.clone()), (2, centroid.clone())]);
+        assert!(DataStore::open(&path, 1.0, 1, 1, None).is_err());
+
+        let path = clusters_db(&dir, "corrupt", &[(0, centroid), (1, vec![0xc1])]);
+        assert!(DataStore::open(&path, 1.0, 1, 1, None).is_err());
+    }
+
+    #[test]
+    fn migrate_config_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("collector.db");
+        let path = path.to_str().unwrap();
+        let connection = sqlite::open(path).unwrap();
+        connection
+            .execute(
+                "CREATE TABLE cluster_group (
+                   id INTEGER PRIMARY KEY AUTOINCREMENT,
+                   config TEXT NOT NULL
+                );
+                INSERT INTO cluster_group (id, config) VALUES (1, 'eps=1 noise=0');",
+            )
+            .unwrap();
+        connection
+            .execute(include_str!("../../../data/normalized.sql"))
+            .unwrap();
+        let mut statement = connection
+            .prepare("INSERT INTO cluster (id, group_id, centroid) VALUES (0, 1, ?)")
+            .unwrap();
+        statement
+            .bind((1, &rmp_serde::to_vec(&sample(0.0)).unwrap() as &[u8]))
+            .unwrap();
+        statement.next().unwrap();
+        drop(statement);
+        drop(connection);
+
+        let ds = DataStore::open(path, 1.0, 1, 2, None).unwrap();
+        assert_eq!(ds.cluster_max, 1);
+        drop(ds);
+        // The settings were saved in the new columns, and are checked from now on.
+        assert!(DataStore::open(path, 2.0, 1, 2, None).is_err());
+        assert!(DataStore::open(path, 1.0, 1, 2, None).is_ok());
+    }
+
+    #[test]
+    fn migrate_noise_cluster_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("collector.db");
+        let path = path.to_str().unwrap();
+        let connection = sqlite::open(path).unwrap();
+        connection
+            .execute(
+                "CREATE TABLE monitoring_data (
+                   timestamp DATETIME NOT NULL,
+                   label_set_id INTEGER NOT NULL,
+                   cluster_id INTEGER NOT NULL,
+                   count INTEGER NOT NULL,
+                   PRIMARY KEY (timestamp, label_set_id)
+                );
+                INSERT INTO monitoring_data VALUES ('1', 1, 0, 3), ('2', 1, -1, 1);",
+            )
+            .unwrap();
+        connection
+            .execute(include_str!("../../../data/normalized.sql"))
+            .unwrap();
+        drop(connection);
+
+        let ds = DataStore::open(path, 1.0, 1, 1, None).unwrap();
+        let clusters: Vec<Option<i64>> = ds
+            .connection
+            .prepare("SELECT cluster_id FROM monitoring_data ORDER BY timestamp")
+            .unwrap()
+            .iter()
+            .map(|row| row.unwrap().read::<Option<i64>, _>(0))
+            .collect();
+        assert_eq!(clusters, [Some(0), None]);
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("collector.db");
+        let path = path.to_str().unwrap();
+        let connection = sqlite::open(path).unwrap();
+        // Without a count column, copying the rows fails after the new table
+        // has been created.
+        connection
+            .execute(
+                "CREATE TABLE monitoring_data (
+                   timestamp DATETIME NOT NULL,
+                   label_set_id INTEGER NOT NULL,
+                   cluster_id INTEGER NOT NULL,
+                   PRIMARY KEY (timestamp, label_set_id)
+                );
+                INSERT INTO monitoring_data VALUES ('1', 1, -1);",
+            )
+            .unwrap();
+        assert!(DataStore::migrate_monitoring_data(&connection).is_err());
+
+        let tables: Vec<String> = connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .iter()
+            .map(|row| row.unwrap().read::<&str, _>(0).to_string())
+            .collect();
+        assert_eq!(tables, ["monitoring_data"]);
+        assert_eq!(
+            DataStore::columns(&connection, "monitoring_data").unwrap(),
+            [
+                ("timestamp".to_string(), true),
+                ("label_set_id".to_string(), true),
+                ("cluster_id".to_string(), true),
+            ]
+        );
+        // The transaction isn't left open, so a new one can be started.
+        connection.execute("BEGIN; COMMIT;").unwrap();
+    }
+}
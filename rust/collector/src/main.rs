@@ -23,7 +23,8 @@ use crate::clustering::DataStore;
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use clap::Parser;
 use env_logger::Env;
-use mumble_prometheus::{histogram_to_ecdf, parse_histogram};
+use mumble::ecdf::InterpolatedECDF;
+use mumble_prometheus::parse_and_convert_batch;
 use std::{fmt::Debug, process::ExitCode};
 
 #[derive(Clone, Debug)]
@@ -43,8 +44,51 @@ struct Cli {
     output_database: String,
 
     /// Minimum distance between samples in a cluster.
+    ///
+    /// This, --min-pts and --min-cluster-size must match the settings the
+    /// output database's clusters were formed with.
     #[arg(short, long, default_value_t = 1.0)]
     eps: f64,
+
+    /// Minimum number of samples within eps of a sample, including itself,
+    /// for it to be a core point of a cluster. Samples that aren't reachable
+    /// from a core point are recorded as noise.
+    #[arg(long, default_value_t = 1)]
+    min_pts: usize,
+
+    /// Minimum number of samples in a new cluster. The members of smaller
+    /// groups are recorded as noise.
+    #[arg(long, default_value_t = 1)]
+    min_cluster_size: usize,
+
+    /// Maximum number of points in a new cluster's centroid. Larger
+    /// centroids are compacted. By default, they keep every point.
+    #[arg(long)]
+    max_centroid_size: Option<usize>,
+
+    /// Length of each clustering batch, in minutes. Added to --batch-seconds
+    /// if both are given. Defaults to 30 minutes. Batch boundaries decide
+    /// which samples are clustered together, so changing this invalidates
+    /// the cluster numbering of previously written data.
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    batch_minutes: Option<i64>,
+
+    /// Length of each clustering batch, in seconds. Added to --batch-minutes
+    /// if both are given.
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    batch_seconds: Option<i64>,
+}
+
+impl Cli {
+    /// The length of each clustering batch.
+    fn batch_size(&self) -> Duration {
+        match (self.batch_minutes, self.batch_seconds) {
+            (None, None) => Duration::minutes(30),
+            (minutes, seconds) => {
+                Duration::minutes(minutes.unwrap_or(0)) + Duration::seconds(seconds.unwrap_or(0))
+            }
+        }
+    }
 }
 
 const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%:z";
@@ -61,6 +105,55 @@ fn round_up(dt: DateTime<Utc>, period: Duration) -> DateTime<Utc> {
     Utc.timestamp_opt(seconds + period, 0).unwrap()
 }
 
+/// Groups time-ordered `items` into batches. A batch starts with the first
+/// item at or after the end of the previous batch, and spans `batch_size`.
+fn batch_by_time<T, I, F>(items: I, batch_size: Duration, timestamp: F) -> Vec<Vec<T>>
+where
+    I: IntoIterator<Item = T>,
+    F: Fn(&T) -> DateTime<Utc>,
+{
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_end = DateTime::<Utc>::MIN_UTC;
+    for item in items {
+        let t = timestamp(&item);
+        if t >= batch_end {
+            if !batch.is_empty() {
+                batches.push(batch);
+            }
+            batch = Vec::new();
+            batch_end = round_up(t, batch_size);
+        }
+        batch.push(item);
+    }
+    // Don't forget to add the last batch!
+    batches.push(batch);
+    batches
+}
+
+/// The number of histograms to parse and convert at a time.
+const CONVERT_CHUNK_SIZE: usize = 1024;
+
+/// Converts serialized native histograms to ECDFs, a chunk at a time.
+fn convert_rows<I>(rows: I) -> Vec<(Id, InterpolatedECDF<f64>)>
+where
+    I: IntoIterator<Item = (Id, Vec<u8>)>,
+{
+    let mut rows = rows.into_iter().peekable();
+    let mut samples = Vec::new();
+    let mut chunk = Vec::with_capacity(CONVERT_CHUNK_SIZE);
+    while rows.peek().is_some() {
+        chunk.clear();
+        chunk.extend(rows.by_ref().take(CONVERT_CHUNK_SIZE));
+        let blobs: Vec<&[u8]> = chunk.iter().map(|(_, data)| data.as_slice()).collect();
+        let ecdfs = parse_and_convert_batch(&blobs);
+        for ((id, _), ecdf) in chunk.drain(..).zip(ecdfs) {
+            samples.push((id, ecdf.expect("convert histogram")));
+        }
+    }
+    samples
+}
+
 fn main() -> ExitCode {
     // Parse command-line arguments
     let args = Cli::parse();
@@ -68,49 +161,90 @@ fn main() -> ExitCode {
     // Initialize logging
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    // Break per-second samples up unto 1 minute batches.
-    let batch_size = chrono::Duration::minutes(30);
-
-    let mut batches = Vec::new();
-    let mut batch = Vec::new();
-    let mut batch_end = DateTime::<Utc>::MIN_UTC;
-
     // Open the input database
     let input_connection =
         sqlite::open(/*&args.*/ &args.input_database).expect("open input database");
 
     let query = "SELECT * FROM monitoring_data ORDER BY timestamp ASC;";
-    for row in input_connection
+    let mut statement = input_connection
         .prepare(query)
-        .expect("prepare input query")
+        .expect("prepare input query");
+    let rows = statement
         .iter()
         .map(|row| row.expect("read input row"))
-    {
-        let id = Id {
-            timestamp: row.read::<&str, _>(0).to_string(),
-            label_set_id: row.read::<i64, _>(1),
-        };
-        let data = row.read::<&[u8], _>(2);
-
-        let ecdf = histogram_to_ecdf(&parse_histogram(data).expect("deserialize histogram"));
-
-        let t = parse_timestamp(&id.timestamp);
-        if t >= batch_end {
-            if !batch.is_empty() {
-                batches.push(batch);
-            }
-            batch = Vec::new();
-            batch_end = round_up(t, batch_size);
-        }
-        batch.push((id, ecdf));
-    }
-    // Don't forget to add the last batch!
-    batches.push(batch);
-
-    let mut ds = DataStore::open(&args.output_database, args.eps).expect("open data store");
+        .map(|row| {
+            let id = Id {
+                timestamp: row.read::<&str, _>(0).to_string(),
+                label_set_id: row.read::<i64, _>(1),
+            };
+            (id, row.read::<&[u8], _>(2).to_vec())
+        });
+    let samples = convert_rows(rows);
+    let batches = batch_by_time(samples, args.batch_size(), |(id, _)| {
+        parse_timestamp(&id.timestamp)
+    });
+
+    let mut ds = DataStore::open(
+        &args.output_database,
+        args.eps,
+        args.min_pts,
+        args.min_cluster_size,
+        args.max_centroid_size,
+    )
+    .expect("open data store");
     for batch in batches {
         ds.process_batch(batch);
     }
 
     ExitCode::SUCCESS
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_size() {
+        let args = Cli::parse_from(["collector", "in.db", "out.db"]);
+        assert_eq!(args.batch_size(), Duration::minutes(30));
+        let args = Cli::parse_from(["collector", "in.db", "out.db", "--batch-minutes", "5"]);
+        assert_eq!(args.batch_size(), Duration::minutes(5));
+        let args = Cli::parse_from(["collector", "in.db", "out.db", "--batch-seconds", "90"]);
+        assert_eq!(args.batch_size(), Duration::seconds(90));
+        let args = Cli::parse_from([
+            "collector",
+            "in.db",
+            "out.db",
+            "--batch-minutes",
+            "1",
+            "--batch-seconds",
+            "30",
+        ]);
+        assert_eq!(args.batch_size(), Duration::seconds(90));
+        assert!(
+            Cli::try_parse_from(["collector", "in.db", "out.db", "--batch-minutes", "0"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["collector", "in.db", "out.db", "--batch-seconds", "-1"]).is_err()
+        );
+    }
+
+    #[test]
+    fn batches() {
+        let args = Cli::parse_from(["collector", "in.db", "out.db", "--batch-seconds", "10"]);
+        let times = [0, 1, 9, 10, 15, 19, 20, 45, 46];
+        let batches = batch_by_time(times, args.batch_size(), |&t| {
+            Utc.timestamp_opt(t, 0).unwrap()
+        });
+        assert_eq!(
+            batches,
+            vec![vec![0, 1, 9], vec![10, 15, 19], vec![20], vec![45, 46]]
+        );
+
+        let args = Cli::parse_from(["collector", "in.db", "out.db", "--batch-minutes", "1"]);
+        let batches = batch_by_time(times, args.batch_size(), |&t| {
+            Utc.timestamp_opt(t, 0).unwrap()
+        });
+        assert_eq!(batches, vec![times.to_vec()]);
+    }
+}
@@ -20,11 +20,13 @@ extern crate log;
 use clap::Parser;
 use env_logger::Env;
 use hyper::{server::conn::http1, service::service_fn};
-use mumble::{ui, Histogram, Instrument};
+use mumble::{ui, Gauge, Histogram, MonotonicSource};
 use procfs::process::{Process, Stat};
-use procfs::{CpuTime, KernelStats, ProcResult};
+use procfs::{CpuTime, KernelStats, Meminfo, ProcResult};
+use std::collections::HashMap;
 use std::io::Error;
 use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::runtime;
@@ -32,182 +34,375 @@ use tokio::signal;
 use tokio::task;
 use tokio::time::{Instant, MissedTickBehavior};
 
+/// Cumulative time the system has spent in each CPU mode, in ticks.
+#[derive(Clone, Copy, Debug, Default)]
+struct KernelTicks {
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+    guest: u64,
+    guest_nice: u64,
+}
+
+impl KernelTicks {
+    /// The ticks spent in each mode, labelled by name.
+    fn modes(&self) -> [(&'static str, u64); 10] {
+        [
+            ("user", self.user),
+            ("nice", self.nice),
+            ("system", self.system),
+            ("idle", self.idle),
+            ("iowait", self.iowait),
+            ("irq", self.irq),
+            ("softirq", self.softirq),
+            ("steal", self.steal),
+            ("guest", self.guest),
+            ("guest_nice", self.guest_nice),
+        ]
+    }
+
+    fn total(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+            + self.guest
+            + self.guest_nice
+    }
+}
+
+impl From<&CpuTime> for KernelTicks {
+    fn from(cpu: &CpuTime) -> Self {
+        KernelTicks {
+            user: cpu.user,
+            nice: cpu.nice,
+            system: cpu.system,
+            idle: cpu.idle,
+            iowait: cpu.iowait.unwrap_or(0),
+            irq: cpu.irq.unwrap_or(0),
+            softirq: cpu.softirq.unwrap_or(0),
+            steal: cpu.steal.unwrap_or(0),
+            guest: cpu.guest.unwrap_or(0),
+            guest_nice: cpu.guest_nice.unwrap_or(0),
+        }
+    }
+}
+
+/// A single reading of the kernel's CPU counters.
+#[derive(Clone, Debug, Default)]
+struct SystemTicks {
+    /// The ticks summed over all CPU cores.
+    total: KernelTicks,
+    /// The ticks for each online CPU core, in the order the kernel lists them.
+    cores: Vec<KernelTicks>,
+}
+
+impl From<&KernelStats> for SystemTicks {
+    fn from(stats: &KernelStats) -> Self {
+        SystemTicks {
+            total: KernelTicks::from(&stats.total),
+            cores: stats.cpu_time.iter().map(KernelTicks::from).collect(),
+        }
+    }
+}
+
+/// Cumulative CPU time used by a process, in ticks.
+#[derive(Clone, Copy, Debug, Default)]
+struct ProcessTicks {
+    utime: u64,
+    stime: u64,
+}
+
+impl From<&Stat> for ProcessTicks {
+    fn from(stat: &Stat) -> Self {
+        ProcessTicks {
+            utime: stat.utime,
+            stime: stat.stime,
+        }
+    }
+}
+
+/// Memory used by a process, in bytes.
+#[derive(Clone, Copy, Debug, Default)]
+struct ProcessMemory {
+    resident: u64,
+    virtual_: u64,
+}
+
+/// Memory in use across the whole system, in bytes.
+#[derive(Clone, Copy, Debug, Default)]
+struct SystemMemory {
+    total: u64,
+    available: u64,
+}
+
+impl From<&Meminfo> for SystemMemory {
+    fn from(meminfo: &Meminfo) -> Self {
+        SystemMemory {
+            total: meminfo.mem_total,
+            // Older kernels don't estimate the available memory.
+            available: meminfo.mem_available.unwrap_or(meminfo.mem_free),
+        }
+    }
+}
+
+/// A source of the CPU and memory readings sampled by [Metrics].
+trait StatsSource {
+    fn kernel_ticks(&mut self) -> ProcResult<SystemTicks>;
+    fn process_ticks(&mut self) -> ProcResult<ProcessTicks>;
+    fn ticks_per_second(&self) -> u64;
+    fn process_memory(&mut self) -> ProcResult<ProcessMemory>;
+    fn system_memory(&mut self) -> ProcResult<SystemMemory>;
+}
+
+/// Reads CPU counters for the system and the current process from `/proc`.
+struct ProcStats;
+
+impl StatsSource for ProcStats {
+    fn kernel_ticks(&mut self) -> ProcResult<SystemTicks> {
+        Ok(SystemTicks::from(&KernelStats::new()?))
+    }
+
+    fn process_ticks(&mut self) -> ProcResult<ProcessTicks> {
+        Ok(ProcessTicks::from(&Process::myself()?.stat()?))
+    }
+
+    fn ticks_per_second(&self) -> u64 {
+        procfs::ticks_per_second()
+    }
+
+    fn process_memory(&mut self) -> ProcResult<ProcessMemory> {
+        // statm reports sizes in pages.
+        let statm = Process::myself()?.statm()?;
+        let page_size = procfs::page_size();
+        Ok(ProcessMemory {
+            resident: statm.resident * page_size,
+            virtual_: statm.size * page_size,
+        })
+    }
+
+    fn system_memory(&mut self) -> ProcResult<SystemMemory> {
+        Ok(SystemMemory::from(&Meminfo::new()?))
+    }
+}
+
+/// Kernel readings covering fewer ticks than this are too coarse to record.
+const MIN_TICKS: u64 = 10;
+
+/// CPU usage histograms for a single core, one for each mode.
+struct CoreMetrics {
+    last: Option<KernelTicks>,
+    modes: Vec<Arc<Mutex<Histogram<f64>>>>,
+}
+
+impl CoreMetrics {
+    fn new(meter: &mut mumble::Meter, cpu: usize) -> CoreMetrics {
+        CoreMetrics {
+            last: None,
+            modes: KernelTicks::default()
+                .modes()
+                .iter()
+                .map(|&(mode, _)| {
+                    meter
+                        .create_histogram("core_cpu")
+                        .set_unit("1")
+                        .add_attribute("cpu", (cpu as i64).into())
+                        .add_attribute("mode", mode.into())
+                        .build()
+                })
+                .collect(),
+        }
+    }
+
+    /// Records the fraction of time spent in each mode since the last reading.
+    fn observe(&mut self, ticks: KernelTicks) {
+        if let Some(last) = &self.last {
+            let elapsed = MonotonicSource::increase(last.total(), ticks.total());
+            if elapsed < MIN_TICKS {
+                return;
+            }
+            for ((h, (_, before)), (_, now)) in
+                self.modes.iter().zip(last.modes()).zip(ticks.modes())
+            {
+                let rate = MonotonicSource::increase(before, now) as f64 / elapsed as f64;
+                h.lock().unwrap().record(rate);
+            }
+        }
+        self.last = Some(ticks);
+    }
+}
+
 struct Metrics {
-    last_kernel: Option<KernelStats>,
-    last_process: Option<Stat>,
-    kernel_cpu_user: Histogram<f64>,
-    kernel_cpu_nice: Histogram<f64>,
-    kernel_cpu_system: Histogram<f64>,
-    kernel_cpu_idle: Histogram<f64>,
-    kernel_cpu_iowait: Histogram<f64>,
-    kernel_cpu_irq: Histogram<f64>,
-    kernel_cpu_softirq: Histogram<f64>,
-    kernel_cpu_steal: Histogram<f64>,
-    kernel_cpu_guest: Histogram<f64>,
-    kernel_cpu_guest_nice: Histogram<f64>,
-    process_cpu_user: Histogram<f64>,
-    process_cpu_system: Histogram<f64>,
+    last_kernel: Option<KernelTicks>,
+    /// Per-core metrics, keyed by the core's position in the kernel's list.
+    cores: HashMap<usize, CoreMetrics>,
+    kernel_cpu_user: MonotonicSource,
+    kernel_cpu_nice: MonotonicSource,
+    kernel_cpu_system: MonotonicSource,
+    kernel_cpu_idle: MonotonicSource,
+    kernel_cpu_iowait: MonotonicSource,
+    kernel_cpu_irq: MonotonicSource,
+    kernel_cpu_softirq: MonotonicSource,
+    kernel_cpu_steal: MonotonicSource,
+    kernel_cpu_guest: MonotonicSource,
+    kernel_cpu_guest_nice: MonotonicSource,
+    process_cpu_user: MonotonicSource,
+    process_cpu_system: MonotonicSource,
+    process_memory_resident: Arc<Mutex<Gauge<u64>>>,
+    process_memory_virtual: Arc<Mutex<Gauge<u64>>>,
+    system_memory_used: Arc<Mutex<Gauge<u64>>>,
+    system_memory_available: Arc<Mutex<Gauge<u64>>>,
+}
+
+/// Creates a source for the rate at which a CPU counter in the given mode increases.
+fn rate(meter: &mut mumble::Meter, name: &str, unit: &str, mode: &str) -> MonotonicSource {
+    MonotonicSource::new(
+        meter
+            .create_histogram(name)
+            .set_unit(unit)
+            .add_attribute("mode", mode.into())
+            .build(),
+    )
 }
 
-fn total_ticks(cpu: &CpuTime) -> u64 {
-    cpu.user
-        + cpu.nice
-        + cpu.system
-        + cpu.idle
-        + cpu.iowait.unwrap_or(0)
-        + cpu.irq.unwrap_or(0)
-        + cpu.softirq.unwrap_or(0)
-        + cpu.steal.unwrap_or(0)
-        + cpu.guest.unwrap_or(0)
-        + cpu.guest_nice.unwrap_or(0)
+/// Creates a gauge for the memory size of the given type.
+fn memory(meter: &mut mumble::Meter, name: &str, kind: &str) -> Arc<Mutex<Gauge<u64>>> {
+    meter
+        .create_gauge(name)
+        .set_unit("By")
+        .add_attribute("type", kind.into())
+        .build()
 }
 
 impl Metrics {
     pub fn new(meter: &mut mumble::Meter) -> Metrics {
+        // Kernel CPU time is reported as a fraction of the elapsed ticks, and
+        // process CPU time in seconds.
         Metrics {
             last_kernel: None,
-            last_process: None,
-            kernel_cpu_user: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "user".into())
-                .build(),
-            kernel_cpu_nice: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "nice".into())
-                .build(),
-            kernel_cpu_system: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "system".into())
-                .build(),
-            kernel_cpu_idle: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "idle".into())
-                .build(),
-            kernel_cpu_iowait: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "iowait".into())
-                .build(),
-            kernel_cpu_irq: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "irq".into())
-                .build(),
-            kernel_cpu_softirq: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "softirq".into())
-                .build(),
-            kernel_cpu_steal: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "steal".into())
-                .build(),
-            kernel_cpu_guest: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "guest".into())
-                .build(),
-            kernel_cpu_guest_nice: meter
-                .create_histogram("kernel_cpu")
-                .add_attribute("mode", "guest_nice".into())
-                .build(),
-            process_cpu_user: meter
-                .create_histogram("process_cpu")
-                .add_attribute("mode", "user".into())
-                .build(),
-            process_cpu_system: meter
-                .create_histogram("process_cpu")
-                .add_attribute("mode", "system".into())
-                .build(),
+            cores: HashMap::new(),
+            kernel_cpu_user: rate(meter, "kernel_cpu", "1", "user"),
+            kernel_cpu_nice: rate(meter, "kernel_cpu", "1", "nice"),
+            kernel_cpu_system: rate(meter, "kernel_cpu", "1", "system"),
+            kernel_cpu_idle: rate(meter, "kernel_cpu", "1", "idle"),
+            kernel_cpu_iowait: rate(meter, "kernel_cpu", "1", "iowait"),
+            kernel_cpu_irq: rate(meter, "kernel_cpu", "1", "irq"),
+            kernel_cpu_softirq: rate(meter, "kernel_cpu", "1", "softirq"),
+            kernel_cpu_steal: rate(meter, "kernel_cpu", "1", "steal"),
+            kernel_cpu_guest: rate(meter, "kernel_cpu", "1", "guest"),
+            kernel_cpu_guest_nice: rate(meter, "kernel_cpu", "1", "guest_nice"),
+            process_cpu_user: rate(meter, "process_cpu", "s", "user"),
+            process_cpu_system: rate(meter, "process_cpu", "s", "system"),
+            process_memory_resident: memory(meter, "process_memory", "resident"),
+            process_memory_virtual: memory(meter, "process_memory", "virtual"),
+            system_memory_used: memory(meter, "system_memory", "used"),
+            system_memory_available: memory(meter, "system_memory", "available"),
         }
     }
 
-    fn sample(&mut self) -> ProcResult<()> {
-        let ks = KernelStats::new()?;
-        if let Some(last_ks) = &self.last_kernel {
-            // Kernel stats are given in ticks, which can be converted to seconds
-            // using procfs::ticks_per_second().
-            let ticks_raw = total_ticks(&ks.total) - total_ticks(&last_ks.total);
-            if ticks_raw < 10 {
-                return Ok(());
-            }
-            let ticks = ticks_raw as f64;
-            self.kernel_cpu_user
-                .record(((ks.total.user - last_ks.total.user) as f64) / ticks);
-            self.kernel_cpu_nice
-                .record(((ks.total.nice - last_ks.total.nice) as f64) / ticks);
-            self.kernel_cpu_system
-                .record(((ks.total.system - last_ks.total.system) as f64) / ticks);
-            self.kernel_cpu_idle
-                .record(((ks.total.idle - last_ks.total.idle) as f64) / ticks);
-            self.kernel_cpu_iowait.record(
-                ((ks.total.iowait.unwrap_or(0) - last_ks.total.iowait.unwrap_or(0)) as f64) / ticks,
-            );
-            self.kernel_cpu_irq.record(
-                ((ks.total.irq.unwrap_or(0) - last_ks.total.irq.unwrap_or(0)) as f64) / ticks,
-            );
-            self.kernel_cpu_softirq.record(
-                ((ks.total.softirq.unwrap_or(0) - last_ks.total.softirq.unwrap_or(0)) as f64)
-                    / ticks,
-            );
-            self.kernel_cpu_steal.record(
-                ((ks.total.steal.unwrap_or(0) - last_ks.total.steal.unwrap_or(0)) as f64) / ticks,
-            );
-            self.kernel_cpu_guest.record(
-                ((ks.total.guest.unwrap_or(0) - last_ks.total.guest.unwrap_or(0)) as f64) / ticks,
-            );
-            self.kernel_cpu_guest_nice.record(
-                ((ks.total.guest_nice.unwrap_or(0) - last_ks.total.guest_nice.unwrap_or(0)) as f64)
-                    / ticks,
-            );
+    /// Takes a reading from `source`. Histograms for newly seen CPU cores are
+    /// created with `meter`.
+    fn sample(
+        &mut self,
+        meter: &mut mumble::Meter,
+        source: &mut impl StatsSource,
+    ) -> ProcResult<()> {
+        // Memory readings are levels rather than counters, so only the
+        // latest one matters.
+        let pm = source.process_memory()?;
+        let record = |g: &Arc<Mutex<Gauge<u64>>>, bytes: u64| {
+            g.lock().unwrap().set(bytes);
+        };
+        record(&self.process_memory_resident, pm.resident);
+        record(&self.process_memory_virtual, pm.virtual_);
+        let sm = source.system_memory()?;
+        record(
+            &self.system_memory_used,
+            sm.total.saturating_sub(sm.available),
+        );
+        record(&self.system_memory_available, sm.available);
+
+        // The totals and the per-core counters come from the same reading,
+        // so they cover the same interval.
+        let SystemTicks { total: ks, cores } = source.kernel_ticks()?;
+        for (cpu, ticks) in cores.iter().enumerate() {
+            self.cores
+                .entry(cpu)
+                .or_insert_with(|| CoreMetrics::new(meter, cpu))
+                .observe(*ticks);
+        }
+        // Cores that went offline start over if they come back, since their
+        // counters may have been reset in the meantime.
+        for (_, core) in self.cores.iter_mut().filter(|(&cpu, _)| cpu >= cores.len()) {
+            core.last = None;
         }
+
+        let ticks = match &self.last_kernel {
+            Some(last_ks) => {
+                // Kernel stats are given in ticks, which can be converted to seconds
+                // using procfs::ticks_per_second().
+                let ticks_raw = MonotonicSource::increase(last_ks.total(), ks.total());
+                if ticks_raw < MIN_TICKS {
+                    return Ok(());
+                }
+                ticks_raw as f64
+            }
+            // The first readings only establish the baseline, so aren't recorded.
+            None => 1.0,
+        };
+        self.kernel_cpu_user.observe(ks.user, ticks);
+        self.kernel_cpu_nice.observe(ks.nice, ticks);
+        self.kernel_cpu_system.observe(ks.system, ticks);
+        self.kernel_cpu_idle.observe(ks.idle, ticks);
+        self.kernel_cpu_iowait.observe(ks.iowait, ticks);
+        self.kernel_cpu_irq.observe(ks.irq, ticks);
+        self.kernel_cpu_softirq.observe(ks.softirq, ticks);
+        self.kernel_cpu_steal.observe(ks.steal, ticks);
+        self.kernel_cpu_guest.observe(ks.guest, ticks);
+        self.kernel_cpu_guest_nice.observe(ks.guest_nice, ticks);
         self.last_kernel = Some(ks);
 
-        let ps = Process::myself()?.stat()?;
-        if let Some(last_ps) = &self.last_process {
-            let ticks = procfs::ticks_per_second() as f64;
-            self.process_cpu_user
-                .record(((ps.utime - last_ps.utime) as f64) / ticks);
-            self.process_cpu_system
-                .record(((ps.stime - last_ps.stime) as f64) / ticks);
-        }
-        self.last_process = Some(ps);
+        let ps = source.process_ticks()?;
+        let ticks = source.ticks_per_second() as f64;
+        self.process_cpu_user.observe(ps.utime, ticks);
+        self.process_cpu_system.observe(ps.stime, ticks);
         Ok(())
     }
-
-    fn push(&mut self) {
-        let t = mumble::get_timestamp();
-        self.kernel_cpu_user.push(t);
-        self.kernel_cpu_nice.push(t);
-        self.kernel_cpu_system.push(t);
-        self.kernel_cpu_idle.push(t);
-        self.kernel_cpu_iowait.push(t);
-        self.kernel_cpu_irq.push(t);
-        self.kernel_cpu_softirq.push(t);
-        self.kernel_cpu_steal.push(t);
-        self.kernel_cpu_guest.push(t);
-        self.kernel_cpu_guest_nice.push(t);
-        self.process_cpu_user.push(t);
-        self.process_cpu_system.push(t);
-    }
 }
 
-async fn monitoring_loop(port: u16) -> Result<(), Error> {
+async fn monitoring_loop(
+    port: u16,
+    sample_period: Duration,
+    push_period: Duration,
+) -> Result<(), Error> {
     let mut mp = mumble::MeterProvider::default();
-    let mut metrics = Metrics::new(mp.get_meter(
+    let meter = mp.get_meter(
         env!("CARGO_PKG_NAME").into(),
         Some(env!("CARGO_PKG_VERSION").into()),
         None,
         None,
-    ));
+    );
+    let mut metrics = Metrics::new(meter);
 
     let listener = TcpListener::bind(("127.0.0.1", port)).await?;
     info!("Listening on port {}", port);
 
-    let mut sample_interval = tokio::time::interval(Duration::from_millis(500));
+    let mut sample_interval = tokio::time::interval(sample_period);
     sample_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-    const PUSH_DURATION: Duration = Duration::from_secs(5);
-    let mut push_interval = tokio::time::interval_at(Instant::now() + PUSH_DURATION, PUSH_DURATION);
+    let mut push_interval = tokio::time::interval_at(Instant::now() + push_period, push_period);
     push_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-    let mut maintenance_interval = tokio::time::interval(ui::MAINTENANCE_INTERVAL);
+    let mut maintenance_interval = tokio::time::interval(ui::heartbeat_interval());
     maintenance_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
     loop {
@@ -217,12 +412,12 @@ async fn monitoring_loop(port: u16) -> Result<(), Error> {
                 break
             }
             _ = sample_interval.tick() => {
-                if let Err(e) = metrics.sample() {
+                if let Err(e) = metrics.sample(meter, &mut ProcStats) {
                     error!("unable to sample metrics: {}", e);
                 }
             }
             _ = push_interval.tick() => {
-                metrics.push();
+                meter.push_all(mumble::get_timestamp());
             }
             _ = maintenance_interval.tick() => {
                 ui::perform_maintenance();
@@ -236,6 +431,9 @@ async fn monitoring_loop(port: u16) -> Result<(), Error> {
         }
         task::yield_now().await;
     }
+    // Don't lose what was sampled since the last push.
+    mp.shutdown();
+    ui::shutdown();
     Ok(())
 }
 
@@ -244,6 +442,44 @@ struct Cli {
     /// Monitoring port to use.
     #[arg(short, long, default_value_t = 9100)]
     port: u16,
+
+    /// How often to sample CPU counters, in milliseconds.
+    #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..))]
+    sample_interval_ms: u64,
+
+    /// How often to push collected metrics, in seconds.
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    push_interval_secs: u64,
+
+    /// How often to check on UI clients and drop stale ones, in seconds.
+    #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    heartbeat_interval_secs: u64,
+}
+
+impl Cli {
+    fn sample_interval(&self) -> Duration {
+        Duration::from_millis(self.sample_interval_ms)
+    }
+
+    fn push_interval(&self) -> Duration {
+        Duration::from_secs(self.push_interval_secs)
+    }
+
+    fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs)
+    }
+
+    /// Checks that the arguments are consistent with each other.
+    fn validate(&self) -> Result<(), String> {
+        if self.sample_interval() > self.push_interval() {
+            return Err(format!(
+                "sample interval ({:?}) must not be longer than the push interval ({:?})",
+                self.sample_interval(),
+                self.push_interval()
+            ));
+        }
+        Ok(())
+    }
 }
 
 fn main() -> ExitCode {
@@ -251,12 +487,22 @@ fn main() -> ExitCode {
     let args = Cli::parse();
     // Initialize logging
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    if let Err(err) = args.validate() {
+        error!("{}", err);
+        return ExitCode::FAILURE;
+    }
+    ui::configure(ui::ServerConfig {
+        heartbeat_interval: args.heartbeat_interval(),
+        ..Default::default()
+    })
+    .expect("UI is only configured once");
 
+    let monitor = monitoring_loop(args.port, args.sample_interval(), args.push_interval());
     match runtime::Builder::new_current_thread()
         .enable_time()
         .enable_io()
         .build()
-        .and_then(|rt| rt.block_on(monitoring_loop(args.port)))
+        .and_then(|rt| rt.block_on(monitor))
     {
         Err(err) => {
             error!("{}", err);
@@ -265,3 +511,396 @@ fn main() -> ExitCode {
         _ => ExitCode::SUCCESS,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use procfs::FromRead;
+    use std::collections::VecDeque;
+
+    /// Replays a fixed sequence of snapshots.
+    struct FakeStats {
+        kernel: VecDeque<KernelTicks>,
+        cores: VecDeque<Vec<KernelTicks>>,
+        process: VecDeque<ProcessTicks>,
+    }
+
+    impl StatsSource for FakeStats {
+        fn kernel_ticks(&mut self) -> ProcResult<SystemTicks> {
+            Ok(SystemTicks {
+                total: self.kernel.pop_front().unwrap(),
+                cores: self.cores.pop_front().unwrap_or_default(),
+            })
+        }
+
+        fn process_ticks(&mut self) -> ProcResult<ProcessTicks> {
+            Ok(self.process.pop_front().unwrap())
+        }
+
+        fn ticks_per_second(&self) -> u64 {
+            100
+        }
+
+        fn process_memory(&mut self) -> ProcResult<ProcessMemory> {
+            Ok(ProcessMemory {
+                resident: 4096,
+                virtual_: 65536,
+            })
+        }
+
+        fn system_memory(&mut self) -> ProcResult<SystemMemory> {
+            Ok(SystemMemory {
+                total: 1 << 30,
+                available: 1 << 28,
+            })
+        }
+    }
+
+    fn only_value(h: &Histogram<f64>) -> f64 {
+        let ecdf = h.ecdf();
+        assert_eq!(ecdf.len(), 1);
+        ecdf.point_iter().next().unwrap().0
+    }
+
+    #[test]
+    fn sample_fractions() {
+        let mut mp = mumble::MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut metrics = Metrics::new(meter);
+        let mut source = FakeStats {
+            kernel: VecDeque::from([
+                KernelTicks::default(),
+                KernelTicks {
+                    user: 10,
+                    nice: 20,
+                    system: 30,
+                    idle: 40,
+                    iowait: 50,
+                    irq: 60,
+                    softirq: 70,
+                    steal: 80,
+                    guest: 90,
+                    guest_nice: 50,
+                },
+            ]),
+            cores: VecDeque::new(),
+            process: VecDeque::from([
+                ProcessTicks { utime: 0, stime: 0 },
+                ProcessTicks {
+                    utime: 25,
+                    stime: 50,
+                },
+            ]),
+        };
+        metrics.sample(meter, &mut source).unwrap();
+        assert!(metrics
+            .kernel_cpu_user
+            .histogram()
+            .lock()
+            .unwrap()
+            .ecdf()
+            .is_empty());
+        assert!(metrics
+            .process_cpu_user
+            .histogram()
+            .lock()
+            .unwrap()
+            .ecdf()
+            .is_empty());
+        metrics.sample(meter, &mut source).unwrap();
+
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_user.histogram().lock().unwrap()),
+            0.02
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_nice.histogram().lock().unwrap()),
+            0.04
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_system.histogram().lock().unwrap()),
+            0.06
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_idle.histogram().lock().unwrap()),
+            0.08
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_iowait.histogram().lock().unwrap()),
+            0.1
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_irq.histogram().lock().unwrap()),
+            0.12
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_softirq.histogram().lock().unwrap()),
+            0.14
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_steal.histogram().lock().unwrap()),
+            0.16
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_guest.histogram().lock().unwrap()),
+            0.18
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_guest_nice.histogram().lock().unwrap()),
+            0.1
+        );
+        assert_eq!(
+            only_value(&metrics.process_cpu_user.histogram().lock().unwrap()),
+            0.25
+        );
+        assert_eq!(
+            only_value(&metrics.process_cpu_system.histogram().lock().unwrap()),
+            0.5
+        );
+    }
+
+    #[test]
+    fn per_core() {
+        let mut mp = mumble::MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut metrics = Metrics::new(meter);
+        // Cumulative ticks for a core that has spent `user` of `total` ticks busy.
+        let busy = |user, total| KernelTicks {
+            user,
+            idle: total - user,
+            ..Default::default()
+        };
+        let mut source = FakeStats {
+            kernel: VecDeque::from([KernelTicks::default(); 4]),
+            cores: VecDeque::from([
+                vec![busy(0, 100), busy(0, 100)],
+                vec![busy(25, 200), busy(50, 200), busy(10, 100)],
+                // Core 2 goes offline, then comes back with its counters reset.
+                vec![busy(50, 300), busy(100, 300)],
+                vec![busy(75, 400), busy(150, 400), busy(0, 10)],
+            ]),
+            process: VecDeque::from([ProcessTicks::default(); 4]),
+        };
+        metrics.sample(meter, &mut source).unwrap();
+        assert_eq!(metrics.cores.len(), 2);
+        metrics.sample(meter, &mut source).unwrap();
+        assert_eq!(metrics.cores.len(), 3);
+        for core in metrics.cores.values() {
+            assert_eq!(core.modes.len(), 10);
+        }
+        // Each core reports the fraction of its own ticks spent in each mode.
+        assert_eq!(
+            only_value(&metrics.cores[&0].modes[0].lock().unwrap()),
+            0.25
+        );
+        assert_eq!(
+            only_value(&metrics.cores[&0].modes[3].lock().unwrap()),
+            0.75
+        );
+        assert_eq!(only_value(&metrics.cores[&1].modes[0].lock().unwrap()), 0.5);
+        assert!(metrics.cores[&2].modes[0].lock().unwrap().ecdf().is_empty());
+
+        metrics.sample(meter, &mut source).unwrap();
+        assert!(metrics.cores[&2].last.is_none());
+        metrics.sample(meter, &mut source).unwrap();
+        assert_eq!(metrics.cores.len(), 3);
+        assert!(metrics.cores[&2].modes[0].lock().unwrap().ecdf().is_empty());
+    }
+
+    #[test]
+    fn sample_memory() {
+        let mut mp = mumble::MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut metrics = Metrics::new(meter);
+        let mut source = FakeStats {
+            kernel: VecDeque::from([KernelTicks::default()]),
+            cores: VecDeque::new(),
+            process: VecDeque::from([ProcessTicks::default()]),
+        };
+        metrics.sample(meter, &mut source).unwrap();
+        let value = |g: &Arc<Mutex<Gauge<u64>>>| g.lock().unwrap().value();
+        assert_eq!(value(&metrics.process_memory_resident), Some(4096));
+        assert_eq!(value(&metrics.process_memory_virtual), Some(65536));
+        assert_eq!(value(&metrics.system_memory_used), Some(3 << 28));
+        assert_eq!(value(&metrics.system_memory_available), Some(1 << 28));
+    }
+
+    #[test]
+    fn parse_proc() {
+        let kernel = KernelStats::from_read(
+            "cpu  100 2 30 400 5 6 7 8 9 10
+cpu0 60 1 20 200 3 4 5 6 7 8
+cpu1 40 1 10 200 2 2 2 2 2 2
+intr 0
+ctxt 1000
+btime 1700000000
+processes 50
+procs_running 1
+procs_blocked 0
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let SystemTicks { total, cores } = SystemTicks::from(&kernel);
+        assert_eq!(total.total(), 577);
+        assert_eq!((total.user, total.guest_nice), (100, 10));
+        assert_eq!(cores.len(), 2);
+        assert_eq!(cores[0].total() + cores[1].total(), total.total());
+
+        let stat = Stat::from_read(
+            "1234 (cpumon) S 1 1234 1234 0 -1 4194304 100 0 0 0 25 7 0 0 20 0 4 0 12345 \
+             65536000 1000 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 3 0 0 0 0 0 0 0 \
+             0 0 0 0 0 0"
+                .as_bytes(),
+        )
+        .unwrap();
+        let process = ProcessTicks::from(&stat);
+        assert_eq!((process.utime, process.stime), (25, 7));
+
+        let meminfo = |available: &str| {
+            let text = format!(
+                "MemTotal:        4096 kB
+MemFree:         1024 kB
+{}Buffers:          128 kB
+Cached:           512 kB
+SwapCached:         0 kB
+Active:          1024 kB
+Inactive:         512 kB
+SwapTotal:          0 kB
+SwapFree:           0 kB
+Dirty:              0 kB
+Writeback:          0 kB
+Mapped:           256 kB
+Slab:             128 kB
+",
+                available
+            );
+            SystemMemory::from(&Meminfo::from_read(text.as_bytes()).unwrap())
+        };
+        let memory = meminfo("MemAvailable:    2048 kB\n");
+        assert_eq!((memory.total, memory.available), (4096 << 10, 2048 << 10));
+        // Older kernels don't report MemAvailable.
+        let memory = meminfo("");
+        assert_eq!(memory.available, 1024 << 10);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sample_proc() {
+        let mut mp = mumble::MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut metrics = Metrics::new(meter);
+        metrics.sample(meter, &mut ProcStats).unwrap();
+        assert!(metrics.process_memory_resident.lock().unwrap().value() > Some(0));
+        assert!(metrics.system_memory_available.lock().unwrap().value() > Some(0));
+    }
+
+    #[test]
+    fn skip_short_intervals() {
+        let mut mp = mumble::MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut metrics = Metrics::new(meter);
+        let mut source = FakeStats {
+            kernel: VecDeque::from([
+                KernelTicks::default(),
+                KernelTicks {
+                    user: 5,
+                    ..Default::default()
+                },
+            ]),
+            cores: VecDeque::new(),
+            process: VecDeque::from([ProcessTicks::default()]),
+        };
+        metrics.sample(meter, &mut source).unwrap();
+        metrics.sample(meter, &mut source).unwrap();
+        // Fewer than 10 ticks elapsed, so the kernel baseline is kept.
+        assert_eq!(metrics.last_kernel.unwrap().user, 0);
+        assert!(metrics
+            .kernel_cpu_user
+            .histogram()
+            .lock()
+            .unwrap()
+            .ecdf()
+            .is_empty());
+    }
+
+    #[test]
+    fn cli_intervals() {
+        let args = Cli::parse_from(["cpumon"]);
+        assert_eq!(args.sample_interval(), Duration::from_millis(500));
+        assert_eq!(args.push_interval(), Duration::from_secs(5));
+        assert!(args.validate().is_ok());
+
+        let args = Cli::parse_from([
+            "cpumon",
+            "--sample-interval-ms",
+            "10",
+            "--push-interval-secs",
+            "60",
+        ]);
+        assert_eq!(args.sample_interval(), Duration::from_millis(10));
+        assert_eq!(args.push_interval(), Duration::from_secs(60));
+        assert!(args.validate().is_ok());
+
+        let args = Cli::parse_from([
+            "cpumon",
+            "--sample-interval-ms",
+            "2000",
+            "--push-interval-secs",
+            "1",
+        ]);
+        assert!(args.validate().is_err());
+        assert!(Cli::try_parse_from(["cpumon", "--sample-interval-ms", "0"]).is_err());
+    }
+
+    #[test]
+    fn counter_reset() {
+        let mut mp = mumble::MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut metrics = Metrics::new(meter);
+        let mut source = FakeStats {
+            kernel: VecDeque::from([
+                KernelTicks {
+                    user: 1000,
+                    idle: 1000,
+                    ..Default::default()
+                },
+                // The counters were reset.
+                KernelTicks {
+                    user: 30,
+                    idle: 70,
+                    ..Default::default()
+                },
+            ]),
+            cores: VecDeque::new(),
+            process: VecDeque::from([
+                ProcessTicks {
+                    utime: 500,
+                    stime: 500,
+                },
+                ProcessTicks {
+                    utime: 10,
+                    stime: 20,
+                },
+            ]),
+        };
+        metrics.sample(meter, &mut source).unwrap();
+        metrics.sample(meter, &mut source).unwrap();
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_user.histogram().lock().unwrap()),
+            0.3
+        );
+        assert_eq!(
+            only_value(&metrics.kernel_cpu_idle.histogram().lock().unwrap()),
+            0.7
+        );
+        assert_eq!(
+            only_value(&metrics.process_cpu_user.histogram().lock().unwrap()),
+            0.1
+        );
+        assert_eq!(
+            only_value(&metrics.process_cpu_system.histogram().lock().unwrap()),
+            0.2
+        );
+    }
+}
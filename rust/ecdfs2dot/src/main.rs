@@ -1,58 +1,157 @@
+use clap::Parser;
 use flame_clustering::{DistanceGraph, ObjectType};
 use mumble::ecdf::ECDF;
-use std::{collections::HashSet, io};
+use std::{
+    collections::HashSet,
+    io::{self, BufRead, Write},
+};
 
-fn main() {
-    let ecdfs: Vec<ECDF<f64>> = io::stdin()
+/// Clusters ECDFs read from standard input, one JSON document per line, and
+/// writes the result as a Graphviz DOT graph.
+#[derive(Parser)]
+struct Cli {
+    /// The number of nearest neighbors used to estimate each ECDF's density.
+    #[arg(long, default_value_t = 3)]
+    knn: usize,
+
+    /// The density below which ECDFs are outliers. Negative values choose
+    /// a threshold automatically.
+    #[arg(long, default_value_t = -1.0, allow_negative_numbers = true)]
+    outlier_threshold: f64,
+
+    /// The maximum number of iterations when approximating fuzzy memberships.
+    #[arg(long, default_value_t = 100)]
+    max_iterations: usize,
+
+    /// Stops approximating fuzzy memberships once they change by less than this.
+    #[arg(long, default_value_t = 1e-6)]
+    epsilon: f64,
+
+    /// The membership an ECDF needs to join a cluster. Negative values
+    /// assign each ECDF to the cluster it belongs to most.
+    #[arg(long, default_value_t = -1.0, allow_negative_numbers = true)]
+    cluster_threshold: f64,
+}
+
+fn read_ecdfs<R: BufRead>(reader: R) -> Vec<ECDF<f64>> {
+    reader
         .lines()
         .map(|x| {
             let ecdf: ECDF<f64> = serde_json::from_str(&x.unwrap()).unwrap();
             ecdf
         })
-        .collect();
+        .collect()
+}
 
-    let graph = DistanceGraph::build(&ecdfs, |a, b| a.area_difference(b));
+fn write_dot<W: Write>(args: &Cli, ecdfs: &[ECDF<f64>], out: &mut W) -> io::Result<()> {
+    let graph = DistanceGraph::build(ecdfs, |a, b| a.area_difference(b));
     let csos = graph
-        .find_supporting_objects(3, -1.0)
-        .approximate_fuzzy_memberships(100, 1e-6);
+        .find_supporting_objects(args.knn, args.outlier_threshold)
+        .approximate_fuzzy_memberships(args.max_iterations, args.epsilon);
 
-    let (clusters, outliers) = csos.make_clusters(-1.0);
+    let (clusters, outliers) = csos.make_clusters(args.cluster_threshold);
 
-    println!("graph {{");
+    writeln!(out, "graph {{")?;
     for (cid, cluster) in clusters.iter().enumerate() {
-        println!("  subgraph cluster_{} {{", cid);
+        writeln!(out, "  subgraph cluster_{} {{", cid)?;
         for &id in cluster {
-            print!("    n{} [label=\"{}\"", id, id);
+            write!(out, "    n{} [label=\"{}\"", id, id)?;
             match csos.object_type(id) {
                 ObjectType::Support => {
-                    print!(" color=\"blue\" style=\"bold\"");
+                    write!(out, " color=\"blue\" style=\"bold\"")?;
                 }
                 ObjectType::Outlier => {
-                    print!(" color=\"red\"");
+                    write!(out, " color=\"red\"")?;
                 }
                 _ => {}
             }
-            println!("];");
+            writeln!(out, "];")?;
         }
-        println!("    label=\"cluster {}\";", cid);
-        println!("    graph[style=solid];");
-        println!("  }}");
+        writeln!(out, "    label=\"cluster {}\";", cid)?;
+        writeln!(out, "    graph[style=solid];")?;
+        writeln!(out, "  }}")?;
     }
     for id in outliers {
-        println!("  n{} [label=\"{}\"];", id, id);
+        writeln!(out, "  n{} [label=\"{}\"];", id, id)?;
     }
     let mut edges = HashSet::new();
     for id in 0..ecdfs.len() {
         for (n, d) in graph.neighbors(id) {
             let key = if id < n { (id, n) } else { (n, id) };
             if !edges.contains(&key) {
-                println!(
+                writeln!(
+                    out,
                     "  n{} -- n{} [style=dashed tooltip=\"{}\" len={}];",
                     id, n, d, d
-                );
+                )?;
                 edges.insert(key);
             }
         }
     }
-    println!("}}");
+    writeln!(out, "}}")
+}
+
+fn main() {
+    let args = Cli::parse();
+    let ecdfs = read_ecdfs(io::stdin().lock());
+    write_dot(&args, &ecdfs, &mut io::stdout().lock()).expect("write graph");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args() {
+        let args = Cli::parse_from(["ecdfs2dot"]);
+        assert_eq!(args.knn, 3);
+        assert_eq!(args.outlier_threshold, -1.0);
+        assert_eq!(args.max_iterations, 100);
+        assert_eq!(args.epsilon, 1e-6);
+        assert_eq!(args.cluster_threshold, -1.0);
+
+        let args = Cli::parse_from([
+            "ecdfs2dot",
+            "--knn",
+            "5",
+            "--outlier-threshold",
+            "-0.5",
+            "--max-iterations",
+            "10",
+            "--epsilon",
+            "0.01",
+            "--cluster-threshold",
+            "0.25",
+        ]);
+        assert_eq!(args.knn, 5);
+        assert_eq!(args.outlier_threshold, -0.5);
+        assert_eq!(args.max_iterations, 10);
+        assert_eq!(args.epsilon, 0.01);
+        assert_eq!(args.cluster_threshold, 0.25);
+    }
+
+    #[test]
+    fn dot() {
+        // Two groups of similar ECDFs.
+        let input: String = [0.0, 0.1, 0.2, 10.0, 10.1, 10.2]
+            .iter()
+            .map(|c| format!("[[{},1],[{},1]]\n", c, c + 1.0))
+            .collect();
+        let ecdfs = read_ecdfs(input.as_bytes());
+        assert_eq!(ecdfs.len(), 6);
+
+        let mut out = Vec::new();
+        write_dot(&Cli::parse_from(["ecdfs2dot"]), &ecdfs, &mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("graph {\n"), "{}", dot);
+        assert!(dot.ends_with("}\n"), "{}", dot);
+        assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+        for id in 0..ecdfs.len() {
+            assert!(
+                dot.contains(&format!("  n{} [label=\"{}\"", id, id)),
+                "{}",
+                dot
+            );
+        }
+    }
 }
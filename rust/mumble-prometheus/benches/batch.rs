@@ -0,0 +1,82 @@
+// Compares allocations made converting histograms singly and in batches.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use mumble::ecdf::ECDF;
+use mumble_prometheus::{
+    ecdf_to_histogram, histogram_to_ecdf, parse_and_convert_batch, parse_histogram,
+};
+use protobuf::Message;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const HISTOGRAMS: usize = 10_000;
+const SAMPLES: usize = 200;
+
+/// Wraps the system allocator, counting every allocation it makes.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn measure<T>(name: &str, f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let out = f();
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("{name:>6}: {allocations:>9} allocations in {elapsed:?}");
+    (out, allocations)
+}
+
+fn main() {
+    let blobs: Vec<Vec<u8>> = (0..HISTOGRAMS)
+        .map(|i| {
+            let samples: Vec<f64> = (0..SAMPLES)
+                .map(|j| ((i * 7 + j * 13) % 500) as f64 * 0.1 - 25.0)
+                .collect();
+            ecdf_to_histogram(&ECDF::from(samples).interpolate(), 3)
+                .write_to_bytes()
+                .expect("serialize histogram")
+        })
+        .collect();
+    let refs: Vec<&[u8]> = blobs.iter().map(|b| b.as_slice()).collect();
+
+    let (single, single_allocations) = measure("single", || {
+        refs.iter()
+            .map(|data| histogram_to_ecdf(&parse_histogram(data).expect("parse histogram")))
+            .collect::<Vec<_>>()
+    });
+    let (batch, batch_allocations) = measure("batch", || parse_and_convert_batch(&refs));
+    assert_eq!(single.len(), batch.len());
+    println!(
+        "batch conversion made {:.1}% of the allocations",
+        100.0 * batch_allocations as f64 / single_allocations as f64
+    );
+}
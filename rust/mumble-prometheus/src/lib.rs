@@ -16,17 +16,63 @@
 
 mod histogram;
 mod protos;
+mod reset;
+mod scrape;
+mod text;
 
 use crate::histogram::get_bound;
-use protos::metrics::BucketSpan;
 pub use protos::metrics::Histogram;
+use protos::metrics::{Bucket, BucketSpan};
+pub use reset::{detect_reset, HistogramAccumulator};
+pub use scrape::{scrape, ScrapeError, PROTOBUF_CONTENT_TYPE};
+pub use text::{parse_text_histograms, ParseError};
 
 use mumble::ecdf::{InterpolatedECDF, ECDF};
 
 use protobuf::Message;
+use std::fmt::{self, Display};
 
-fn positive_counts(spans: &Vec<BucketSpan>, deltas: &Vec<i64>, schema: i32) -> Vec<(f64, usize)> {
-    let mut out = Vec::with_capacity(deltas.len() + spans.len());
+/// Errors that can occur when converting serialized Prometheus histograms.
+#[derive(Debug)]
+pub enum HistogramError {
+    /// The data could not be decoded as a histogram.
+    Decode(protobuf::Error),
+    /// The histogram uses classic (fixed) buckets, which aren't supported.
+    ///
+    /// These can be converted with [classic_histogram_to_ecdf] instead.
+    Unsupported,
+    /// The bucket spans describe more buckets than there are counts for.
+    MissingBuckets,
+}
+
+impl Display for HistogramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistogramError::Decode(e) => write!(f, "unable to decode histogram: {}", e),
+            HistogramError::Unsupported => write!(f, "classic histograms are not supported"),
+            HistogramError::MissingBuckets => {
+                write!(f, "spans describe more buckets than there are counts")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HistogramError {}
+
+impl From<protobuf::Error> for HistogramError {
+    fn from(e: protobuf::Error) -> Self {
+        HistogramError::Decode(e)
+    }
+}
+
+fn positive_counts(
+    spans: &Vec<BucketSpan>,
+    deltas: &Vec<i64>,
+    schema: i32,
+    out: &mut Vec<(f64, usize)>,
+) -> Result<(), HistogramError> {
+    out.clear();
+    out.reserve(deltas.len() + spans.len());
 
     let mut last_schema_idx: i32 = 0;
     let mut bucket_idx: usize = 0;
@@ -38,80 +84,624 @@ fn positive_counts(spans: &Vec<BucketSpan>, deltas: &Vec<i64>, schema: i32) -> V
 
         out.push((get_bound(start_schema_idx - 1, schema), 0));
         for schema_idx in start_schema_idx..end_schema_idx {
-            bucket_sum += deltas[bucket_idx];
+            bucket_sum += deltas
+                .get(bucket_idx)
+                .ok_or(HistogramError::MissingBuckets)?;
             bucket_idx += 1;
             out.push((get_bound(schema_idx, schema), bucket_sum as usize));
         }
     }
-    out
+    Ok(())
 }
 
-fn negative_counts(spans: &Vec<BucketSpan>, deltas: &Vec<i64>, schema: i32) -> Vec<(f64, usize)> {
-    let mut last_schema_idx: i32 = 0;
-    let mut last_bucket_idx: usize = 0;
-    for span in spans.iter() {
-        last_schema_idx += span.offset() + span.length() as i32;
-        last_bucket_idx += span.length() as usize;
+fn negative_counts(
+    spans: &Vec<BucketSpan>,
+    deltas: &Vec<i64>,
+    schema: i32,
+    out: &mut Vec<(f64, usize)>,
+) -> Result<(), HistogramError> {
+    // Negative buckets are laid out like positive ones, mirrored around zero.
+    // Each count belongs to the bucket's edge nearest zero, which after the
+    // mirroring is the following point, so shift the counts along by one.
+    positive_counts(spans, deltas, schema, out)?;
+    out.reverse();
+    let mut carry = 0;
+    for (v, n) in out.iter_mut() {
+        *v = -*v;
+        std::mem::swap(n, &mut carry);
     }
-    assert_eq!(last_bucket_idx, deltas.len());
-    let mut bucket_sum: i64 = deltas.iter().sum();
-
-    let mut out = Vec::with_capacity(deltas.len() + spans.len());
+    Ok(())
+}
 
-    for span in spans.iter().rev() {
-        let end_bucket_idx = last_bucket_idx;
-        let start_bucket_idx = end_bucket_idx - span.length() as usize;
-        last_bucket_idx = start_bucket_idx;
+pub fn parse_histogram(data: &[u8]) -> Result<Histogram, protobuf::Error> {
+    let mut h = Histogram::new();
+    h.merge_from_bytes(data)?;
+    Ok(h)
+}
 
-        let end_schema_idx = last_schema_idx;
-        let start_schema_idx = end_schema_idx - span.length() as i32;
-        last_schema_idx = end_schema_idx - span.offset();
+/// Whether `h` is a native histogram with integer counts, which is what
+/// [histogram_to_ecdf] expects.
+pub fn is_native(h: &Histogram) -> bool {
+    h.bucket.is_empty() && h.positive_count.is_empty() && h.negative_count.is_empty()
+}
 
-        out.push((-get_bound(end_schema_idx, schema), 0));
+/// Converts a native histogram, using `positive` and `negative` as scratch space.
+pub(crate) fn convert(
+    h: &Histogram,
+    positive: &mut Vec<(f64, usize)>,
+    negative: &mut Vec<(f64, usize)>,
+) -> Result<InterpolatedECDF<f64>, HistogramError> {
+    positive_counts(&h.positive_span, &h.positive_delta, h.schema(), positive)?;
+    negative_counts(&h.negative_span, &h.negative_delta, h.schema(), negative)?;
+    let zero_threshold = h.zero_threshold();
+    let zero_count = h.zero_count() as usize;
 
-        for (buckets_idx, schema_idx) in (start_bucket_idx..end_bucket_idx)
-            .rev()
-            .zip((start_schema_idx..end_schema_idx).rev())
-        {
-            out.push((-get_bound(schema_idx, schema), bucket_sum as usize));
-            bucket_sum -= deltas[buckets_idx];
+    // Observations within the zero threshold are counted by the zero bucket,
+    // so clip the innermost buckets on either side where they overlap it.
+    if let Some(first_neg) = negative.last_mut() {
+        if first_neg.0 > -zero_threshold {
+            first_neg.0 = -zero_threshold;
         }
     }
-    out
-}
+    if let Some(first_pos) = positive.first_mut() {
+        if first_pos.0 < zero_threshold {
+            first_pos.0 = zero_threshold;
+        }
+    }
+    // Spread the zero bucket across its whole width, like any other bucket.
+    let zero_lower = if zero_count > 0 && zero_threshold > 0.0 {
+        Some((-zero_threshold, 0))
+    } else {
+        None
+    };
 
-pub fn parse_histogram(data: &[u8]) -> Result<Histogram, protobuf::Error> {
-    let mut h = Histogram::new();
-    h.merge_from_bytes(data)?;
-    Ok(h)
+    Ok(ECDF::from_counts(
+        negative
+            .drain(..)
+            .chain(zero_lower)
+            .chain(std::iter::once((zero_threshold, zero_count)))
+            .chain(positive.drain(..)),
+    )
+    .interpolate())
 }
 
+/// Converts a native histogram to an ECDF.
+///
+/// This panics if the histogram isn't native or its spans describe more
+/// buckets than it has counts for. Use [parse_and_convert_batch] to get
+/// these as errors instead.
 pub fn histogram_to_ecdf(h: &Histogram) -> InterpolatedECDF<f64> {
     // Sanity check the deserialized histogram.
     assert!(h.bucket.is_empty());
     assert!(h.positive_count.is_empty());
     assert!(h.negative_count.is_empty());
 
-    let positive_counts = positive_counts(&h.positive_span, &h.positive_delta, h.schema());
-    let mut negative_counts = negative_counts(&h.negative_span, &h.negative_delta, h.schema());
-    let zero_count = (h.zero_threshold(), h.zero_count() as usize);
-
-    // Adjust the bounds of the last negative bucket to avoid overlap with the zero bucket.
-    if !negative_counts.is_empty() {
-        let first_neg = negative_counts.len() - 1;
-        if negative_counts[first_neg].0 < -h.zero_threshold() {
-            // We need to adjust the bounds of the last negative bucket because it overlaps with the zero bucket.
-            let count = negative_counts[first_neg].1;
-            negative_counts[first_neg] = (-h.zero_threshold(), count);
+    convert(h, &mut Vec::new(), &mut Vec::new()).unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn cumulative_count(b: &Bucket) -> usize {
+    if b.cumulative_count_float() > 0.0 {
+        b.cumulative_count_float().round() as usize
+    } else {
+        b.cumulative_count() as usize
+    }
+}
+
+/// Converts a classic histogram, with fixed cumulative buckets, to an ECDF.
+///
+/// Classic buckets have no lower bound, so like Prometheus we assume the
+/// lowest bucket starts at zero if its upper bound is positive. Observations
+/// in the `+Inf` bucket are attributed to the highest finite bound.
+pub fn classic_histogram_to_ecdf(h: &Histogram) -> InterpolatedECDF<f64> {
+    let mut buckets: Vec<(f64, usize)> = h
+        .bucket
+        .iter()
+        .map(|b| (b.upper_bound(), cumulative_count(b)))
+        .collect();
+    buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut counts = Vec::with_capacity(buckets.len() + 1);
+    let mut last = 0;
+    let mut overflow = 0;
+    for (bound, cumulative) in buckets {
+        let count = cumulative.saturating_sub(last);
+        last = last.max(cumulative);
+        if !bound.is_finite() {
+            overflow += count;
+            continue;
         }
+        if counts.is_empty() && bound > 0.0 {
+            counts.push((0.0, 0));
+        }
+        counts.push((bound, count));
+    }
+    // The +Inf bucket is optional, in which case it's implied by the total.
+    let total = if h.sample_count_float() > 0.0 {
+        h.sample_count_float().round() as usize
+    } else {
+        h.sample_count() as usize
+    };
+    overflow += total.saturating_sub(last);
+    if let Some(highest) = counts.last_mut() {
+        highest.1 += overflow;
     }
 
-    let mut ecdf = ECDF::default();
-    ecdf.merge_sorted(
-        negative_counts
-            .into_iter()
-            .chain(std::iter::once(zero_count))
-            .chain(positive_counts.into_iter()),
+    ECDF::from_counts(counts).interpolate()
+}
+
+/// The index of the native bucket containing `v`, which must be positive.
+fn bucket_index(v: f64, schema: i32) -> i32 {
+    // Estimate, then correct for rounding errors at the boundaries.
+    let mut idx = (v.log2() * 2f64.powi(schema)).ceil() as i32;
+    while get_bound(idx - 1, schema) >= v {
+        idx -= 1;
+    }
+    while get_bound(idx, schema) < v {
+        idx += 1;
+    }
+    idx
+}
+
+/// Packs the non-empty buckets, given in increasing index order, into spans.
+fn pack_buckets(
+    buckets: impl Iterator<Item = (i32, i64)>,
+    spans: &mut Vec<BucketSpan>,
+    deltas: &mut Vec<i64>,
+) {
+    let mut next_idx = None;
+    let mut last_count = 0;
+    for (idx, count) in buckets.filter(|&(_, count)| count != 0) {
+        if next_idx == Some(idx) {
+            let span = spans.last_mut().unwrap();
+            span.set_length(span.length() + 1);
+        } else {
+            let mut span = BucketSpan::new();
+            span.set_offset(idx - next_idx.unwrap_or(0));
+            span.set_length(1);
+            spans.push(span);
+        }
+        deltas.push(count - last_count);
+        last_count = count;
+        next_idx = Some(idx + 1);
+    }
+}
+
+/// Merges the buckets described by `spans` and `deltas` into those of a
+/// schema `shift` levels coarser.
+fn downscale_buckets(
+    spans: &[BucketSpan],
+    deltas: &[i64],
+    shift: u32,
+    out_spans: &mut Vec<BucketSpan>,
+    out_deltas: &mut Vec<i64>,
+) {
+    let mut merged: Vec<(i32, i64)> = Vec::with_capacity(deltas.len());
+    let mut deltas = deltas.iter();
+    let mut idx: i32 = 0;
+    let mut count: i64 = 0;
+    for span in spans {
+        idx += span.offset();
+        for _ in 0..span.length() {
+            count += deltas.next().expect("delta for each bucket");
+            // Each coarse bucket covers 2^shift fine buckets, and its
+            // upper bound is that of the last one.
+            let target = ((idx - 1) >> shift) + 1;
+            match merged.last_mut() {
+                Some(last) if last.0 == target => last.1 += count,
+                _ => merged.push((target, count)),
+            }
+            idx += 1;
+        }
+    }
+    pack_buckets(merged.into_iter(), out_spans, out_deltas);
+}
+
+/// Converts a native histogram to a coarser bucket schema, like Prometheus
+/// does when combining histograms of different resolutions.
+///
+/// Adjacent buckets are merged, so no observations are lost, and the zero
+/// bucket is unchanged. Histograms whose schema is already at or below
+/// `target_schema` are returned as is, since buckets can't be split.
+pub fn downscale(h: &Histogram, target_schema: i32) -> Histogram {
+    let mut out = h.clone();
+    if h.schema() <= target_schema {
+        return out;
+    }
+    let shift = (h.schema() - target_schema) as u32;
+    out.set_schema(target_schema);
+    out.positive_span.clear();
+    out.positive_delta.clear();
+    out.negative_span.clear();
+    out.negative_delta.clear();
+    downscale_buckets(
+        &h.positive_span,
+        &h.positive_delta,
+        shift,
+        &mut out.positive_span,
+        &mut out.positive_delta,
     );
-    ecdf.interpolate()
+    downscale_buckets(
+        &h.negative_span,
+        &h.negative_delta,
+        shift,
+        &mut out.negative_span,
+        &mut out.negative_delta,
+    );
+    out
+}
+
+/// Evaluates the piecewise linear CDF through `points` at `x`.
+fn cdf(points: &[(f64, f64)], x: f64) -> f64 {
+    let i = points.partition_point(|&(v, _)| v <= x);
+    if i == 0 {
+        return 0.0;
+    }
+    if i == points.len() {
+        return 1.0;
+    }
+    let (v0, p0) = points[i - 1];
+    let (v1, p1) = points[i];
+    p0 + (p1 - p0) * (x - v0) / (v1 - v0)
+}
+
+/// Converts an ECDF into a native histogram with the given bucket schema.
+///
+/// Samples are spread across buckets the same way the ECDF interpolates
+/// between them, and cumulative counts are rounded so the total is kept.
+/// The zero bucket is made as wide as possible without swallowing any of the
+/// ECDF's points, other than those at zero.
+pub fn ecdf_to_histogram(ecdf: &InterpolatedECDF<f64>, schema: i32) -> Histogram {
+    let mut h = Histogram::new();
+    h.set_schema(schema);
+    let points: Vec<(f64, f64)> = ecdf.point_iter().collect();
+    let (lo, hi) = match (points.first(), points.last()) {
+        (Some(&(lo, _)), Some(&(hi, _))) => (lo, hi),
+        _ => return h,
+    };
+    let total = ecdf.len();
+    let rank = |x: f64| (total * cdf(&points, x)).round() as i64;
+
+    let smallest = points
+        .iter()
+        .map(|&(v, _)| v.abs())
+        .filter(|&v| v > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    // The index of the bucket whose upper bound is the zero threshold.
+    let zero_idx = if smallest.is_finite() {
+        bucket_index(smallest, schema) - 1
+    } else {
+        i32::MIN
+    };
+    let zero_threshold = if smallest.is_finite() {
+        get_bound(zero_idx, schema)
+    } else {
+        0.0
+    };
+    h.set_zero_threshold(zero_threshold);
+    if !smallest.is_finite() {
+        // Every sample is zero, which the ranks below can't tell apart.
+        h.set_zero_count(total.round() as u64);
+        return h;
+    }
+    h.set_zero_count((rank(zero_threshold) - rank(-zero_threshold)) as u64);
+
+    if hi > zero_threshold {
+        let first = if lo > zero_threshold {
+            bucket_index(lo, schema)
+        } else {
+            zero_idx + 1
+        };
+        let last = bucket_index(hi, schema);
+        let buckets = (first..=last).map(|idx| {
+            let count = rank(get_bound(idx, schema)) - rank(get_bound(idx - 1, schema));
+            (idx, count)
+        });
+        pack_buckets(buckets, &mut h.positive_span, &mut h.positive_delta);
+    }
+    if lo < -zero_threshold {
+        let first = if hi < -zero_threshold {
+            bucket_index(-hi, schema)
+        } else {
+            zero_idx + 1
+        };
+        let last = bucket_index(-lo, schema);
+        let buckets = (first..=last).map(|idx| {
+            // The outermost bucket also takes any sample on its lower bound.
+            let below = if idx == last {
+                0
+            } else {
+                rank(-get_bound(idx, schema))
+            };
+            (idx, rank(-get_bound(idx - 1, schema)) - below)
+        });
+        pack_buckets(buckets, &mut h.negative_span, &mut h.negative_delta);
+    }
+    h
+}
+
+/// Parses and converts a batch of serialized native histograms.
+///
+/// This gives the same results as calling [parse_histogram] and
+/// [histogram_to_ecdf] on each blob, but reuses intermediate buffers across
+/// conversions. Blobs are converted independently of each other, so large
+/// batches can be split into chunks and converted in parallel.
+pub fn parse_and_convert_batch(
+    blobs: &[&[u8]],
+) -> Vec<Result<InterpolatedECDF<f64>, HistogramError>> {
+    let mut h = Histogram::new();
+    let mut positive = Vec::new();
+    let mut negative = Vec::new();
+    blobs
+        .iter()
+        .map(|data| {
+            h.clear();
+            h.merge_from_bytes(data)?;
+            if !is_native(&h) {
+                return Err(HistogramError::Unsupported);
+            }
+            convert(&h, &mut positive, &mut negative)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(offset: i32, length: u32) -> BucketSpan {
+        let mut s = BucketSpan::new();
+        s.set_offset(offset);
+        s.set_length(length);
+        s
+    }
+
+    fn native_histogram(schema: i32, zero_count: u64, deltas: Vec<i64>) -> Vec<u8> {
+        let mut h = Histogram::new();
+        h.set_schema(schema);
+        h.set_zero_threshold(1e-3);
+        h.set_zero_count(zero_count);
+        h.positive_span.push(span(0, 2));
+        h.positive_span.push(span(1, (deltas.len() - 2) as u32));
+        h.positive_delta = deltas.clone();
+        h.negative_span.push(span(-1, 1));
+        h.negative_delta = vec![1];
+        h.write_to_bytes().unwrap()
+    }
+
+    #[test]
+    fn batch_matches_single() {
+        let blobs = vec![
+            native_histogram(0, 1, vec![1, 2, -1, 0]),
+            native_histogram(2, 0, vec![3, -2, 4]),
+            native_histogram(1, 5, vec![1, 1, 1, 1, -3]),
+        ];
+        let refs: Vec<&[u8]> = blobs.iter().map(|b| b.as_slice()).collect();
+        let batch = parse_and_convert_batch(&refs);
+        assert_eq!(batch.len(), blobs.len());
+        for (data, got) in blobs.iter().zip(batch.into_iter()) {
+            let got = got.unwrap();
+            let want = histogram_to_ecdf(&parse_histogram(data).unwrap());
+            assert_eq!(got.len(), want.len());
+            assert_eq!(got.area_difference(&want), 0.0);
+            assert_eq!(
+                got.percentiles(&[0.0, 0.1, 0.5, 0.9, 1.0]),
+                want.percentiles(&[0.0, 0.1, 0.5, 0.9, 1.0])
+            );
+        }
+    }
+
+    fn bucket(upper_bound: f64, cumulative_count: u64) -> Bucket {
+        let mut b = Bucket::new();
+        b.set_upper_bound(upper_bound);
+        b.set_cumulative_count(cumulative_count);
+        b
+    }
+
+    #[test]
+    fn classic_histogram() {
+        let mut h = Histogram::new();
+        h.set_sample_count(10);
+        h.bucket.push(bucket(0.5, 5));
+        h.bucket.push(bucket(0.1, 2));
+        h.bucket.push(bucket(1.0, 9));
+        h.bucket.push(bucket(f64::INFINITY, 10));
+        let ecdf = classic_histogram_to_ecdf(&h);
+        assert_eq!(ecdf.len(), 10.0);
+        assert_eq!(ecdf.quantile(0.2), 0.1);
+        assert!((ecdf.quantile(0.35) - 0.3).abs() < 1e-9);
+        assert!((ecdf.quantile(0.9) - 0.9).abs() < 1e-9);
+
+        // Without the +Inf bucket the overflow comes from the sample count.
+        h.bucket.pop();
+        let implied = classic_histogram_to_ecdf(&h);
+        assert_eq!(implied.len(), 10.0);
+        assert_eq!(implied.area_difference(&ecdf), 0.0);
+    }
+
+    #[test]
+    fn negative_buckets() {
+        let mut h = Histogram::new();
+        h.set_schema(0);
+        h.set_zero_threshold(1.0);
+        h.negative_span.push(span(1, 2));
+        h.negative_span.push(span(1, 1));
+        h.negative_delta = vec![1, 1, 1];
+        let ecdf = histogram_to_ecdf(&h);
+        // Buckets 1 and 2 cover [-4, -1), and bucket 4 covers [-16, -8).
+        assert_eq!(ecdf.len(), 6.0);
+        assert_eq!(ecdf.fraction(-8.0), 0.5);
+        assert_eq!(ecdf.fraction(-4.0), 0.5);
+        assert!((ecdf.fraction(-2.0) - 5.0 / 6.0).abs() < 1e-9);
+        assert_eq!(ecdf.fraction(-1.0), 1.0);
+    }
+
+    /// Whether both the values and probabilities of `ecdf` never decrease.
+    fn is_monotonic(ecdf: &InterpolatedECDF<f64>) -> bool {
+        let points: Vec<(f64, f64)> = ecdf.point_iter().collect();
+        points
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1)
+    }
+
+    #[test]
+    fn zero_bucket_only() {
+        let mut h = Histogram::new();
+        h.set_schema(0);
+        h.set_zero_threshold(1e-3);
+        h.set_zero_count(4);
+        let ecdf = histogram_to_ecdf(&h);
+        assert!(is_monotonic(&ecdf));
+        assert_eq!(ecdf.len(), 4.0);
+        assert_eq!(ecdf.fraction(-1e-3), 0.0);
+        assert_eq!(ecdf.fraction(0.0), 0.5);
+        assert_eq!(ecdf.fraction(1e-3), 1.0);
+
+        // All the observations were exactly zero.
+        h.set_zero_threshold(0.0);
+        let ecdf = histogram_to_ecdf(&h);
+        assert!(is_monotonic(&ecdf));
+        assert_eq!(ecdf.len(), 4.0);
+        assert_eq!(ecdf.point_iter().collect::<Vec<_>>(), vec![(0.0, 1.0)]);
+    }
+
+    #[test]
+    fn zero_threshold_at_bucket_edge() {
+        let mut h = Histogram::new();
+        h.set_schema(0);
+        h.set_zero_threshold(1.0);
+        h.set_zero_count(4);
+        // Buckets 1 and 2 cover (1, 4], and bucket 1 covers [-2, -1).
+        h.positive_span.push(span(1, 2));
+        h.positive_delta = vec![2, 1];
+        h.negative_span.push(span(1, 1));
+        h.negative_delta = vec![1];
+        let ecdf = histogram_to_ecdf(&h);
+        assert!(is_monotonic(&ecdf));
+        assert_eq!(ecdf.len(), 10.0);
+        assert_eq!(ecdf.fraction(-1.0), 0.1);
+        assert_eq!(ecdf.fraction(1.0), 0.5);
+        assert_eq!(ecdf.fraction(2.0), 0.7);
+
+        // A wider zero bucket takes over the overlapping parts of the
+        // innermost buckets.
+        h.set_zero_threshold(1.5);
+        let ecdf = histogram_to_ecdf(&h);
+        assert!(is_monotonic(&ecdf));
+        assert_eq!(ecdf.len(), 10.0);
+        assert_eq!(ecdf.fraction(-1.5), 0.1);
+        assert_eq!(ecdf.fraction(1.5), 0.5);
+        assert_eq!(ecdf.fraction(2.0), 0.7);
+
+        // Without a zero count, the threshold still sits between the buckets.
+        h.set_zero_threshold(0.0);
+        h.set_zero_count(0);
+        let ecdf = histogram_to_ecdf(&h);
+        assert!(is_monotonic(&ecdf));
+        assert_eq!(ecdf.len(), 6.0);
+        assert_eq!(ecdf.fraction(0.5), 1.0 / 6.0);
+    }
+
+    #[test]
+    fn downscale_schema() {
+        let mut h = Histogram::new();
+        h.set_schema(3);
+        h.set_zero_threshold(1e-3);
+        h.set_zero_count(2);
+        // Counts 1 through 5 in buckets 0 to 4, and 6 through 9 in 8 to 11.
+        h.positive_span.push(span(0, 5));
+        h.positive_span.push(span(3, 4));
+        h.positive_delta = vec![1; 9];
+        h.negative_span.push(span(-2, 3));
+        h.negative_delta = vec![1, 0, 0];
+
+        let down = downscale(&h, 1);
+        assert_eq!(down.schema(), 1);
+        assert_eq!(down.zero_threshold(), h.zero_threshold());
+        assert_eq!(down.zero_count(), h.zero_count());
+        // Every four buckets are merged into one, keyed by their upper bound.
+        assert_eq!(down.positive_span, vec![span(0, 4)]);
+        assert_eq!(down.positive_delta, vec![1, 13, -8, 18]);
+        assert_eq!(down.negative_span, vec![span(0, 1)]);
+        assert_eq!(down.negative_delta, vec![3]);
+
+        let fine = histogram_to_ecdf(&h);
+        let coarse = histogram_to_ecdf(&down);
+        assert_eq!(fine.len(), 50.0);
+        assert_eq!(coarse.len(), fine.len());
+        // The coarse bucket bounds are shared, so counts below them agree.
+        for idx in -1..=3 {
+            for v in [get_bound(idx, 1), -get_bound(idx, 1)] {
+                assert!(
+                    (coarse.fraction(v) - fine.fraction(v)).abs() < 1e-12,
+                    "{}: {} vs {}",
+                    v,
+                    coarse.fraction(v),
+                    fine.fraction(v)
+                );
+            }
+        }
+
+        // Buckets can't be split, so finer schemas are left alone.
+        assert_eq!(downscale(&h, 3), h);
+        assert_eq!(downscale(&h, 5), h);
+    }
+
+    #[test]
+    fn round_trip() {
+        let samples: Vec<f64> = (1..=200).map(|i| (i - 50) as f64 * 0.25).collect();
+        let ecdf = ECDF::from(samples).interpolate();
+        for schema in [2, -1] {
+            let h = ecdf_to_histogram(&ecdf, schema);
+            assert_eq!(h.schema(), schema);
+            let got = histogram_to_ecdf(&h);
+            assert_eq!(got.len(), ecdf.len());
+
+            // Quantiles should land in the same bucket, give or take.
+            let growth = 2f64.powf(2f64.powi(-schema));
+            for q in (1..20).map(|i| i as f64 / 20.0) {
+                let want = ecdf.quantile(q);
+                let tolerance = want.abs() * (growth - 1.0) + h.zero_threshold();
+                assert!(
+                    (got.quantile(q) - want).abs() <= tolerance,
+                    "schema {} q {}: {} vs {}",
+                    schema,
+                    q,
+                    got.quantile(q),
+                    want
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn all_zeros() {
+        let ecdf = ECDF::from(vec![0.0; 5]).interpolate();
+        let h = ecdf_to_histogram(&ecdf, 3);
+        assert_eq!(h.zero_threshold(), 0.0);
+        assert_eq!(h.zero_count(), 5);
+        assert!(h.positive_span.is_empty());
+        assert!(h.negative_span.is_empty());
+        assert_eq!(histogram_to_ecdf(&h).len(), 5.0);
+    }
+
+    #[test]
+    fn batch_errors() {
+        let mut classic = Histogram::new();
+        classic.bucket.push(Default::default());
+        let classic = classic.write_to_bytes().unwrap();
+        let valid = native_histogram(0, 1, vec![1, 2, -1]);
+        let garbage: &[u8] = &[0xff, 0xff, 0xff];
+        // The span claims four buckets, but there are only three counts.
+        let mut short = Histogram::new();
+        short.set_schema(0);
+        short.positive_span.push(span(1, 4));
+        short.positive_delta = vec![1, 2, -1];
+        let short = short.write_to_bytes().unwrap();
+
+        let batch = parse_and_convert_batch(&[garbage, &classic, &valid, &short]);
+        assert!(matches!(batch[0], Err(HistogramError::Decode(_))));
+        assert!(matches!(batch[1], Err(HistogramError::Unsupported)));
+        assert!(batch[2].is_ok());
+        assert!(matches!(batch[3], Err(HistogramError::MissingBuckets)));
+    }
 }
@@ -0,0 +1,284 @@
+// Turns a stream of cumulative histograms into per-interval ECDFs.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::protos::metrics::{BucketSpan, Histogram};
+use crate::{convert, is_native, pack_buckets, HistogramError};
+
+use mumble::ecdf::InterpolatedECDF;
+use std::cmp::Ordering;
+
+/// Expands spans and deltas into (index, count) pairs, in index order.
+fn bucket_counts(spans: &[BucketSpan], deltas: &[i64]) -> Vec<(i32, i64)> {
+    let mut out = Vec::with_capacity(deltas.len());
+    let mut idx = 0;
+    let mut count = 0;
+    let mut deltas = deltas.iter();
+    for span in spans {
+        idx += span.offset();
+        for _ in 0..span.length() {
+            count += deltas.next().copied().unwrap_or_default();
+            out.push((idx, count));
+            idx += 1;
+        }
+    }
+    out
+}
+
+/// Checks that `spans` don't describe more buckets than there are `deltas`.
+///
+/// [bucket_counts] treats missing counts as zero, so this is checked before
+/// a histogram is kept to compare with the next one.
+fn check_spans(spans: &[BucketSpan], deltas: &[i64]) -> Result<(), HistogramError> {
+    let buckets: u64 = spans.iter().map(|s| s.length() as u64).sum();
+    if buckets > deltas.len() as u64 {
+        return Err(HistogramError::MissingBuckets);
+    }
+    Ok(())
+}
+
+/// Subtracts the counts in `prev` from those in `curr`, bucket by bucket.
+fn subtract_buckets(curr: &[(i32, i64)], prev: &[(i32, i64)]) -> Vec<(i32, i64)> {
+    let mut out = Vec::with_capacity(curr.len());
+    let mut prev = prev.iter().peekable();
+    for &(idx, count) in curr {
+        let mut before = 0;
+        while let Some(&&(p_idx, p_count)) = prev.peek() {
+            match p_idx.cmp(&idx) {
+                Ordering::Less => {
+                    prev.next();
+                }
+                Ordering::Equal => {
+                    before = p_count;
+                    prev.next();
+                    break;
+                }
+                Ordering::Greater => break,
+            }
+        }
+        out.push((idx, count - before));
+    }
+    out
+}
+
+/// Whether any bucket in `curr` has fewer observations than in `prev`.
+fn any_decrease(curr: &[(i32, i64)], prev: &[(i32, i64)]) -> bool {
+    // Buckets missing from `curr` have a count of zero.
+    let mut curr = curr.iter().peekable();
+    for &(idx, count) in prev.iter().filter(|&&(_, count)| count > 0) {
+        while curr.next_if(|&&(c_idx, _)| c_idx < idx).is_some() {}
+        match curr.peek() {
+            Some(&&(c_idx, c_count)) if c_idx == idx && c_count >= count => {}
+            _ => return true,
+        }
+    }
+    false
+}
+
+fn sample_count(h: &Histogram) -> f64 {
+    if h.sample_count_float() > 0.0 {
+        h.sample_count_float()
+    } else {
+        h.sample_count() as f64
+    }
+}
+
+/// Whether `curr` was reset since `prev` was observed.
+///
+/// A histogram has been reset if it was recreated, if its bucket layout
+/// changed, or if any of its counts went down.
+pub fn detect_reset(prev: &Histogram, curr: &Histogram) -> bool {
+    if curr.created_timestamp.is_some() && curr.created_timestamp != prev.created_timestamp {
+        return true;
+    }
+    if curr.schema() != prev.schema() || curr.zero_threshold() != prev.zero_threshold() {
+        return true;
+    }
+    if sample_count(curr) < sample_count(prev) || curr.zero_count() < prev.zero_count() {
+        return true;
+    }
+    any_decrease(
+        &bucket_counts(&curr.positive_span, &curr.positive_delta),
+        &bucket_counts(&prev.positive_span, &prev.positive_delta),
+    ) || any_decrease(
+        &bucket_counts(&curr.negative_span, &curr.negative_delta),
+        &bucket_counts(&prev.negative_span, &prev.negative_delta),
+    )
+}
+
+/// The observations made between `prev` and `curr`, as a native histogram.
+fn difference(curr: &Histogram, prev: &Histogram) -> Histogram {
+    let mut h = Histogram::new();
+    h.set_schema(curr.schema());
+    h.set_zero_threshold(curr.zero_threshold());
+    h.set_zero_count(curr.zero_count() - prev.zero_count());
+    h.set_sample_count(curr.sample_count().saturating_sub(prev.sample_count()));
+    h.set_sample_sum(curr.sample_sum() - prev.sample_sum());
+    pack_buckets(
+        subtract_buckets(
+            &bucket_counts(&curr.positive_span, &curr.positive_delta),
+            &bucket_counts(&prev.positive_span, &prev.positive_delta),
+        )
+        .into_iter(),
+        &mut h.positive_span,
+        &mut h.positive_delta,
+    );
+    pack_buckets(
+        subtract_buckets(
+            &bucket_counts(&curr.negative_span, &curr.negative_delta),
+            &bucket_counts(&prev.negative_span, &prev.negative_delta),
+        )
+        .into_iter(),
+        &mut h.negative_span,
+        &mut h.negative_delta,
+    );
+    h
+}
+
+/// Converts successive scrapes of a cumulative native histogram into ECDFs
+/// of the observations made between each scrape.
+///
+/// If the histogram is reset, e.g. because its target restarted, the
+/// baseline is zeroed so that the next interval covers everything observed
+/// since the reset.
+#[derive(Default)]
+pub struct HistogramAccumulator {
+    last: Option<Histogram>,
+}
+
+impl HistogramAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next scrape of the histogram.
+    ///
+    /// Returns the ECDF of observations made since the previous scrape, or
+    /// None if this is the first one. Classic histograms are rejected with
+    /// [HistogramError::Unsupported], and those with spans that describe
+    /// more buckets than they have counts for with
+    /// [HistogramError::MissingBuckets], leaving the previous scrape in place.
+    pub fn push(&mut self, h: Histogram) -> Result<Option<InterpolatedECDF<f64>>, HistogramError> {
+        if !is_native(&h) {
+            return Err(HistogramError::Unsupported);
+        }
+        check_spans(&h.positive_span, &h.positive_delta)?;
+        check_spans(&h.negative_span, &h.negative_delta)?;
+        let ecdf = |h: &Histogram| convert(h, &mut Vec::new(), &mut Vec::new());
+        let interval = match &self.last {
+            None => None,
+            Some(prev) if detect_reset(prev, &h) => Some(ecdf(&h)?),
+            Some(prev) => Some(ecdf(&difference(&h, prev))?),
+        };
+        self.last = Some(h);
+        Ok(interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::metrics::Bucket;
+    use protobuf::well_known_types::timestamp::Timestamp;
+
+    /// A schema 0 histogram with a single span of buckets starting at 1.
+    fn native(zero_count: u64, counts: &[i64]) -> Histogram {
+        let mut h = Histogram::new();
+        h.set_zero_threshold(1e-3);
+        h.set_zero_count(zero_count);
+        let mut span = BucketSpan::new();
+        span.set_offset(1);
+        span.set_length(counts.len() as u32);
+        h.positive_span.push(span);
+        let mut last = 0;
+        for &count in counts {
+            h.positive_delta.push(count - last);
+            last = count;
+        }
+        h.set_sample_count(zero_count + counts.iter().sum::<i64>() as u64);
+        h
+    }
+
+    #[test]
+    fn no_reset() {
+        let prev = native(1, &[2, 3]);
+        let curr = native(1, &[4, 3, 1]);
+        assert!(!detect_reset(&prev, &curr));
+
+        let mut acc = HistogramAccumulator::new();
+        assert!(acc.push(prev).unwrap().is_none());
+        let interval = acc.push(curr).unwrap().unwrap();
+        // Two new observations in (1, 2], and one in (4, 8].
+        assert_eq!(interval.len(), 3.0);
+        assert_eq!(interval.fraction(2.0), 2.0 / 3.0);
+        assert_eq!(interval.fraction(4.0), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn reset() {
+        let prev = native(1, &[2, 3]);
+        let curr = native(1, &[1, 3]);
+        assert!(detect_reset(&prev, &curr));
+        assert!(detect_reset(&prev, &native(0, &[2, 3])));
+        assert!(detect_reset(&prev, &native(1, &[2])));
+
+        let mut acc = HistogramAccumulator::new();
+        acc.push(prev.clone()).unwrap();
+        // After a reset the whole histogram is new.
+        assert_eq!(acc.push(curr).unwrap().unwrap().len(), 5.0);
+
+        let mut restarted = prev.clone();
+        let mut created = Timestamp::new();
+        created.seconds = 1_700_000_000;
+        restarted.created_timestamp = Some(created).into();
+        assert!(detect_reset(&prev, &restarted));
+        assert!(!detect_reset(&restarted, &restarted));
+    }
+
+    #[test]
+    fn classic() {
+        let mut classic = Histogram::new();
+        let mut bucket = Bucket::new();
+        bucket.set_upper_bound(1.0);
+        bucket.set_cumulative_count(2);
+        classic.bucket.push(bucket);
+        classic.set_sample_count(2);
+
+        let mut acc = HistogramAccumulator::new();
+        assert!(matches!(
+            acc.push(classic.clone()),
+            Err(HistogramError::Unsupported)
+        ));
+        // A rejected scrape doesn't replace the baseline.
+        acc.push(native(1, &[2, 3])).unwrap();
+        assert!(acc.push(classic).is_err());
+        assert_eq!(acc.push(native(1, &[4, 3])).unwrap().unwrap().len(), 2.0);
+    }
+
+    #[test]
+    fn missing_buckets() {
+        let mut short = native(1, &[2, 3]);
+        short.positive_delta.pop();
+
+        let mut acc = HistogramAccumulator::new();
+        assert!(matches!(
+            acc.push(short.clone()),
+            Err(HistogramError::MissingBuckets)
+        ));
+        acc.push(native(1, &[2, 3])).unwrap();
+        assert!(acc.push(short).is_err());
+        assert_eq!(acc.push(native(1, &[4, 3])).unwrap().unwrap().len(), 2.0);
+    }
+}
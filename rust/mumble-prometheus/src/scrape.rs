@@ -0,0 +1,277 @@
+// Fetches histograms from a Prometheus metrics endpoint.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::protos::metrics::{Histogram, MetricFamily, MetricType};
+use crate::text::{parse_text_histograms, series_key, ParseError};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::client::conn::http1;
+use hyper::header::{ACCEPT, CONTENT_TYPE, HOST};
+use hyper::{Request, StatusCode, Uri};
+use protobuf::CodedInputStream;
+use std::fmt::{self, Display};
+use tokio::net::TcpStream;
+
+/// The length-delimited protobuf format, one `MetricFamily` after another.
+pub const PROTOBUF_CONTENT_TYPE: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+/// Prefer protobuf, which is the only format that carries native histograms.
+const ACCEPT_HEADER: &str = "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3";
+
+/// Errors that can occur when scraping a metrics endpoint.
+#[derive(Debug)]
+pub enum ScrapeError {
+    /// The URL can't be scraped, e.g. because it isn't plain HTTP.
+    InvalidUrl(String),
+    /// The target couldn't be reached.
+    Connect(std::io::Error),
+    /// The HTTP exchange with the target failed.
+    Http(hyper::Error),
+    /// The target responded with something other than 200 OK.
+    Status(StatusCode),
+    /// The response was in a format that can't be parsed.
+    ContentType(String),
+    /// A protobuf response couldn't be decoded.
+    Decode(protobuf::Error),
+    /// A text response couldn't be parsed.
+    Parse(ParseError),
+}
+
+impl Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScrapeError::InvalidUrl(url) => write!(f, "unable to scrape {}", url),
+            ScrapeError::Connect(e) => write!(f, "unable to connect: {}", e),
+            ScrapeError::Http(e) => write!(f, "request failed: {}", e),
+            ScrapeError::Status(status) => write!(f, "unexpected status {}", status),
+            ScrapeError::ContentType(t) => write!(f, "unsupported content type {:?}", t),
+            ScrapeError::Decode(e) => write!(f, "unable to decode metrics: {}", e),
+            ScrapeError::Parse(e) => write!(f, "unable to parse metrics: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScrapeError {}
+
+impl From<hyper::Error> for ScrapeError {
+    fn from(e: hyper::Error) -> Self {
+        ScrapeError::Http(e)
+    }
+}
+
+impl From<protobuf::Error> for ScrapeError {
+    fn from(e: protobuf::Error) -> Self {
+        ScrapeError::Decode(e)
+    }
+}
+
+impl From<ParseError> for ScrapeError {
+    fn from(e: ParseError) -> Self {
+        ScrapeError::Parse(e)
+    }
+}
+
+/// Reads the histograms out of a stream of length-delimited metric families.
+fn parse_delimited(data: &[u8]) -> Result<Vec<(String, Histogram)>, protobuf::Error> {
+    let mut input = CodedInputStream::from_bytes(data);
+    let mut histograms = Vec::new();
+    while !input.eof()? {
+        let mut family: MetricFamily = input.read_message()?;
+        if !matches!(
+            family.type_(),
+            MetricType::HISTOGRAM | MetricType::GAUGE_HISTOGRAM
+        ) {
+            continue;
+        }
+        for mut metric in std::mem::take(&mut family.metric) {
+            let labels: Vec<(String, String)> = metric
+                .label
+                .iter()
+                .map(|l| (l.name().to_string(), l.value().to_string()))
+                .collect();
+            if let Some(h) = metric.histogram.take() {
+                histograms.push((series_key(family.name(), &labels), h));
+            }
+        }
+    }
+    Ok(histograms)
+}
+
+/// Fetches the histograms exposed at `url`.
+///
+/// Only plain HTTP is supported. Protobuf is requested, but text responses
+/// are parsed too. Histograms are keyed the same way as
+/// [parse_text_histograms], and other metric types are skipped.
+///
+/// This must be called from within a Tokio runtime.
+pub async fn scrape(url: &str) -> Result<Vec<(String, Histogram)>, ScrapeError> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|_| ScrapeError::InvalidUrl(url.to_string()))?;
+    if uri.scheme_str().is_some_and(|s| s != "http") {
+        return Err(ScrapeError::InvalidUrl(url.to_string()));
+    }
+    let authority = uri
+        .authority()
+        .ok_or_else(|| ScrapeError::InvalidUrl(url.to_string()))?
+        .clone();
+    let port = authority.port_u16().unwrap_or(80);
+
+    let stream = TcpStream::connect((authority.host(), port))
+        .await
+        .map_err(ScrapeError::Connect)?;
+    let (mut sender, connection) = http1::handshake(stream).await?;
+    tokio::spawn(connection);
+
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let request = Request::get(path)
+        .header(HOST, authority.as_str())
+        .header(ACCEPT, ACCEPT_HEADER)
+        .body(Empty::<Bytes>::new())
+        .map_err(|_| ScrapeError::InvalidUrl(url.to_string()))?;
+    let response = sender.send_request(request).await?;
+    if response.status() != StatusCode::OK {
+        return Err(ScrapeError::Status(response.status()));
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("text/plain")
+        .to_string();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    if content_type.starts_with("application/vnd.google.protobuf") {
+        Ok(parse_delimited(&body)?)
+    } else if content_type.starts_with("text/plain") {
+        Ok(parse_text_histograms(&String::from_utf8_lossy(&body))?)
+    } else {
+        Err(ScrapeError::ContentType(content_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::metrics::{Bucket, LabelPair, Metric};
+    use http_body_util::Full;
+    use hyper::server::conn::http1 as server;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use protobuf::Message;
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+
+    fn payload() -> Vec<u8> {
+        let mut h = Histogram::new();
+        h.set_sample_count(3);
+        let mut b = Bucket::new();
+        b.set_upper_bound(0.5);
+        b.set_cumulative_count(2);
+        h.bucket.push(b);
+
+        let mut label = LabelPair::new();
+        label.set_name("path".into());
+        label.set_value("/a".into());
+        let mut metric = Metric::new();
+        metric.label.push(label);
+        metric.histogram = Some(h).into();
+
+        let mut family = MetricFamily::new();
+        family.set_name("latency_seconds".into());
+        family.set_type(MetricType::HISTOGRAM);
+        family.metric.push(metric);
+
+        let mut counter = MetricFamily::new();
+        counter.set_name("requests_total".into());
+        counter.set_type(MetricType::COUNTER);
+        counter.metric.push(Metric::new());
+
+        let mut data = Vec::new();
+        counter.write_length_delimited_to_vec(&mut data).unwrap();
+        family.write_length_delimited_to_vec(&mut data).unwrap();
+        data
+    }
+
+    /// Serves a single connection, answering every request with `body`.
+    async fn serve(content_type: &'static str, body: Vec<u8>) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |_req| {
+                let body = body.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header(CONTENT_TYPE, content_type)
+                            .body(Full::new(Bytes::from(body)))
+                            .unwrap(),
+                    )
+                }
+            });
+            // The client may hang up early on bad responses.
+            let _ = server::Builder::new()
+                .serve_connection(stream, service)
+                .await;
+        });
+        format!("http://{}/metrics", addr)
+    }
+
+    #[tokio::test]
+    async fn scrape_protobuf() {
+        let url = serve(PROTOBUF_CONTENT_TYPE, payload()).await;
+        let histograms = scrape(&url).await.unwrap();
+        assert_eq!(histograms.len(), 1);
+        let (name, h) = &histograms[0];
+        assert_eq!(name, r#"latency_seconds{path="/a"}"#);
+        assert_eq!(h.sample_count(), 3);
+        assert_eq!(h.bucket[0].cumulative_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn scrape_text() {
+        let text = "# TYPE x histogram\nx_bucket{le=\"+Inf\"} 2\nx_count 2\n";
+        let url = serve("text/plain; version=0.0.4", text.into()).await;
+        let histograms = scrape(&url).await.unwrap();
+        assert_eq!(histograms.len(), 1);
+        assert_eq!(histograms[0].1.sample_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn scrape_errors() {
+        let url = serve(PROTOBUF_CONTENT_TYPE, vec![0xff, 0xff, 0xff]).await;
+        assert!(matches!(scrape(&url).await, Err(ScrapeError::Decode(_))));
+
+        let url = serve("application/json", Vec::new()).await;
+        assert!(matches!(
+            scrape(&url).await,
+            Err(ScrapeError::ContentType(_))
+        ));
+
+        // Nothing is listening on port 1.
+        assert!(matches!(
+            scrape("http://127.0.0.1:1/metrics").await,
+            Err(ScrapeError::Connect(_))
+        ));
+        assert!(matches!(
+            scrape("https://example.com/metrics").await,
+            Err(ScrapeError::InvalidUrl(_))
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+// Parses histograms out of the Prometheus text exposition format.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::protos::metrics::{Bucket, Histogram};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+
+/// A malformed line in a text exposition.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// The line the error was found on, starting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A single sample line, such as `name{label="value"} 1.5 1690000000000`.
+struct Sample {
+    line: usize,
+    name: String,
+    labels: Vec<(String, String)>,
+    value: f64,
+}
+
+/// The samples collected so far for one histogram series.
+#[derive(Default)]
+struct Series {
+    buckets: Vec<(f64, f64)>,
+    sum: Option<f64>,
+    count: Option<f64>,
+}
+
+fn error(line: usize, message: impl Into<String>) -> ParseError {
+    ParseError {
+        line,
+        message: message.into(),
+    }
+}
+
+fn parse_value(s: &str) -> Option<f64> {
+    // Rust accepts "inf" and "NaN" spelled in any case, which covers the
+    // "+Inf", "-Inf" and "NaN" used by the exposition format.
+    s.parse().ok()
+}
+
+/// Parses the label set at the start of `s`, which begins after the `{`.
+///
+/// Returns the labels and whatever follows the closing `}`.
+fn parse_labels(s: &str) -> Result<(Vec<(String, String)>, &str), String> {
+    let mut labels = Vec::new();
+    let mut rest = s.trim_start();
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            return Ok((labels, after));
+        }
+        let eq = rest.find('=').ok_or("expected '=' in label set")?;
+        let name = rest[..eq].trim().to_string();
+        rest = rest[eq + 1..]
+            .trim_start()
+            .strip_prefix('"')
+            .ok_or("expected quoted label value")?;
+
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated label value".into()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err("unterminated label value".into()),
+            }
+        };
+        labels.push((name, value));
+
+        rest = rest[end + 1..].trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.starts_with('}') {
+            return Err("expected ',' or '}' after label".into());
+        }
+    }
+}
+
+fn parse_sample(line: usize, s: &str) -> Result<Sample, ParseError> {
+    let name_end = s
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or_else(|| error(line, "missing value"))?;
+    let name = s[..name_end].to_string();
+    let (labels, rest) = match s[name_end..].strip_prefix('{') {
+        Some(after) => parse_labels(after).map_err(|message| error(line, message))?,
+        None => (Vec::new(), &s[name_end..]),
+    };
+    // A timestamp may follow the value, but it isn't needed here.
+    let value = rest
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| error(line, "missing value"))?;
+    let value =
+        parse_value(value).ok_or_else(|| error(line, format!("invalid value {value:?}")))?;
+    Ok(Sample {
+        line,
+        name,
+        labels,
+        value,
+    })
+}
+
+/// Identifies a series by its name and labels, leaving out `le`.
+pub(crate) fn series_key(family: &str, labels: &[(String, String)]) -> String {
+    let mut labels: Vec<_> = labels.iter().filter(|(name, _)| name != "le").collect();
+    if labels.is_empty() {
+        return family.to_string();
+    }
+    labels.sort();
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{name}={value:?}"))
+        .collect();
+    format!("{}{{{}}}", family, labels.join(","))
+}
+
+fn to_histogram(series: Series) -> Histogram {
+    let mut h = Histogram::new();
+    let mut buckets = series.buckets;
+    buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut count = series.count;
+    for (upper_bound, cumulative) in buckets {
+        if upper_bound == f64::INFINITY {
+            // The +Inf bucket is implied by the sample count, as in the
+            // protobuf format.
+            count = count.or(Some(cumulative));
+            continue;
+        }
+        let mut b = Bucket::new();
+        b.set_upper_bound(upper_bound);
+        if cumulative.fract() == 0.0 {
+            b.set_cumulative_count(cumulative as u64);
+        } else {
+            b.set_cumulative_count_float(cumulative);
+        }
+        h.bucket.push(b);
+    }
+    if let Some(count) = count {
+        if count.fract() == 0.0 {
+            h.set_sample_count(count as u64);
+        } else {
+            h.set_sample_count_float(count);
+        }
+    }
+    if let Some(sum) = series.sum {
+        h.set_sample_sum(sum);
+    }
+    h
+}
+
+/// Parses the histograms in a text exposition as classic histograms.
+///
+/// Histograms are grouped by metric family and label set. Each is returned
+/// with the family name followed by its labels, other than `le`, in sorted
+/// order, e.g. `latency_seconds{method="GET"}`. Series are returned in the
+/// order they first appear, and samples of other metric types are skipped.
+pub fn parse_text_histograms(input: &str) -> Result<Vec<(String, Histogram)>, ParseError> {
+    let mut families = HashSet::new();
+    let mut samples = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.split_whitespace();
+            if words.next() == Some("TYPE") {
+                let name = words
+                    .next()
+                    .ok_or_else(|| error(line_no, "missing metric name"))?;
+                if words.next() == Some("histogram") {
+                    families.insert(name.to_string());
+                }
+            }
+            continue;
+        }
+        samples.push(parse_sample(line_no, line)?);
+    }
+
+    // Samples are grouped once all the types are known, so the order of the
+    // lines doesn't matter.
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut series: Vec<(String, Series)> = Vec::new();
+    for sample in samples {
+        let Some((family, suffix)) = sample.name.rsplit_once('_') else {
+            continue;
+        };
+        if !families.contains(family) {
+            continue;
+        }
+        let key = series_key(family, &sample.labels);
+        let i = *index.entry(key.clone()).or_insert_with(|| {
+            series.push((key, Series::default()));
+            series.len() - 1
+        });
+        let s = &mut series[i].1;
+        match suffix {
+            "bucket" => {
+                let le = sample
+                    .labels
+                    .iter()
+                    .find(|(name, _)| name == "le")
+                    .ok_or_else(|| error(sample.line, "bucket is missing le label"))?;
+                let le = parse_value(&le.1)
+                    .ok_or_else(|| error(sample.line, format!("invalid le {:?}", le.1)))?;
+                s.buckets.push((le, sample.value));
+            }
+            "sum" => s.sum = Some(sample.value),
+            "count" => s.count = Some(sample.value),
+            _ => {}
+        }
+    }
+    Ok(series
+        .into_iter()
+        .map(|(key, s)| (key, to_histogram(s)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classic_histogram_to_ecdf;
+
+    const EXPOSITION: &str = r#"
+# HELP http_requests_total The total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="post",code="200"} 1027 1395066363000
+
+# A histogram, with its lines shuffled.
+# HELP latency_seconds Request latency.
+# TYPE latency_seconds histogram
+latency_seconds_bucket{path="/a",le="0.5"} 5
+latency_seconds_bucket{le="0.1",path="/a"} 2
+latency_seconds_bucket{path="/b", le="+Inf"} 3
+latency_seconds_sum{path="/a"} 4.2
+latency_seconds_bucket{path="/a",le="+Inf"} 10
+latency_seconds_bucket{path="/a",le="1"} 9
+latency_seconds_count{path="/a"} 10
+latency_seconds_bucket{path="/b",le="0.1"} 3
+latency_seconds_count{path="/b"} 3
+
+# TYPE size_bytes histogram
+size_bytes_bucket{le="1024"} 1
+size_bytes_bucket{le="+Inf"} 4
+size_bytes_sum 9000
+size_bytes_count 4
+"#;
+
+    #[test]
+    fn parse_exposition() {
+        let histograms = parse_text_histograms(EXPOSITION).unwrap();
+        let names: Vec<&str> = histograms.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                r#"latency_seconds{path="/a"}"#,
+                r#"latency_seconds{path="/b"}"#,
+                "size_bytes"
+            ]
+        );
+
+        let a = &histograms[0].1;
+        let bounds: Vec<f64> = a.bucket.iter().map(|b| b.upper_bound()).collect();
+        assert_eq!(bounds, [0.1, 0.5, 1.0]);
+        let counts: Vec<u64> = a.bucket.iter().map(|b| b.cumulative_count()).collect();
+        assert_eq!(counts, [2, 5, 9]);
+        assert_eq!(a.sample_count(), 10);
+        assert_eq!(a.sample_sum(), 4.2);
+        assert_eq!(classic_histogram_to_ecdf(a).len(), 10.0);
+
+        let size = &histograms[2].1;
+        assert_eq!(size.bucket.len(), 1);
+        assert_eq!(size.sample_count(), 4);
+        assert_eq!(size.sample_sum(), 9000.0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err =
+            parse_text_histograms("# TYPE x histogram\nx_bucket{le=\"1\"} one\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        let err = parse_text_histograms("# TYPE x histogram\nx_bucket 1\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(parse_text_histograms("x_bucket{le=\"1} 1\n").is_err());
+    }
+}
@@ -14,18 +14,108 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::kstest;
+use crate::{adtest, kstest};
 use num_traits::cast::ToPrimitive;
 use num_traits::{Float, Num};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::convert::From;
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display};
 use std::iter::FusedIterator;
 
-#[derive(Clone, Debug, Default)]
+/// The reasons a quantile or fraction can't be calculated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuantileError {
+    /// The distribution has no samples.
+    Empty,
+    /// There are too few samples to interpolate the result.
+    InsufficientSamples,
+    /// The input is NaN, or the requested quantile is outside of [0, 1].
+    OutOfRange,
+}
+
+impl Display for QuantileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuantileError::Empty => write!(f, "distribution is empty"),
+            QuantileError::InsufficientSamples => write!(f, "not enough samples to interpolate"),
+            QuantileError::OutOfRange => write!(f, "input out of range"),
+        }
+    }
+}
+
+impl std::error::Error for QuantileError {}
+
+/// Strategies for automatically choosing bucket boundaries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BoundStrategy {
+    /// Boundaries evenly spaced between the smallest and largest values.
+    Linear,
+    /// Boundaries evenly spaced on a logarithmic scale between the smallest
+    /// and largest positive values.
+    Log,
+    /// Boundaries taken from the exponential bucket scheme used by native
+    /// Prometheus histograms, at the highest resolution that fits.
+    Exponential,
+}
+
+/// The deviations from a reference distribution that a Kolmogorov-Smirnov
+/// test looks for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KsSide {
+    /// Deviations in either direction.
+    TwoSided,
+    /// Samples tending to be greater than the reference, i.e. where the
+    /// ECDF lies below the reference CDF.
+    Greater,
+    /// Samples tending to be less than the reference, i.e. where the ECDF
+    /// lies above the reference CDF.
+    Less,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct ECDF<V> {
-    samples: Vec<(V, usize)>,
+    pub(crate) samples: Vec<(V, usize)>,
+}
+
+impl<V> ECDF<V>
+where
+    V: Copy,
+{
+    /// Builds an ECDF from pre-counted `(value, count)` pairs, in any order.
+    ///
+    /// Counts for the same value are added together. This is equivalent to
+    /// collecting the pairs, but reads better when the type isn't otherwise
+    /// known.
+    pub fn from_counts(pairs: impl IntoIterator<Item = (V, usize)>) -> Self
+    where
+        V: PartialOrd,
+    {
+        pairs.into_iter().collect()
+    }
+
+    /// The smallest observed value, or `None` if there are no samples.
+    pub fn min(&self) -> Option<V> {
+        self.samples.first().map(|&(v, _)| v)
+    }
+
+    /// The largest observed value, or `None` if there are no samples.
+    pub fn max(&self) -> Option<V> {
+        self.samples.last().map(|&(v, _)| v)
+    }
+
+    /// Applies `f` to every sample value, keeping the counts.
+    ///
+    /// `f` doesn't need to be monotonic; the results are re-sorted. Values
+    /// that map to the same result are coalesced into a single sample whose
+    /// count is the sum of their counts.
+    pub fn map_values<U, F>(&self, f: F) -> ECDF<U>
+    where
+        U: PartialOrd + Copy,
+        F: Fn(V) -> U,
+    {
+        self.samples.iter().map(|&(v, n)| (f(v), n)).collect()
+    }
 }
 
 impl<V> ECDF<V>
@@ -47,6 +137,14 @@ where
         self.samples.is_empty()
     }
 
+    /// The sum of all sample values, each weighted by the number of times it was observed.
+    pub fn sum(&self) -> f64 {
+        self.samples
+            .iter()
+            .map(|&(v, n)| v.to_f64().unwrap() * (n as f64))
+            .sum()
+    }
+
     /// Calculates sample mean, standard deviation, and count.
     pub fn stats(&self) -> (f64, f64, usize) {
         let mut sum = 0.0;
@@ -86,10 +184,101 @@ where
         self.add_n(sample, 1)
     }
 
+    /// Returns the smallest observed value `v` where `P(x <= v) >= q`.
+    pub fn try_quantile(&self, q: f64) -> Result<V, QuantileError> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(QuantileError::OutOfRange);
+        }
+        let last = match self.samples.last() {
+            Some(&(v, _)) => v,
+            None => return Err(QuantileError::Empty),
+        };
+        let rank = q * (self.len() as f64);
+        let mut sum: usize = 0;
+        for &(v, n) in self.samples.iter() {
+            sum += n;
+            if sum as f64 >= rank {
+                return Ok(v);
+            }
+        }
+        Ok(last)
+    }
+
+    /// Returns the fraction of observations that are less than or equal to `v`.
+    pub fn try_fraction(&self, v: V) -> Result<f64, QuantileError> {
+        if v.partial_cmp(&v).is_none() {
+            return Err(QuantileError::OutOfRange);
+        }
+        if self.samples.is_empty() {
+            return Err(QuantileError::Empty);
+        }
+        let mut below: usize = 0;
+        let mut total: usize = 0;
+        for &(x, n) in self.samples.iter() {
+            if x <= v {
+                below += n;
+            }
+            total += n;
+        }
+        Ok(below as f64 / total as f64)
+    }
+
+    /// The fraction of observations at or below `threshold`.
+    ///
+    /// When `threshold` is a service level objective's target, such as a
+    /// maximum latency, this is the service level indicator. Returns NaN if
+    /// there are no observations.
+    pub fn fraction_below(&self, threshold: V) -> f64 {
+        self.try_fraction(threshold).unwrap_or(f64::NAN)
+    }
+
+    /// The fraction of the error budget that remains, for an objective that
+    /// `target_fraction` of observations are at or below `threshold`.
+    ///
+    /// The error budget is the fraction of observations allowed to miss the
+    /// threshold, `1 - target_fraction`. A result of 1 means that no
+    /// observations missed, 0 means that the budget is exactly spent, and
+    /// negative values mean that it has been overspent.
+    ///
+    /// A target of 1 leaves no budget at all, so the result is 1 if no
+    /// observations missed and negative infinity otherwise. Returns NaN if
+    /// the target isn't between 0 and 1, or there are no observations.
+    pub fn slo_budget_remaining(&self, threshold: V, target_fraction: f64) -> f64 {
+        if !(0.0..=1.0).contains(&target_fraction) {
+            return f64::NAN;
+        }
+        let budget = 1.0 - target_fraction;
+        let spent = 1.0 - self.fraction_below(threshold);
+        if budget == 0.0 {
+            return if spent == 0.0 {
+                1.0
+            } else if spent > 0.0 {
+                f64::NEG_INFINITY
+            } else {
+                f64::NAN
+            };
+        }
+        (budget - spent) / budget
+    }
+
+    /// Adds pre-counted `(value, count)` pairs to this ECDF.
+    ///
+    /// The pairs must be in ascending order of value, which lets them be
+    /// merged in a single pass. Repeated values are fine. Out of order values
+    /// leave the ECDF unsorted, so debug builds panic on them. Use
+    /// [ECDF::merge_unsorted] when the order isn't known.
     pub fn merge_sorted(&mut self, it: impl Iterator<Item = (V, usize)>) {
         let mut i = 0;
         let mut n = self.samples.len();
+        let mut prev: Option<V> = None;
         for (v, c) in it {
+            debug_assert!(
+                prev.map_or(true, |p| p <= v),
+                "merge_sorted: {:?} follows {:?}",
+                v,
+                prev.unwrap()
+            );
+            prev = Some(v);
             loop {
                 if i == n {
                     self.samples.push((v, c));
@@ -111,17 +300,84 @@ where
                     }
                 }
             }
-            i += 1;
         }
     }
 
+    /// Adds `(value, count)` pairs to this ECDF, in any order.
+    ///
+    /// The pairs are sorted before being merged, so this is safe to use when
+    /// [ECDF::merge_sorted]'s ordering contract can't be guaranteed.
+    pub fn merge_unsorted(&mut self, it: impl IntoIterator<Item = (V, usize)>) {
+        let mut pairs: Vec<(V, usize)> = it.into_iter().collect();
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        self.merge_sorted(pairs.into_iter());
+    }
+
+    /// Combines the samples of two ECDFs into a new one.
+    pub fn merge(&self, other: &ECDF<V>) -> ECDF<V> {
+        let mut samples = Vec::with_capacity(self.samples.len() + other.samples.len());
+        let mut a = self.samples.iter().peekable();
+        let mut b = other.samples.iter().peekable();
+        loop {
+            let next = match (a.peek(), b.peek()) {
+                (Some(&&(av, an)), Some(&&(bv, bn))) => match av.partial_cmp(&bv).unwrap() {
+                    Ordering::Less => {
+                        a.next();
+                        (av, an)
+                    }
+                    Ordering::Equal => {
+                        a.next();
+                        b.next();
+                        (av, an + bn)
+                    }
+                    Ordering::Greater => {
+                        b.next();
+                        (bv, bn)
+                    }
+                },
+                (Some(_), None) => {
+                    samples.extend(a);
+                    break;
+                }
+                (None, Some(_)) => {
+                    samples.extend(b);
+                    break;
+                }
+                (None, None) => break,
+            };
+            samples.push(next);
+        }
+        ECDF { samples }
+    }
+
     pub fn compact(&mut self, target_size: usize) {
         self.compact_if(target_size, target_size)
     }
 
     pub fn compact_if(&mut self, over_size: usize, target_size: usize) {
+        self.compact_by(over_size, target_size, |_| 1.0)
+    }
+
+    /// Like [ECDF::compact], but the error of dropping each point is taken
+    /// relative to the height of the curve at that point.
+    ///
+    /// The absolute error treats every count the same, which tends to merge
+    /// away the low end of distributions that span several orders of
+    /// magnitude. Weighting by the cumulative count preserves detail where
+    /// the curve is small.
+    pub fn compact_relative(&mut self, target_size: usize) {
+        self.compact_by(target_size, target_size, |rank| 1.0 / rank.max(1) as f64)
+    }
+
+    /// Drops points until there are at most `target_size`, once there are
+    /// more than `over_size`. Each point's interpolation error is multiplied
+    /// by `scale` of its cumulative count.
+    fn compact_by<F>(&mut self, over_size: usize, target_size: usize, scale: F)
+    where
+        F: Fn(usize) -> f64,
+    {
         if target_size < 3 {
-            return self.compact_if(over_size, 3);
+            return self.compact_by(over_size, 3, scale);
         }
         let mut len = self.samples.len();
         if len <= over_size {
@@ -139,6 +395,17 @@ where
         // N items, extracts the indices, sort that, and use that to remove the
         // items in a way that minimizes copies in self.samples.
 
+        // The cumulative count at each point, which doesn't change when its
+        // neighbours are merged.
+        let mut ranks: Vec<usize> = self
+            .samples
+            .iter()
+            .scan(0, |sum, &(_, n)| {
+                *sum += n;
+                Some(*sum)
+            })
+            .collect();
+
         // Calculate the errors for all elements except the ends.
         let mut errs = Vec::<f64>::with_capacity(len - 1);
         let mut x0 = self.samples[0].0;
@@ -147,7 +414,7 @@ where
             let (x2, y2) = self.samples[i];
             // Find expected y for x1, given linear interpolation between x0 and x2.
             let y = (x1 - x0).to_f64().unwrap() * ((y1 + y2) as f64) / (x2 - x0).to_f64().unwrap();
-            errs.push((y1 as f64 - y).abs());
+            errs.push((y1 as f64 - y).abs() * scale(ranks[i - 1]));
             x0 = x1;
             (x1, y1) = (x2, y2);
         }
@@ -171,6 +438,7 @@ where
             // Drop the chosen sample, add the sample count to the next greater sample.
             errs.remove(best_index);
             let (_, c) = self.samples.remove(best_index + 1);
+            ranks.remove(best_index + 1);
             self.samples[best_index + 1].1 += c;
             len -= 1;
 
@@ -182,7 +450,7 @@ where
                 let (x2, y2) = self.samples[best_index + 1];
                 let y =
                     (x1 - x0).to_f64().unwrap() * ((y1 + y2) as f64) / (x2 - x0).to_f64().unwrap();
-                errs[i] = (y1 as f64 - y).abs();
+                errs[i] = (y1 as f64 - y).abs() * scale(ranks[best_index]);
                 x0 = x1;
                 (x1, y1) = (x2, y2);
             } else {
@@ -193,7 +461,7 @@ where
                 let (x2, y2) = self.samples[best_index + 2];
                 let y =
                     (x1 - x0).to_f64().unwrap() * ((y1 + y2) as f64) / (x2 - x0).to_f64().unwrap();
-                errs[best_index] = (y1 as f64 - y).abs();
+                errs[best_index] = (y1 as f64 - y).abs() * scale(ranks[best_index + 1]);
             }
         }
     }
@@ -203,9 +471,6 @@ where
         self.samples.shrink_to_fit()
     }
 
-    // TODO: Would using an Anderson-Darling test be better? In what ways?
-    // Is: https://en.wikipedia.org/wiki/Anderson%E2%80%93Darling_test
-
     /// Runs a Kolmogorov-Smirnov test against a given reference distribution.
     ///
     /// The returned value is the calculated confidence level, an estimate of the
@@ -217,46 +482,153 @@ where
     where
         F: Fn(V) -> f64,
     {
-        // Find the maximum difference between the sample and the reference distribution.
+        self.drawn_from_distribution_directed(cdf, KsSide::TwoSided)
+    }
+
+    /// Runs a Kolmogorov-Smirnov test against a given reference distribution,
+    /// only counting deviations on the given `side`.
+    ///
+    /// For example, [KsSide::Greater] asks whether the samples are larger
+    /// than the reference distribution would suggest, and isn't bothered if
+    /// they are smaller.
+    pub fn drawn_from_distribution_directed<F>(&self, cdf: F, side: KsSide) -> f64
+    where
+        F: Fn(V) -> f64,
+    {
+        // Find the maximum differences above and below the reference distribution.
         let total = self.len() as f64;
-        let mut max_diff = 0.0;
+        let mut above: f64 = 0.0;
+        let mut below: f64 = 0.0;
         let mut p = 0.0;
         let mut sum: usize = 0;
         for &(v, n) in self.samples.iter() {
             let p_dist = cdf(v);
-            let mut diff = (p_dist - p).abs();
-            if diff > max_diff {
-                max_diff = diff;
-            }
+            // The ECDF is furthest below just before each step, and furthest
+            // above just after it.
+            below = below.max(p_dist - p);
             sum += n;
             p = sum as f64 / total;
-            diff = (p_dist - p).abs();
-            if diff > max_diff {
-                max_diff = diff;
-            }
+            above = above.max(p - p_dist);
         }
+        let max_diff = match side {
+            KsSide::TwoSided => above.max(below),
+            KsSide::Greater => below,
+            KsSide::Less => above,
+        };
         let z = max_diff * total.sqrt();
-        kstest::kprob(z)
+        match side {
+            // The exact distribution assumes there are no ties.
+            KsSide::TwoSided if self.samples.iter().all(|&(_, n)| n == 1) => {
+                kstest::ks_test(max_diff, self.len())
+            }
+            KsSide::TwoSided => kstest::kprob(z),
+            _ => kstest::kprob_one_sided(z),
+        }
+    }
+
+    /// Runs an Anderson-Darling test against a given reference distribution.
+    ///
+    /// Like [ECDF::drawn_from_distribution], the returned value is an estimate
+    /// of the likelihood that the sample comes from the reference distribution.
+    /// Unlike the Kolmogorov-Smirnov test, deviations in the tails are weighted
+    /// more heavily than deviations near the median.
+    ///
+    /// See:
+    /// https://en.wikipedia.org/wiki/Anderson%E2%80%93Darling_test
+    pub fn anderson_darling<F>(&self, cdf: F) -> f64
+    where
+        F: Fn(V) -> f64,
+    {
+        let n = self.len();
+        if n == 0 {
+            return 1.0;
+        }
+        let total = n as f64;
+        // A² = -n - Σ [(2i - 1) ln F(x_i) + (2(n - i) + 1) ln(1 - F(x_i))] / n
+        // Sample i ranges over the ranks of equal values, so the weights can be
+        // summed in closed form.
+        let mut sum = 0.0;
+        let mut below: usize = 0;
+        for &(v, count) in self.samples.iter() {
+            let p = cdf(v);
+            let above = n - below;
+            let lo_weight = ((below + count).pow(2) - below.pow(2)) as f64;
+            let hi_weight = (above.pow(2) - (above - count).pow(2)) as f64;
+            sum += lo_weight * p.ln() + hi_weight * (1.0 - p).ln();
+            below += count;
+        }
+        let a2 = -total - sum / total;
+        adtest::adprob(a2)
     }
 
     /// Runs a two-sample Kolmogorov-Smirnov test.
     ///
     /// The returned value is the calculated confidence level, an estimate of the
     /// likelihood that the two samples were drawn from the same distribution.
+    /// It is exact for small samples without ties.
     ///
     /// See:
     /// https://en.wikipedia.org/wiki/Kolmogorov%E2%80%93Smirnov_test#Two-sample_Kolmogorov%E2%80%93Smirnov_test
     pub fn drawn_from_same_distribution_as(&self, other: &ECDF<V>) -> f64 {
-        let max_diff = self
-            .zip(other)
+        kstest::ks2prob(self.ks_statistic(other), self.len(), other.len())
+    }
+
+    /// Calculates the two-sample Kolmogorov-Smirnov statistic, D.
+    ///
+    /// This is the largest vertical distance between the two ECDF curves.
+    pub fn ks_statistic(&self, other: &ECDF<V>) -> f64 {
+        self.zip(other)
             // find the difference between self and other at each point of the curve
             .map(|(_, a, b)| (a - b).abs())
             .reduce(|a, b| if a < b { b } else { a })
-            .unwrap_or(0.0);
-        let n = self.len();
-        let m = other.len();
-        let z = max_diff * ((n * m) as f64 / (n + m) as f64).sqrt();
-        kstest::kprob(z)
+            .unwrap_or(0.0)
+    }
+
+    /// Whether a two-sample Kolmogorov-Smirnov test rejects the hypothesis
+    /// that both samples were drawn from the same distribution, at the
+    /// significance level `alpha`.
+    ///
+    /// The statistic is compared against the asymptotic critical value
+    /// `c(alpha) * sqrt((n + m) / (n * m))`, where `c(alpha)` is
+    /// `sqrt(-ln(alpha / 2) / 2)`. For example, `alpha = 0.05` means that the
+    /// distributions differ with 95% confidence. Empty samples are never
+    /// rejected.
+    pub fn ks_reject(&self, other: &ECDF<V>, alpha: f64) -> bool {
+        let (n, m) = (self.len() as f64, other.len() as f64);
+        if n == 0.0 || m == 0.0 {
+            return false;
+        }
+        let c = (-(alpha / 2.0).ln() / 2.0).sqrt();
+        self.ks_statistic(other) > c * ((n + m) / (n * m)).sqrt()
+    }
+
+    /// Calculates the two-sample Cramér-von Mises statistic, T.
+    ///
+    /// Where [ECDF::ks_statistic] only looks at the largest gap between the
+    /// two curves, this integrates the squared gap over the pooled samples,
+    /// so it is more sensitive to differences in overall shape. The result
+    /// is normalized by the sample sizes, so it can be compared against the
+    /// statistic's asymptotic distribution. Returns NaN if either ECDF is
+    /// empty.
+    ///
+    /// See:
+    /// https://en.wikipedia.org/wiki/Cram%C3%A9r%E2%80%93von_Mises_criterion#Cram%C3%A9r%E2%80%93von_Mises_test_for_two_samples
+    pub fn cramer_von_mises(&self, other: &ECDF<V>) -> f64 {
+        let (n, m) = (self.len() as f64, other.len() as f64);
+        if n == 0.0 || m == 0.0 {
+            return f64::NAN;
+        }
+        let mut last = (0.0, 0.0);
+        let sum: f64 = self
+            .zip(other)
+            .map(|(_, a, b)| {
+                // The number of pooled samples at this value.
+                let count = (a - last.0) * n + (b - last.1) * m;
+                last = (a, b);
+                count * (a - b) * (a - b)
+            })
+            .sum();
+        sum * n * m / ((n + m) * (n + m))
     }
 
     /// Iterates through all points on the ECDF curve.
@@ -270,6 +642,15 @@ where
             })
     }
 
+    /// Iterates through all points on the ECDF curve, with exact counts.
+    /// The returned iterator generates (V, number of samples <= V) tuples.
+    pub fn cumulative_iter(&self) -> impl Iterator<Item = (V, usize)> + '_ {
+        self.samples.iter().scan(0, |sum, &(v, n)| {
+            *sum += n;
+            Some((v, *sum))
+        })
+    }
+
     /// Iterates through all points of comparison between two ECDF curves.
     /// The returned iterator generates (V, P(self <= V), P(other <= V)) tuples.
     fn zip<'a>(&'a self, other: &'a ECDF<V>) -> impl Iterator<Item = (V, f64, f64)> + 'a {
@@ -289,10 +670,136 @@ where
 
     /// Calculates the area difference between the two ECDFs.
     pub fn area_difference(&self, other: &ECDF<V>) -> f64 {
+        self.integrate_difference(other, f64::abs)
+    }
+
+    /// Calculates the signed area difference between the two ECDFs.
+    ///
+    /// Unlike [ECDF::area_difference], regions where `self` is below `other`
+    /// subtract from the total. A negative result means that `self` tends to
+    /// have larger values than `other`, a positive result means it tends to
+    /// have smaller ones.
+    pub fn signed_area_difference(&self, other: &ECDF<V>) -> f64 {
+        self.integrate_difference(other, |d| d)
+    }
+
+    /// Splits [ECDF::area_difference] into the area where `self` is below
+    /// `other`, and the area where it is above, in that order.
+    ///
+    /// Where `self` is below, it has larger values than `other`. Keeping the
+    /// two apart lets callers weigh over- and under-estimates differently.
+    pub fn area_difference_parts(&self, other: &ECDF<V>) -> (f64, f64) {
+        (
+            self.integrate_difference(other, |d| (-d).max(0.0)),
+            self.integrate_difference(other, |d| d.max(0.0)),
+        )
+    }
+
+    /// Calculates the first Wasserstein (earth mover's) distance between the two ECDFs.
+    ///
+    /// This integrates the horizontal gap between the quantile functions,
+    /// `|Q_self(p) - Q_other(p)|`, over all probabilities `p`. Returns NaN if
+    /// either ECDF is empty.
+    pub fn wasserstein_distance(&self, other: &ECDF<V>) -> f64 {
+        let (na, nb) = (self.len() as u128, other.len() as u128);
+        if na == 0 || nb == 0 {
+            return f64::NAN;
+        }
+        // Cumulative probabilities are scaled by na * nb to keep them exact.
+        let mut a_iter = self.samples.iter();
+        let mut b_iter = other.samples.iter();
+        let (mut a, n) = *a_iter.next().unwrap();
+        let mut a_rank = n as u128 * nb;
+        let (mut b, n) = *b_iter.next().unwrap();
+        let mut b_rank = n as u128 * na;
+        let mut last = 0;
+        let mut sum = 0.0;
+        loop {
+            // Both quantile functions are constant between last and now.
+            let now = a_rank.min(b_rank);
+            let gap = (a.to_f64().unwrap() - b.to_f64().unwrap()).abs();
+            sum += gap * (now - last) as f64;
+            last = now;
+            if a_rank == now {
+                match a_iter.next() {
+                    Some(&(v, n)) => {
+                        a = v;
+                        a_rank += n as u128 * nb;
+                    }
+                    None => break,
+                }
+            }
+            if b_rank == now {
+                match b_iter.next() {
+                    Some(&(v, n)) => {
+                        b = v;
+                        b_rank += n as u128 * na;
+                    }
+                    None => break,
+                }
+            }
+        }
+        sum / (na * nb) as f64
+    }
+
+    /// Calculates the total variation distance between the two ECDFs.
+    ///
+    /// This is half the L1 distance between the probability masses the two
+    /// samples place at each value, ranging from 0 for identical distributions
+    /// to 1 for samples with no values in common. Returns NaN if either ECDF is
+    /// empty.
+    pub fn total_variation(&self, other: &ECDF<V>) -> f64 {
+        if self.is_empty() || other.is_empty() {
+            return f64::NAN;
+        }
+        self.masses(other).map(|(p, q)| (p - q).abs()).sum::<f64>() / 2.0
+    }
+
+    /// Calculates the Kullback-Leibler divergence of `self` from `other`.
+    ///
+    /// The probability masses at each value are compared, so this is only
+    /// finite if every value in `self` also appears in `other`. Values that
+    /// only appear in `other` contribute nothing. Note that the divergence is
+    /// not symmetric. Returns NaN if either ECDF is empty.
+    ///
+    /// See:
+    /// https://en.wikipedia.org/wiki/Kullback%E2%80%93Leibler_divergence
+    pub fn kl_divergence(&self, other: &ECDF<V>) -> f64 {
+        if self.is_empty() || other.is_empty() {
+            return f64::NAN;
+        }
+        self.masses(other)
+            .filter(|&(p, _)| p > 0.0)
+            .map(|(p, q)| {
+                if q > 0.0 {
+                    p * (p / q).ln()
+                } else {
+                    f64::INFINITY
+                }
+            })
+            .sum()
+    }
+
+    /// Iterates through the probability masses of both ECDFs at each value
+    /// in either of them.
+    fn masses<'a>(&'a self, other: &'a ECDF<V>) -> impl Iterator<Item = (f64, f64)> + 'a {
+        self.zip(other)
+            .scan((0.0, 0.0), |(last_a, last_b), (_, a, b)| {
+                let mass = (a - *last_a, b - *last_b);
+                (*last_a, *last_b) = (a, b);
+                Some(mass)
+            })
+    }
+
+    /// Integrates `f(P(self <= V) - P(other <= V))` over all values of `V`.
+    fn integrate_difference<F>(&self, other: &ECDF<V>, f: F) -> f64
+    where
+        F: Fn(f64) -> f64,
+    {
         let mut it = self
             .zip(other)
             // find the difference between self and other at each point of the curve
-            .map(|(v, a, b)| (v, (a - b).abs()));
+            .map(|(v, a, b)| (v, f(a - b)));
         let mut last: (V, f64);
         match it.next() {
             Some(x) => {
@@ -337,6 +844,195 @@ where
             samples: self.samples.iter().map(|&(v, n)| (v, n as f64)).collect(),
         }
     }
+
+    /// The interquartile range, the distance between the 25th and 75th
+    /// percentiles, interpolating between samples.
+    ///
+    /// Returns NaN if there are no samples.
+    pub fn iqr(&self) -> f64 {
+        let ecdf = self.interpolate();
+        (ecdf.quantile(0.75) - ecdf.quantile(0.25))
+            .to_f64()
+            .unwrap()
+    }
+
+    /// The mean of the observations between quantiles `lower_q` and `upper_q`.
+    ///
+    /// The quantiles are interpolated between samples, so this is a robust
+    /// alternative to the mean of [ECDF::stats] for skewed distributions.
+    /// Returns NaN if there are no observations in range.
+    pub fn trimmed_mean(&self, lower_q: f64, upper_q: f64) -> f64 {
+        let ecdf = self.interpolate();
+        let (lo, hi) = (ecdf.quantile(lower_q), ecdf.quantile(upper_q));
+        let mut sum = 0.0;
+        let mut count = 0;
+        for &(v, n) in self.samples.iter().filter(|&&(v, _)| lo <= v && v <= hi) {
+            sum += v.to_f64().unwrap() * (n as f64);
+            count += n;
+        }
+        sum / (count as f64)
+    }
+
+    /// Generates up to `n` bucket boundaries that span the observed values.
+    ///
+    /// The [BoundStrategy::Log] and [BoundStrategy::Exponential] strategies
+    /// only cover positive values, and return no boundaries if there are none.
+    /// If the values span too many exponential buckets even at the coarsest
+    /// schema, the smallest boundaries are dropped, so that the first bucket
+    /// also holds everything below it.
+    pub fn auto_bounds(&self, strategy: BoundStrategy, n: usize) -> Vec<V> {
+        let lo = match strategy {
+            BoundStrategy::Linear => self.samples.first(),
+            _ => self.samples.iter().find(|&&(v, _)| v > V::zero()),
+        };
+        let (lo, hi) = match (lo, self.samples.last()) {
+            (Some(&(lo, _)), Some(&(hi, _))) => (lo, hi),
+            _ => return Vec::new(),
+        };
+        if n == 0 {
+            return Vec::new();
+        }
+        if n == 1 || lo == hi {
+            return vec![hi];
+        }
+        let steps = V::from(n - 1).unwrap();
+        match strategy {
+            BoundStrategy::Linear => (0..n)
+                .map(|i| match i {
+                    0 => lo,
+                    i if i == n - 1 => hi,
+                    i => lo + (hi - lo) * V::from(i).unwrap() / steps,
+                })
+                .collect(),
+            BoundStrategy::Log => {
+                let (log_lo, log_hi) = (lo.ln(), hi.ln());
+                (0..n)
+                    .map(|i| match i {
+                        0 => lo,
+                        i if i == n - 1 => hi,
+                        i => (log_lo + (log_hi - log_lo) * V::from(i).unwrap() / steps).exp(),
+                    })
+                    .collect()
+            }
+            BoundStrategy::Exponential => {
+                // Bucket boundaries are at 2^(i / 2^schema). Start with the
+                // finest schema Prometheus supports, and reduce resolution
+                // until the observed range fits in `n` boundaries.
+                let two = V::one() + V::one();
+                let mut schema = 8;
+                loop {
+                    let scale = two.powi(schema);
+                    let first = (lo.log2() * scale).floor();
+                    let last = (hi.log2() * scale).ceil();
+                    if last - first < steps + V::one() || schema == -4 {
+                        let last = last.to_i64().unwrap();
+                        let first = first.to_i64().unwrap().max(last - n as i64 + 1);
+                        return (first..=last)
+                            .map(|i| two.powf(V::from(i).unwrap() / scale))
+                            .collect();
+                    }
+                    schema -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// A quantile estimated by an [OpenTailECDF].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TailQuantile<V> {
+    /// The quantile falls within the body, and is an observed value.
+    Value(V),
+    /// The quantile falls in the tail. The actual value is above the given threshold.
+    Overflow(V),
+}
+
+/// An ECDF that only keeps precise values up to a threshold.
+///
+/// Observations above the threshold are counted in a single overflow bucket,
+/// so memory is bounded by the number of distinct values in the body of the
+/// distribution, no matter how long the tail is.
+#[derive(Clone, Debug)]
+pub struct OpenTailECDF<V> {
+    body: ECDF<V>,
+    threshold: V,
+    overflow: usize,
+}
+
+impl<V> OpenTailECDF<V>
+where
+    V: Num + ToPrimitive + PartialOrd + Copy + Debug,
+{
+    /// Creates an empty ECDF that collapses values above `threshold`.
+    pub fn new(threshold: V) -> Self {
+        OpenTailECDF {
+            body: ECDF::default(),
+            threshold,
+            overflow: 0,
+        }
+    }
+
+    /// Adds a single observation.
+    pub fn add(&mut self, sample: V) {
+        if sample > self.threshold {
+            self.overflow += 1;
+        } else {
+            self.body.add(sample);
+        }
+    }
+
+    /// The observations at or below the threshold.
+    pub fn body(&self) -> &ECDF<V> {
+        &self.body
+    }
+
+    /// The threshold above which values are no longer tracked precisely.
+    pub fn threshold(&self) -> V {
+        self.threshold
+    }
+
+    /// The number of observations above the threshold.
+    pub fn overflow(&self) -> usize {
+        self.overflow
+    }
+
+    /// The total number of observations, including the overflow.
+    pub fn len(&self) -> usize {
+        self.body.len() + self.overflow
+    }
+
+    /// Returns `true` if there are no observations.
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty() && self.overflow == 0
+    }
+
+    /// Removes all observations collected so far.
+    pub fn clear(&mut self) {
+        self.body.clear();
+        self.overflow = 0;
+    }
+
+    /// Returns the smallest observed value `v` where `P(x <= v) >= q`.
+    ///
+    /// If that value is in the tail, the threshold is returned as
+    /// [TailQuantile::Overflow] instead.
+    pub fn try_quantile(&self, q: f64) -> Result<TailQuantile<V>, QuantileError> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(QuantileError::OutOfRange);
+        }
+        if self.is_empty() {
+            return Err(QuantileError::Empty);
+        }
+        let rank = q * (self.len() as f64);
+        let mut sum: usize = 0;
+        for &(v, n) in self.body.samples.iter() {
+            sum += n;
+            if sum as f64 >= rank {
+                return Ok(TailQuantile::Value(v));
+            }
+        }
+        Ok(TailQuantile::Overflow(self.threshold))
+    }
 }
 
 impl<V> From<Vec<V>> for ECDF<V>
@@ -350,6 +1046,44 @@ where
     }
 }
 
+impl<V> FromIterator<V> for ECDF<V>
+where
+    V: PartialOrd + Copy,
+{
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        ECDF::from(iter.into_iter().collect::<Vec<V>>())
+    }
+}
+
+impl<V> FromIterator<(V, usize)> for ECDF<V>
+where
+    V: PartialOrd + Copy,
+{
+    /// Builds an ECDF from pre-counted `(value, count)` pairs, in any order.
+    fn from_iter<I: IntoIterator<Item = (V, usize)>>(iter: I) -> Self {
+        let mut counts: Vec<(V, usize)> = iter.into_iter().collect();
+        counts.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut samples: Vec<(V, usize)> = Vec::with_capacity(counts.len());
+        for (v, n) in counts {
+            match samples.last_mut() {
+                Some(last) if last.0 == v => last.1 += n,
+                _ => samples.push((v, n)),
+            }
+        }
+        ECDF { samples }
+    }
+}
+
+impl<V> IntoIterator for ECDF<V> {
+    type Item = (V, usize);
+    type IntoIter = std::vec::IntoIter<(V, usize)>;
+
+    /// Iterates over the distinct sample values and their counts, in ascending order.
+    fn into_iter(self) -> Self::IntoIter {
+        self.samples.into_iter()
+    }
+}
+
 impl<V> Serialize for ECDF<V>
 where
     V: Serialize,
@@ -376,6 +1110,49 @@ where
     }
 }
 
+/// A sample in the labeled serialization format.
+#[derive(Deserialize, Serialize)]
+struct LabeledSample<V> {
+    value: V,
+    count: usize,
+}
+
+impl<V> ECDF<V> {
+    /// Serializes to JSON as an array of `{"value": v, "count": n}` objects.
+    ///
+    /// This is more verbose than the default positional `[[v, n], ...]`
+    /// format, but self-describing, which suits tools not built against
+    /// this crate.
+    pub fn to_labeled_json(&self) -> serde_json::Result<String>
+    where
+        V: Serialize,
+    {
+        let samples: Vec<LabeledSample<&V>> = self
+            .samples
+            .iter()
+            .map(|(value, count)| LabeledSample {
+                value,
+                count: *count,
+            })
+            .collect();
+        serde_json::to_string(&samples)
+    }
+
+    /// Parses JSON written by [`ECDF::to_labeled_json`].
+    ///
+    /// Samples may be in any order, and counts for the same value are added
+    /// together.
+    pub fn from_labeled_json<'de>(json: &'de str) -> serde_json::Result<Self>
+    where
+        V: Deserialize<'de> + PartialOrd + Copy,
+    {
+        let samples: Vec<LabeledSample<V>> = serde_json::from_str(json)?;
+        Ok(ECDF::from_counts(
+            samples.into_iter().map(|s| (s.value, s.count)),
+        ))
+    }
+}
+
 struct Counter<'a, V: 'a> {
     slice: &'a [V],
 }
@@ -485,7 +1262,7 @@ pub struct InterpolatedECDF<V>
 where
     V: Float + Debug,
 {
-    samples: Vec<(V, f64)>,
+    pub(crate) samples: Vec<(V, f64)>,
 }
 
 impl<V> InterpolatedECDF<V>
@@ -497,54 +1274,138 @@ where
         self.samples.iter().map(|x| x.1).sum()
     }
 
-    // TODO: Use a Result<V,?> for these functions rather than returing NaN.
-
+    /// Estimates the value at quantile `q`, interpolating between samples.
+    ///
+    /// Returns NaN if the quantile can't be calculated. Out of range values
+    /// of `q` return negative or positive infinity.
     pub fn quantile(&self, q: f64) -> V {
-        if q.is_nan() {
-            return V::nan();
-        }
-        if q < 0.0 {
-            return V::neg_infinity();
+        match self.try_quantile(q) {
+            Ok(v) => v,
+            Err(QuantileError::OutOfRange) if q < 0.0 => V::neg_infinity(),
+            Err(QuantileError::OutOfRange) if q > 1.0 => V::infinity(),
+            Err(_) => V::nan(),
         }
-        if q > 1.0 {
-            return V::infinity();
+    }
+
+    /// Estimates the value at quantile `q`, interpolating between samples.
+    pub fn try_quantile(&self, q: f64) -> Result<V, QuantileError> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(QuantileError::OutOfRange);
         }
         if self.samples.is_empty() {
-            return V::nan();
+            return Err(QuantileError::Empty);
         }
 
-        let mut rank = self.len() * q;
+        let rank = self.len() * q;
         let mut lv = self.samples[0].0;
         let first = self.samples[0].1;
         if first > rank {
             if self.samples.len() < 2 {
-                return V::nan();
+                return Err(QuantileError::InsufficientSamples);
             }
             // Find the slope between samples 0 and 1, project backwards.
             let dv = (self.samples[1].0 - lv).to_f64().unwrap();
             let dc = self.samples[1].1;
             let m = dv / dc;
-            return lv + V::from((rank - first) * m).unwrap();
-        }
-        rank -= first;
-        for &(v, count) in self.samples.iter().skip(1) {
-            let n = count;
-            if n > rank {
-                let fraction = V::from(rank / n).unwrap();
-                return lv + (v - lv) * fraction;
+            return Ok(lv + V::from((rank - first) * m).unwrap());
+        }
+        let mut sum = first;
+        for &(v, n) in self.samples.iter().skip(1) {
+            if n > rank - sum {
+                let fraction = V::from((rank - sum) / n).unwrap();
+                return Ok(lv + (v - lv) * fraction);
             }
             lv = v;
-            rank -= n;
+            sum += n;
         }
-        lv
+        Ok(lv)
     }
 
-    pub fn fraction(&self, v: V) -> f64 {
-        if v.is_nan() {
-            return f64::nan();
+    /// Iterates through the points where the interpolated ECDF changes slope.
+    /// The returned iterator generates (V, P(v <= V)) tuples.
+    pub fn point_iter(&self) -> impl Iterator<Item = (V, f64)> + '_ {
+        self.samples
+            .iter()
+            .scan((0.0, self.len()), |(sum, total), &(v, n)| {
+                *sum += n;
+                Some((v, *sum / *total))
+            })
+    }
+
+    /// Estimates the median value, interpolating between samples.
+    pub fn median(&self) -> V {
+        self.quantile(0.5)
+    }
+
+    /// Estimates the values at several quantiles in a single pass over the samples.
+    ///
+    /// The results are returned in the same order as `qs`, and match what
+    /// [InterpolatedECDF::quantile] would return for each one.
+    pub fn percentiles(&self, qs: &[f64]) -> Vec<V> {
+        let mut out = vec![V::nan(); qs.len()];
+        let mut order = Vec::with_capacity(qs.len());
+        for (i, &q) in qs.iter().enumerate() {
+            if (0.0..=1.0).contains(&q) {
+                order.push(i);
+            } else {
+                // These don't depend on the samples.
+                out[i] = self.quantile(q);
+            }
         }
         if self.samples.is_empty() {
-            return f64::nan();
+            return out;
+        }
+        order.sort_by(|&a, &b| qs[a].partial_cmp(&qs[b]).unwrap());
+
+        let total = self.len();
+        let (mut lv, first) = self.samples[0];
+        let mut sum = first;
+        let mut iter = self.samples.iter().skip(1);
+        let mut next = iter.next();
+        for i in order {
+            let rank = total * qs[i];
+            if first > rank {
+                if let Some(&(v1, c1)) = self.samples.get(1) {
+                    // Find the slope between samples 0 and 1, project backwards.
+                    let lv = self.samples[0].0;
+                    let m = (v1 - lv).to_f64().unwrap() / c1;
+                    out[i] = lv + V::from((rank - first) * m).unwrap();
+                }
+                continue;
+            }
+            loop {
+                match next {
+                    Some(&(v, n)) if n > rank - sum => {
+                        let fraction = V::from((rank - sum) / n).unwrap();
+                        out[i] = lv + (v - lv) * fraction;
+                        break;
+                    }
+                    Some(&(v, n)) => {
+                        lv = v;
+                        sum += n;
+                        next = iter.next();
+                    }
+                    None => {
+                        out[i] = lv;
+                        break;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Estimates the fraction of samples less than or equal to `v`.
+    ///
+    /// Returns NaN if the fraction can't be calculated.
+    pub fn fraction(&self, v: V) -> f64 {
+        self.try_fraction(v).unwrap_or(f64::NAN)
+    }
+
+    /// Estimates the fraction of samples less than or equal to `v`.
+    pub fn try_fraction(&self, v: V) -> Result<f64, QuantileError> {
+        if v.is_nan() {
+            return Err(QuantileError::OutOfRange);
         }
 
         let rank;
@@ -555,7 +1416,7 @@ where
                 sum = n;
                 (v, n)
             }
-            _ => return f64::nan(),
+            _ => return Err(QuantileError::Empty),
         };
         if v < last_v {
             let (next_v, next_count) = match iter.next() {
@@ -563,7 +1424,7 @@ where
                     sum += n;
                     (v, n)
                 }
-                _ => return f64::nan(),
+                _ => return Err(QuantileError::InsufficientSamples),
             };
             // Find the slope between samples 0 and 1, project backwards.
             let dv = (next_v - last_v).to_f64().unwrap();
@@ -593,7 +1454,44 @@ where
         for &(_, n) in iter {
             sum += n;
         }
-        (rank / sum).clamp(0.0, 1.0)
+        Ok((rank / sum).clamp(0.0, 1.0))
+    }
+
+    /// Estimates the number of samples in buckets with the given ascending
+    /// upper `bounds`, interpolating between samples.
+    ///
+    /// Returns `(bound, count)` pairs. The first bucket holds every sample
+    /// up to and including its bound, and each later one holds the samples
+    /// above the previous bound. Samples above the last bound aren't
+    /// counted, unless it is infinite. Unlike [InterpolatedECDF::compact],
+    /// this can move counts between values, since the buckets don't have
+    /// to line up with the samples.
+    ///
+    /// Bounds below the smallest sample count nothing, unless there are
+    /// enough samples to interpolate below it. Bounds at or above the
+    /// largest sample count everything.
+    pub fn rebin(&self, bounds: &[V]) -> Vec<(V, f64)> {
+        let (min, max) = match (self.samples.first(), self.samples.last()) {
+            (Some(&(min, _)), Some(&(max, _))) => (min, max),
+            _ => return bounds.iter().map(|&b| (b, 0.0)).collect(),
+        };
+        let len = self.len();
+        let mut below = 0.0;
+        bounds
+            .iter()
+            .map(|&b| {
+                let cumulative = if b >= max {
+                    len
+                } else if b < min && self.samples.len() < 2 {
+                    0.0
+                } else {
+                    self.fraction(b) * len
+                };
+                let count = cumulative - below;
+                below = cumulative;
+                (b, count)
+            })
+            .collect()
     }
 
     // TODO: It should be possible to turn this into an iterator using flat_map.
@@ -694,10 +1592,116 @@ where
         let self_counts = self.interpolate_counts(other.samples.iter().map(|&(v, _)| v));
         let other_counts = other.interpolate_counts(self.samples.iter().map(|&(v, _)| v));
         InterpolatedECDF {
-            samples: self_counts
+            samples: self_counts
+                .iter()
+                .zip(other_counts.iter())
+                .map(|(&(v1, c1), &(_, c2))| (v1, c1 + c2))
+                .collect(),
+        }
+    }
+
+    /// Combines several ECDFs into one, weighting each by its number of samples.
+    ///
+    /// Unlike repeatedly calling [InterpolatedECDF::merge], every member is
+    /// interpolated at the same set of points, and the counts at each point
+    /// are summed in a fixed order. The result is therefore identical no
+    /// matter what order the members are given in.
+    pub fn centroid(members: &[&InterpolatedECDF<V>]) -> InterpolatedECDF<V> {
+        let mut points: Vec<V> = members
+            .iter()
+            .flat_map(|m| m.samples.iter().map(|&(v, _)| v))
+            .collect();
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        points.dedup();
+
+        let mut counts: Vec<Vec<f64>> = vec![Vec::with_capacity(members.len()); points.len()];
+        for member in members.iter() {
+            let interpolated = member.interpolate_counts(points.iter().copied());
+            debug_assert_eq!(interpolated.len(), points.len());
+            for (i, (_, n)) in interpolated.into_iter().enumerate() {
+                counts[i].push(n);
+            }
+        }
+        InterpolatedECDF {
+            samples: points
+                .into_iter()
+                .zip(counts)
+                .map(|(v, mut ns)| {
+                    ns.sort_by(f64::total_cmp);
+                    (v, ns.into_iter().sum())
+                })
+                .collect(),
+        }
+    }
+
+    /// Drops points until there are at most `target_size`, like [ECDF::compact].
+    ///
+    /// The points dropped are those that interpolating between their
+    /// neighbours predicts best, and each one's count is added to the next
+    /// greater point. The first and last points are always kept.
+    pub fn compact(&mut self, target_size: usize) {
+        let target_size = target_size.max(3);
+        if self.samples.len() <= target_size {
+            return;
+        }
+        // The error of dropping the point at `i`, which has a neighbour on each side.
+        let err = |samples: &[(V, f64)], i: usize| {
+            let x0 = samples[i - 1].0;
+            let (x1, y1) = samples[i];
+            let (x2, y2) = samples[i + 1];
+            let y = ((x1 - x0) / (x2 - x0)).to_f64().unwrap() * (y1 + y2);
+            (y1 - y).abs()
+        };
+        // errs[i] is the error of dropping samples[i + 1].
+        let mut errs: Vec<f64> = (1..self.samples.len() - 1)
+            .map(|i| err(&self.samples, i))
+            .collect();
+        while self.samples.len() > target_size {
+            let (best, _) = errs
+                .iter()
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap();
+            errs.remove(best);
+            let (_, n) = self.samples.remove(best + 1);
+            self.samples[best + 1].1 += n;
+            // Recompute the error of points next to the removed sample.
+            if best > 0 {
+                errs[best - 1] = err(&self.samples, best);
+            }
+            if best < errs.len() {
+                errs[best] = err(&self.samples, best + 1);
+            }
+        }
+    }
+
+    /// Returns a copy of this ECDF with a uniform probability floor added across its support.
+    ///
+    /// A fraction `epsilon` of the total count is redistributed evenly between
+    /// the smallest and largest values, so that no interval within the support
+    /// has zero density. The total count is unchanged. ECDFs with fewer than
+    /// two distinct values are returned as-is.
+    pub fn smoothed(&self, epsilon: f64) -> InterpolatedECDF<V> {
+        assert!(
+            (0.0..=1.0).contains(&epsilon),
+            "epsilon must be between 0 and 1"
+        );
+        let (first, last) = match (self.samples.first(), self.samples.last()) {
+            (Some(&(first, _)), Some(&(last, _))) if last > first => (first, last),
+            _ => return self.clone(),
+        };
+        let total = self.len();
+        let range = (last - first).to_f64().unwrap();
+        let mut lv = first;
+        InterpolatedECDF {
+            samples: self
+                .samples
                 .iter()
-                .zip(other_counts.iter())
-                .map(|(&(v1, c1), &(_, c2))| (v1, c1 + c2))
+                .map(|&(v, n)| {
+                    let width = (v - lv).to_f64().unwrap();
+                    lv = v;
+                    (v, (1.0 - epsilon) * n + epsilon * total * width / range)
+                })
                 .collect(),
         }
     }
@@ -784,6 +1788,21 @@ where
         }
         sum
     }
+
+    /// Whether the two ECDFs are the same to within `tolerance`.
+    ///
+    /// Both ECDFs are interpolated at the union of their points, and the
+    /// counts at each point must differ by no more than `tolerance`. This
+    /// means that ECDFs describing the same curve with different points are
+    /// considered equal.
+    pub fn approx_eq(&self, other: &Self, tolerance: f64) -> bool {
+        let a = self.interpolate_counts(other.samples.iter().map(|&(v, _)| v));
+        let b = other.interpolate_counts(self.samples.iter().map(|&(v, _)| v));
+        a.len() == b.len()
+            && a.iter()
+                .zip(b.iter())
+                .all(|(&(v1, c1), &(v2, c2))| v1 == v2 && (c1 - c2).abs() <= tolerance)
+    }
 }
 
 impl<V> Serialize for InterpolatedECDF<V>
@@ -828,6 +1847,43 @@ mod tests {
         assert_eq!(x.len(), 0);
     }
 
+    #[test]
+    fn collect_values() {
+        let x: ECDF<i32> = vec![1, 1, 3, 3, 2, 10, 3, 2, 1].into_iter().collect();
+        assert_eq!(&x.samples.as_slice(), &[(1, 3), (2, 2), (3, 3), (10, 1)]);
+        let empty: ECDF<i32> = std::iter::empty::<i32>().collect();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn collect_counts() {
+        let x: ECDF<i32> = vec![(3, 1), (1, 2), (3, 2), (2, 1)].into_iter().collect();
+        assert_eq!(&x.samples.as_slice(), &[(1, 2), (2, 1), (3, 3)]);
+        assert_eq!(x.len(), 6);
+    }
+
+    #[test]
+    fn from_counts() {
+        let x = ECDF::from_counts([(2.5, 1), (-1.0, 4), (2.5, 2), (0.0, 0), (-1.0, 1)]);
+        assert_eq!(&x.samples.as_slice(), &[(-1.0, 5), (0.0, 0), (2.5, 3)]);
+        assert_eq!(x.len(), 8);
+        assert!(ECDF::<f64>::from_counts([]).is_empty());
+    }
+
+    #[test]
+    fn equality() {
+        let a = ECDF::from(vec![3, 1, 2, 1]);
+        assert_eq!(a, ECDF::from_counts([(1, 2), (2, 1), (3, 1)]));
+        assert_ne!(a, ECDF::from(vec![1, 2, 3]));
+        assert_ne!(a, ECDF::default());
+    }
+
+    #[test]
+    fn into_iter() {
+        let x = ECDF::from(vec![1, 1, 3, 3, 2, 10, 3, 2, 1]);
+        itertools::assert_equal(x, [(1, 3), (2, 2), (3, 3), (10, 1)].into_iter());
+    }
+
     #[test]
     fn count_sorted() {
         let v: Vec<i32> = vec![1, 1, 2, 3, 3, 3];
@@ -842,6 +1898,95 @@ mod tests {
         assert_eq!(x.len(), 9);
     }
 
+    #[test]
+    fn min_max() {
+        let empty = ECDF::<i32>::default();
+        assert_eq!(empty.min(), None);
+        assert_eq!(empty.max(), None);
+
+        let one = ECDF::from(vec![7]);
+        assert_eq!(one.min(), Some(7));
+        assert_eq!(one.max(), Some(7));
+
+        let many = ECDF::from(vec![3, 1, 4, 1, 5, 9, 2, 6]);
+        assert_eq!(many.min(), Some(1));
+        assert_eq!(many.max(), Some(9));
+    }
+
+    #[test]
+    fn open_tail() {
+        let mut x = OpenTailECDF::new(10);
+        for v in [1, 2, 2, 10, 11, 50, 1000] {
+            x.add(v);
+        }
+        assert_eq!(x.len(), 7);
+        assert_eq!(x.overflow(), 3);
+        assert_eq!(x.body().samples, vec![(1, 1), (2, 2), (10, 1)]);
+        assert_eq!(x.try_quantile(0.0), Ok(TailQuantile::Value(1)));
+        assert_eq!(x.try_quantile(0.5), Ok(TailQuantile::Value(10)));
+        assert_eq!(x.try_quantile(0.6), Ok(TailQuantile::Overflow(10)));
+        assert_eq!(x.try_quantile(1.0), Ok(TailQuantile::Overflow(10)));
+        assert_eq!(x.try_quantile(1.5), Err(QuantileError::OutOfRange));
+
+        x.clear();
+        assert!(x.is_empty());
+        assert_eq!(x.try_quantile(0.5), Err(QuantileError::Empty));
+    }
+
+    #[test]
+    fn map_values() {
+        let x = ECDF {
+            samples: vec![(1.0, 1), (10.0, 1), (100.0, 1)],
+        };
+        assert_eq!(
+            x.map_values(f64::log10).samples,
+            vec![(0.0, 1), (1.0, 1), (2.0, 1)]
+        );
+
+        // Not monotonic, and -2 and 2 collide.
+        let y = ECDF::from(vec![-2, -1, 0, 2, 2]);
+        assert_eq!(
+            y.map_values(|v: i32| v.abs()).samples,
+            vec![(0, 1), (1, 1), (2, 3)]
+        );
+    }
+
+    #[test]
+    fn slo_budget() {
+        // 95 of 100 requests are faster than 200ms.
+        let x: ECDF<f64> = (0..100)
+            .map(|i| if i < 95 { 100.0 } else { 300.0 })
+            .collect();
+        assert_eq!(x.fraction_below(200.0), 0.95);
+        assert_almost_eq!(x.slo_budget_remaining(200.0, 0.99), -4.0, 1e-9);
+        assert_almost_eq!(x.slo_budget_remaining(200.0, 0.9), 0.5, 1e-9);
+        assert_eq!(x.slo_budget_remaining(300.0, 0.99), 1.0);
+        assert!(ECDF::<f64>::default().fraction_below(200.0).is_nan());
+        assert!(ECDF::<f64>::default()
+            .slo_budget_remaining(200.0, 0.99)
+            .is_nan());
+
+        // With a target of 1, any miss overspends the budget.
+        assert_eq!(x.slo_budget_remaining(300.0, 1.0), 1.0);
+        assert_eq!(x.slo_budget_remaining(200.0, 1.0), f64::NEG_INFINITY);
+        assert!(ECDF::<f64>::default()
+            .slo_budget_remaining(200.0, 1.0)
+            .is_nan());
+
+        assert_almost_eq!(x.slo_budget_remaining(200.0, 0.0), 0.95, 1e-9);
+        for target in [-0.1, 1.1, f64::NAN] {
+            assert!(x.slo_budget_remaining(200.0, target).is_nan(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn sum() {
+        assert_eq!(ECDF::<i32>::default().sum(), 0.0);
+        let x: ECDF<i32> = ECDF::from(vec![1, 1, 2, 3, 5, 8]);
+        assert_eq!(x.sum(), 20.0);
+        assert_eq!(x.len(), 6);
+    }
+
     #[test]
     fn stats() {
         let x: ECDF<i32> = ECDF::from(vec![1, 1, 2, 3, 5, 8]);
@@ -851,6 +1996,23 @@ mod tests {
         assert_eq!(count, 6);
     }
 
+    #[test]
+    fn robust_stats() {
+        let symmetric = ECDF::from((1..=99).map(|x| x as f64).collect::<Vec<_>>());
+        let (mean, _, _) = symmetric.stats();
+        assert_eq!(symmetric.iqr(), 49.5);
+        assert_almost_eq!(symmetric.trimmed_mean(0.1, 0.9), mean, 1.0);
+        assert_eq!(symmetric.trimmed_mean(0.0, 1.0), mean);
+
+        let skewed = ECDF::from((1..=20).map(|x| (x * x) as f64).collect::<Vec<_>>());
+        let (mean, _, _) = skewed.stats();
+        assert_eq!(skewed.trimmed_mean(0.1, 0.9), 124.0);
+        assert!(skewed.trimmed_mean(0.1, 0.9) < mean);
+
+        assert!(ECDF::<f64>::default().iqr().is_nan());
+        assert!(ECDF::<f64>::default().trimmed_mean(0.1, 0.9).is_nan());
+    }
+
     #[test]
     fn insert() {
         let mut x: ECDF<i32> = ECDF::default();
@@ -955,6 +2117,77 @@ mod tests {
             &[(0, 1), (1, 1), (2, 3), (3, 1), (4, 1), (10, 2)]
         );
         assert_eq!(y.len(), 9);
+
+        // Repeated values are combined.
+        let mut z = ECDF::from(vec![5]);
+        z.merge_sorted([(5, 1), (5, 2), (6, 1), (7, 1), (7, 1)].into_iter());
+        assert_eq!(&z.samples.as_slice(), &[(5, 4), (6, 1), (7, 2)]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "merge_sorted")]
+    fn merge_sorted_out_of_order() {
+        let mut x = ECDF::from(vec![1, 2, 3]);
+        x.merge_sorted([(3, 1), (0, 1)].into_iter());
+    }
+
+    #[test]
+    fn merge_unsorted() {
+        let mut x = ECDF::from(vec![1, 2, 3]);
+        // Descending, as negative histogram buckets are produced.
+        x.merge_unsorted([(-1, 1), (-2, 2), (-4, 1)]);
+        x.merge_unsorted([(3, 1), (0, 1), (3, 2)]);
+        assert_eq!(
+            &x.samples.as_slice(),
+            &[(-4, 1), (-2, 2), (-1, 1), (0, 1), (1, 1), (2, 1), (3, 4)]
+        );
+    }
+
+    #[test]
+    fn merge_overlapping() {
+        let a = ECDF {
+            samples: vec![(1, 1), (2, 1), (3, 1)],
+        };
+        let b = ECDF {
+            samples: vec![(0, 1), (2, 2), (4, 1), (10, 2)],
+        };
+        let c = a.merge(&b);
+        assert_eq!(
+            &c.samples.as_slice(),
+            &[(0, 1), (1, 1), (2, 3), (3, 1), (4, 1), (10, 2)]
+        );
+        assert_eq!(c.len(), a.len() + b.len());
+        assert_eq!(b.merge(&a).samples, c.samples);
+    }
+
+    #[test]
+    fn merge_disjoint() {
+        let a = ECDF {
+            samples: vec![(1, 1), (2, 1)],
+        };
+        let b = ECDF {
+            samples: vec![(5, 2), (6, 1)],
+        };
+        assert_eq!(
+            &a.merge(&b).samples.as_slice(),
+            &[(1, 1), (2, 1), (5, 2), (6, 1)]
+        );
+        assert_eq!(
+            &b.merge(&a).samples.as_slice(),
+            &[(1, 1), (2, 1), (5, 2), (6, 1)]
+        );
+    }
+
+    #[test]
+    fn merge_empty() {
+        let empty = ECDF::<i32>::default();
+        let a = ECDF {
+            samples: vec![(1, 1), (2, 1)],
+        };
+        assert_eq!(a.merge(&empty).samples, a.samples);
+        assert_eq!(empty.merge(&a).samples, a.samples);
+        assert!(empty.merge(&empty).is_empty());
     }
 
     /// Verifies correct behavior when samples are in a straight line.
@@ -999,6 +2232,35 @@ mod tests {
         assert_eq!(x.len(), 5);
     }
 
+    /// Verifies that relative compaction keeps more detail at the low end.
+    #[test]
+    fn compact_relative() {
+        let samples: Vec<(i64, usize)> = (0..20).map(|k| (1 << k, 1)).collect();
+        let mut absolute = ECDF {
+            samples: samples.clone(),
+        };
+        absolute.compact(8);
+        let mut relative = ECDF { samples };
+        relative.compact_relative(8);
+        assert_eq!(
+            &relative.samples.as_slice(),
+            &[
+                (1, 1),
+                (2, 1),
+                (8, 2),
+                (32, 2),
+                (128, 2),
+                (2048, 4),
+                (32768, 4),
+                (524288, 4)
+            ]
+        );
+        assert_eq!(relative.len(), 20);
+        let low = |x: &ECDF<i64>| x.samples.iter().filter(|&&(v, _)| v < 1000).count();
+        assert_eq!(low(&absolute), 3);
+        assert_eq!(low(&relative), 5);
+    }
+
     /// Performs compactions with non-zero errors.
     #[test]
     fn compact_non_zero() {
@@ -1038,6 +2300,60 @@ mod tests {
         assert!(p > 0.99, "Expected p > 0.99, was {}", p);
     }
 
+    #[test]
+    fn directed_ks() {
+        let normal = Normal::new(0.0, 1.0).unwrap();
+        let n = 100;
+        let shifted = |shift: f64| {
+            ECDF::from(
+                (1..=n)
+                    .map(|i| normal.inverse_cdf((i as f64 - 0.5) / n as f64) + shift)
+                    .collect::<Vec<f64>>(),
+            )
+        };
+
+        let right = shifted(0.5);
+        let p = right.drawn_from_distribution_directed(|x| normal.cdf(x), KsSide::Greater);
+        assert!(p < 0.01, "Expected p < 0.01, was {}", p);
+        let p = right.drawn_from_distribution_directed(|x| normal.cdf(x), KsSide::Less);
+        assert!(p > 0.9, "Expected p > 0.9, was {}", p);
+
+        let left = shifted(-0.5);
+        let p = left.drawn_from_distribution_directed(|x| normal.cdf(x), KsSide::Greater);
+        assert!(p > 0.9, "Expected p > 0.9, was {}", p);
+        let p = left.drawn_from_distribution_directed(|x| normal.cdf(x), KsSide::Less);
+        assert!(p < 0.01, "Expected p < 0.01, was {}", p);
+
+        // Two-sided is the default.
+        assert_eq!(
+            right.drawn_from_distribution(|x| normal.cdf(x)),
+            right.drawn_from_distribution_directed(|x| normal.cdf(x), KsSide::TwoSided)
+        );
+    }
+
+    #[test]
+    fn anderson_darling_tails() {
+        let normal = Normal::new(0.0, 1.0).unwrap();
+        let n = 100;
+        let mut values: Vec<f64> = (1..=n)
+            .map(|i| normal.inverse_cdf((i as f64 - 0.5) / n as f64))
+            .collect();
+        let x = ECDF::from(values.clone());
+        let p = x.anderson_darling(|x| normal.cdf(x));
+        assert!(p > 0.99, "Expected p > 0.99, was {}", p);
+
+        // Stretch only the five most extreme values on either side.
+        for i in 0..5 {
+            values[i] *= 2.5;
+            values[n - 1 - i] *= 2.5;
+        }
+        let y = ECDF::from(values);
+        let ks = y.drawn_from_distribution(|x| normal.cdf(x));
+        assert!(ks > 0.9, "Expected KS p > 0.9, was {}", ks);
+        let ad = y.anderson_darling(|x| normal.cdf(x));
+        assert!(ad < 0.01, "Expected AD p < 0.01, was {}", ad);
+    }
+
     #[test]
     fn matches_itself() {
         let x = ECDF::from(vec![1.0, 2.0, 3.0, 4.0, 5.0]);
@@ -1053,6 +2369,33 @@ mod tests {
         assert!(p < 0.02, "Expected p < 0.02, was {}", p);
     }
 
+    #[test]
+    fn small_sample_exact() {
+        let x = ECDF::from(vec![1.0, 2.0, 3.0, 4.0, 10.0]);
+        let y = ECDF::from(vec![5.0, 6.0, 7.0, 8.0, 9.0]);
+        // D = 0.8, which has an exact p-value of 5/63.
+        assert_almost_eq!(x.drawn_from_same_distribution_as(&y), 5.0 / 63.0, 1e-9);
+    }
+
+    #[test]
+    fn ks_reject() {
+        let x = ECDF::from((0..20).collect::<Vec<i32>>());
+        let y = ECDF::from((100..120).collect::<Vec<i32>>());
+        assert_eq!(x.ks_statistic(&y), 1.0);
+        assert!(x.ks_reject(&y, 0.05));
+
+        let z = ECDF::from((0..20).collect::<Vec<i32>>());
+        assert_eq!(x.ks_statistic(&z), 0.0);
+        assert!(!x.ks_reject(&z, 0.05));
+
+        // Interleaved samples differ by at most one step.
+        let odd = ECDF::from((0..20).map(|i| 2 * i + 1).collect::<Vec<i32>>());
+        let even = ECDF::from((0..20).map(|i| 2 * i).collect::<Vec<i32>>());
+        assert_almost_eq!(odd.ks_statistic(&even), 0.05, 1e-12);
+        assert!(!odd.ks_reject(&even, 0.05));
+        assert!(!x.ks_reject(&ECDF::default(), 0.05));
+    }
+
     #[test]
     #[ignore = "flaky due to random sampling"]
     fn drawn_from_same_distribution() {
@@ -1073,11 +2416,10 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "doesn't pass due to different method of calculating p-value"]
     fn r_example() {
         // Evaluated in R as a way to check the correctness of this implementation.
         //   ks.test(c(1,2,3), "pnorm", 0, 1) -->  0.007987
-        let normal = Normal::new(2.0, 3.0).unwrap();
+        let normal = Normal::new(0.0, 1.0).unwrap();
         let x = ECDF::from(vec![1.0, 2.0, 3.0]);
         assert_almost_eq!(
             x.drawn_from_distribution(|x| normal.cdf(x)),
@@ -1086,12 +2428,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn linear_bounds() {
+        let x = ECDF::from(vec![0.0, 3.0, 3.0, 7.0, 10.0]);
+        assert_eq!(
+            x.auto_bounds(BoundStrategy::Linear, 6),
+            vec![0.0, 2.0, 4.0, 6.0, 8.0, 10.0]
+        );
+        assert_eq!(x.auto_bounds(BoundStrategy::Linear, 1), vec![10.0]);
+        assert!(x.auto_bounds(BoundStrategy::Linear, 0).is_empty());
+        assert!(ECDF::<f64>::default()
+            .auto_bounds(BoundStrategy::Linear, 5)
+            .is_empty());
+    }
+
+    #[test]
+    fn log_bounds() {
+        let x = ECDF::from(vec![-5.0, 0.0, 1.0, 20.0, 1000.0]);
+        let bounds = x.auto_bounds(BoundStrategy::Log, 4);
+        assert_eq!(bounds.len(), 4);
+        for (got, want) in bounds.into_iter().zip([1.0, 10.0, 100.0, 1000.0]) {
+            assert_almost_eq!(got, want, 1e-10);
+        }
+        let negative = ECDF::from(vec![-5.0, 0.0]);
+        assert!(negative.auto_bounds(BoundStrategy::Log, 4).is_empty());
+        assert!(negative
+            .auto_bounds(BoundStrategy::Exponential, 4)
+            .is_empty());
+    }
+
+    #[test]
+    fn exponential_bounds() {
+        let x = ECDF::from(vec![1.0, 3.0, 4.0]);
+        let bounds = x.auto_bounds(BoundStrategy::Exponential, 5);
+        assert_eq!(bounds.len(), 5);
+        let root2 = 2.0f64.sqrt();
+        for (got, want) in bounds.into_iter().zip([1.0, root2, 2.0, 2.0 * root2, 4.0]) {
+            assert_almost_eq!(got, want, 1e-10);
+        }
+
+        // Too wide for 5 boundaries even at schema -4, where each bucket
+        // spans a factor of 2^16.
+        let wide = ECDF::from(vec![1e-30, 1.0, 1e30]);
+        let bounds = wide.auto_bounds(BoundStrategy::Exponential, 5);
+        assert_eq!(bounds.len(), 5);
+        assert!(*bounds.last().unwrap() >= 1e30);
+        for (got, want) in bounds.into_iter().zip((3..=7).map(|i| 2.0f64.powi(16 * i))) {
+            assert_almost_eq!(got, want, want * 1e-10);
+        }
+    }
+
     #[test]
     fn point_iter() {
         let x = ECDF::from(vec![1, 2, 2, 3]);
         itertools::assert_equal(x.point_iter(), [(1, 0.25), (2, 0.75), (3, 1.0)].into_iter());
     }
 
+    #[test]
+    fn cumulative_iter() {
+        let x = ECDF::from(vec![1, 2, 2, 3, 3, 3]);
+        itertools::assert_equal(x.cumulative_iter(), [(1, 1), (2, 3), (3, 6)].into_iter());
+        assert_eq!(ECDF::<i32>::default().cumulative_iter().count(), 0);
+    }
+
     #[test]
     fn zip_ecdfs_interleave() {
         let a = ECDF::from(vec![1, 3, 3, 5]);
@@ -1156,6 +2555,122 @@ mod tests {
         assert_eq!(e.area_difference(&d), 0.5);
     }
 
+    #[test]
+    fn cramer_von_mises() {
+        let a = ECDF::from(vec![1, 2, 3, 4]);
+        assert_eq!(a.cramer_von_mises(&a), 0.0);
+        let b = ECDF::from(vec![1, 3, 5, 7]);
+        let c = ECDF::from(vec![2, 4, 6, 8]);
+        assert_eq!(b.cramer_von_mises(&c), 0.0625);
+        assert_eq!(c.cramer_von_mises(&b), 0.0625);
+        assert!(a.cramer_von_mises(&ECDF::default()).is_nan());
+
+        // The statistic grows as two uniform samples are pulled apart.
+        let uniform: Vec<i32> = (1..=10).collect();
+        let x = ECDF::from(uniform.clone());
+        let mut last = 0.0;
+        for shift in [2, 4, 6, 8, 10] {
+            let y = ECDF::from(uniform.iter().map(|v| v + shift).collect::<Vec<_>>());
+            let t = x.cramer_von_mises(&y);
+            assert!(t > last, "shift {}: {} <= {}", shift, t, last);
+            last = t;
+        }
+        assert_almost_eq!(last, 1.675, 1e-9);
+    }
+
+    #[test]
+    fn signed_diff() {
+        let a = ECDF::from(vec![1, 2, 3, 4]);
+        let b = ECDF::from(vec![2, 3, 4, 5]);
+        assert_eq!(a.signed_area_difference(&a), 0.0);
+        // b is shifted to the right, so a's CDF is above b's.
+        assert_eq!(a.signed_area_difference(&b), 1.0);
+        assert_eq!(b.signed_area_difference(&a), -1.0);
+        assert_eq!(a.area_difference(&b), 1.0);
+
+        // Differences in opposite directions cancel out.
+        let c = ECDF::from(vec![1, 3, 3, 4]);
+        let d = ECDF::from(vec![2, 2, 3, 4]);
+        assert_eq!(c.signed_area_difference(&d), 0.0);
+        assert_eq!(c.area_difference(&d), 0.5);
+    }
+
+    #[test]
+    fn area_difference_parts() {
+        let a = ECDF::from(vec![1, 2, 3, 4]);
+        let b = ECDF::from(vec![1, 3, 3, 4]);
+        let c = ECDF::from(vec![4, 4, 4, 4]);
+        let d = ECDF::from(vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        let e = ECDF::from(vec![2, 4, 6, 8]);
+        for (x, y) in [(&a, &a), (&a, &b), (&a, &c), (&d, &e), (&e, &d)] {
+            let (below, above) = x.area_difference_parts(y);
+            assert_almost_eq!(below + above, x.area_difference(y), 1e-9);
+            assert_eq!(y.area_difference_parts(x), (above, below));
+        }
+        assert_eq!(a.area_difference_parts(&c), (0.0, 1.5));
+        assert_eq!(e.area_difference_parts(&d), (0.5, 0.0));
+
+        // The two parts don't cancel out, unlike the signed difference.
+        let f = ECDF::from(vec![2, 2, 3, 4]);
+        assert_eq!(b.area_difference_parts(&f), (0.25, 0.25));
+    }
+
+    #[test]
+    fn wasserstein_shifted_uniform() {
+        let a = ECDF::from((0..100).map(|x| x as f64).collect::<Vec<_>>());
+        let b = ECDF::from((0..100).map(|x| x as f64 + 2.5).collect::<Vec<_>>());
+        assert_eq!(a.wasserstein_distance(&a), 0.0);
+        assert_eq!(a.wasserstein_distance(&b), 2.5);
+        assert_eq!(b.wasserstein_distance(&a), 2.5);
+        assert_almost_eq!(a.wasserstein_distance(&b), a.area_difference(&b), 1e-9);
+    }
+
+    #[test]
+    fn wasserstein_matches_area() {
+        let d = ECDF::from(vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        let e = ECDF::from(vec![2, 4, 6, 8]);
+        assert_eq!(d.wasserstein_distance(&e), 0.5);
+        assert_eq!(e.wasserstein_distance(&d), 0.5);
+
+        let a = ECDF::from(vec![1, 2, 3, 4]);
+        let c = ECDF::from(vec![4, 4, 4, 4]);
+        assert_eq!(a.wasserstein_distance(&c), a.area_difference(&c));
+        assert!(a.wasserstein_distance(&ECDF::default()).is_nan());
+    }
+
+    #[test]
+    fn divergence_identical() {
+        let a = ECDF::from(vec![1, 2, 2, 3]);
+        assert_eq!(a.total_variation(&a), 0.0);
+        assert_eq!(a.kl_divergence(&a), 0.0);
+        let b = ECDF::from(vec![3, 2, 1, 2]);
+        assert_eq!(a.total_variation(&b), 0.0);
+        assert_eq!(a.kl_divergence(&b), 0.0);
+    }
+
+    #[test]
+    fn divergence_disjoint() {
+        let a = ECDF::from(vec![1, 2]);
+        let b = ECDF::from(vec![3, 4, 5]);
+        assert_almost_eq!(a.total_variation(&b), 1.0, 1e-12);
+        assert_almost_eq!(b.total_variation(&a), 1.0, 1e-12);
+        assert_eq!(a.kl_divergence(&b), f64::INFINITY);
+        assert!(a.total_variation(&ECDF::default()).is_nan());
+        assert!(a.kl_divergence(&ECDF::default()).is_nan());
+    }
+
+    #[test]
+    fn divergence_overlap() {
+        let a = ECDF::from(vec![1, 2]);
+        let b = ECDF::from(vec![1, 1, 1, 2]);
+        assert_almost_eq!(a.total_variation(&b), 0.25, 1e-12);
+        assert_almost_eq!(a.kl_divergence(&b), (4.0f64 / 3.0).ln() / 2.0, 1e-12);
+        // a has no mass at 3, so only the divergence of a from c is finite.
+        let c = ECDF::from(vec![1, 2, 3, 3]);
+        assert_almost_eq!(a.kl_divergence(&c), 2.0f64.ln(), 1e-12);
+        assert_eq!(c.kl_divergence(&a), f64::INFINITY);
+    }
+
     #[test]
     fn identity_fraction() {
         let ecdf = ECDF::from(vec![0.5, 1.0]).interpolate();
@@ -1197,6 +2712,185 @@ mod tests {
         assert_eq!(ecdf.quantile(2.0), f64::infinity());
     }
 
+    #[test]
+    fn percentiles_match_quantile() {
+        let qs = [
+            0.9,
+            0.5,
+            0.0,
+            1.0,
+            0.5,
+            0.1,
+            0.99,
+            0.25,
+            -0.5,
+            2.0,
+            f64::nan(),
+        ];
+        for ecdf in [
+            ECDF::<f64>::default().interpolate(),
+            ECDF::from(vec![1.0]).interpolate(),
+            ECDF::from(vec![1.0, 2.0]).interpolate(),
+            ECDF::from(vec![1.0, 2.0, 2.0, 3.0, 5.0, 8.0, 13.0]).interpolate(),
+            InterpolatedECDF {
+                samples: vec![(0.0, 0.0), (1.0, 1.0), (7.0, 0.0), (9.0, 2.0)],
+            },
+        ] {
+            let got = ecdf.percentiles(&qs);
+            assert_eq!(got.len(), qs.len());
+            for (&q, &v) in qs.iter().zip(got.iter()) {
+                let want = ecdf.quantile(q);
+                assert!(
+                    v == want || (v.is_nan() && want.is_nan()),
+                    "quantile({}) = {}, percentiles gave {}",
+                    q,
+                    want,
+                    v
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn median() {
+        let ecdf = ECDF::from(vec![0.5, 1.0]).interpolate();
+        assert_eq!(ecdf.median(), 0.5);
+        assert!(ECDF::<f64>::default().interpolate().median().is_nan());
+    }
+
+    #[test]
+    fn try_quantile_errors() {
+        let empty = ECDF::<f64>::default().interpolate();
+        assert_eq!(empty.try_quantile(0.5), Err(QuantileError::Empty));
+        assert_eq!(empty.try_fraction(0.5), Err(QuantileError::Empty));
+
+        let one = ECDF::from(vec![1.0]).interpolate();
+        assert_eq!(
+            one.try_quantile(0.75),
+            Err(QuantileError::InsufficientSamples)
+        );
+        assert_eq!(
+            one.try_fraction(0.5),
+            Err(QuantileError::InsufficientSamples)
+        );
+        assert_eq!(one.try_fraction(1.0), Ok(1.0));
+
+        let ecdf = ECDF::from(vec![1.0, 2.0, 3.0, 4.0]).interpolate();
+        assert_eq!(
+            ecdf.try_quantile(f64::nan()),
+            Err(QuantileError::OutOfRange)
+        );
+        assert_eq!(ecdf.try_quantile(-0.5), Err(QuantileError::OutOfRange));
+        assert_eq!(ecdf.try_quantile(2.0), Err(QuantileError::OutOfRange));
+        assert_eq!(ecdf.try_quantile(0.75), Ok(3.0));
+        assert_eq!(
+            ecdf.try_fraction(f64::nan()),
+            Err(QuantileError::OutOfRange)
+        );
+        assert_eq!(ecdf.try_fraction(3.0), Ok(0.75));
+    }
+
+    #[test]
+    fn step_quantile() {
+        let x = ECDF::from(vec![1, 2, 2, 3]);
+        assert_eq!(x.try_quantile(0.0), Ok(1));
+        assert_eq!(x.try_quantile(0.25), Ok(1));
+        assert_eq!(x.try_quantile(0.5), Ok(2));
+        assert_eq!(x.try_quantile(0.75), Ok(2));
+        assert_eq!(x.try_quantile(0.9), Ok(3));
+        assert_eq!(x.try_quantile(1.0), Ok(3));
+        assert_eq!(x.try_quantile(1.5), Err(QuantileError::OutOfRange));
+        assert_eq!(
+            ECDF::<i32>::default().try_quantile(0.5),
+            Err(QuantileError::Empty)
+        );
+    }
+
+    #[test]
+    fn step_fraction() {
+        let x = ECDF::from(vec![1.0, 2.0, 2.0, 3.0]);
+        assert_eq!(x.try_fraction(0.0), Ok(0.0));
+        assert_eq!(x.try_fraction(1.0), Ok(0.25));
+        assert_eq!(x.try_fraction(2.5), Ok(0.75));
+        assert_eq!(x.try_fraction(3.0), Ok(1.0));
+        assert_eq!(x.try_fraction(f64::nan()), Err(QuantileError::OutOfRange));
+        assert_eq!(
+            ECDF::<f64>::default().try_fraction(1.0),
+            Err(QuantileError::Empty)
+        );
+    }
+
+    #[test]
+    fn smoothed() {
+        // The gap between 4 and 8 has no density.
+        let a = InterpolatedECDF {
+            samples: vec![(0.0, 1.0), (4.0, 3.0), (8.0, 0.0), (10.0, 4.0)],
+        };
+        let b = a.smoothed(0.1);
+        assert_eq!(b.samples.len(), a.samples.len());
+        for w in b.samples.windows(2) {
+            let density = w[1].1 / (w[1].0 - w[0].0);
+            assert!(density > 0.0);
+        }
+        assert_almost_eq!(b.len(), a.len(), 1e-9);
+        assert_almost_eq!(b.fraction(10.0), 1.0, 1e-9);
+        assert_almost_eq!(b.fraction(8.0) - b.fraction(4.0), 0.1 * 4.0 / 10.0, 1e-9);
+        let mass: f64 = b.samples.iter().map(|&(_, n)| n / b.len()).sum();
+        assert_almost_eq!(mass, 1.0, 1e-9);
+
+        // No smoothing leaves the ECDF unchanged.
+        assert_eq!(a.smoothed(0.0).samples, a.samples);
+    }
+
+    #[test]
+    fn interpolated_compact() {
+        let members: Vec<InterpolatedECDF<f64>> = (0..50)
+            .map(|i| {
+                let values = (0..20).map(|j| j as f64 * 0.37 + i as f64 * 0.11);
+                ECDF::from(values.collect::<Vec<_>>()).interpolate()
+            })
+            .collect();
+        let merged = InterpolatedECDF::centroid(&members.iter().collect::<Vec<_>>());
+        assert!(merged.samples.len() > 900);
+
+        let mut compacted = merged.clone();
+        compacted.compact(32);
+        assert_eq!(compacted.samples.len(), 32);
+        assert_eq!(compacted.samples.first().unwrap().0, 0.0);
+        assert_eq!(
+            compacted.samples.last().unwrap().0,
+            merged.samples.last().unwrap().0
+        );
+        assert_almost_eq!(compacted.len(), merged.len(), 1e-9);
+        let diff = merged.area_difference(&compacted);
+        assert!(diff < 0.01, "area difference was {}", diff);
+
+        // Compacting to a larger size does nothing.
+        let mut same = compacted.clone();
+        same.compact(100);
+        assert_eq!(same.samples, compacted.samples);
+    }
+
+    #[test]
+    fn centroid_order_independent() {
+        let a = ECDF::from(vec![0.1, 1.3, 2.0, 3.7, 4.0]).interpolate();
+        let b = ECDF::from(vec![0.7, 0.7, 2.9]).interpolate();
+        let c = ECDF::from(vec![1.1, 3.3, 3.4, 5.0, 8.5, 9.0]).interpolate();
+        let expected = InterpolatedECDF::centroid(&[&a, &b, &c]);
+        assert_almost_eq!(expected.len(), a.len() + b.len() + c.len(), 1e-9);
+        for members in [
+            [&a, &c, &b],
+            [&b, &a, &c],
+            [&b, &c, &a],
+            [&c, &a, &b],
+            [&c, &b, &a],
+        ] {
+            let actual = InterpolatedECDF::centroid(&members);
+            assert_eq!(actual.samples, expected.samples);
+        }
+        assert!(InterpolatedECDF::<f64>::centroid(&[]).samples.is_empty());
+    }
+
     #[test]
     fn merge_interpolated() {
         let a = ECDF::from(vec![0.0, 1.0, 2.0, 3.0, 4.0]).interpolate();
@@ -1238,6 +2932,27 @@ mod tests {
         assert_eq!(a.area_difference(&b), 0.3125);
     }
 
+    #[test]
+    fn interpolated_approx_eq() {
+        let a = InterpolatedECDF {
+            samples: vec![(1.0, 1.0), (2.0, 1.0), (3.0, 1.0)],
+        };
+        let b = InterpolatedECDF {
+            samples: vec![(1.0, 1.0), (2.0, 1.0001), (3.0, 1.0)],
+        };
+        assert!(a.approx_eq(&a, 0.0));
+        assert!(a.approx_eq(&b, 0.001));
+        assert!(b.approx_eq(&a, 0.001));
+        assert!(!a.approx_eq(&b, 1e-6));
+
+        // The same curve, without the point in the middle.
+        let c = InterpolatedECDF {
+            samples: vec![(1.0, 1.0), (3.0, 2.0)],
+        };
+        assert!(a.approx_eq(&c, 1e-9));
+        assert!(!a.approx_eq(&InterpolatedECDF::default(), 0.5));
+    }
+
     #[test]
     fn area_of_crossing_lines() {
         // Creates two interpolated ECDFs that cross over each other more than
@@ -1263,4 +2978,61 @@ mod tests {
         assert!((a.area_difference(&b) - 3.0).abs() < 1e-10);
         assert!((b.area_difference(&a) - 3.0).abs() < 1e-10);
     }
+
+    #[test]
+    fn labeled_json() {
+        let ecdf = ECDF::from_counts([(1, 3), (2, 2)]);
+        let json = ecdf.to_labeled_json().unwrap();
+        assert_eq!(json, r#"[{"value":1,"count":3},{"value":2,"count":2}]"#);
+        assert_eq!(ECDF::<i32>::from_labeled_json(&json).unwrap(), ecdf);
+
+        // The compact default format is unchanged.
+        assert_eq!(serde_json::to_string(&ecdf).unwrap(), "[[1,3],[2,2]]");
+
+        let ecdf: ECDF<f64> = vec![0.5, 0.25, 0.5, 2.0].into();
+        let json = ecdf.to_labeled_json().unwrap();
+        assert_eq!(ECDF::<f64>::from_labeled_json(&json).unwrap(), ecdf);
+
+        // Samples are sorted and coalesced.
+        let parsed = ECDF::<i32>::from_labeled_json(
+            r#"[{"value":2,"count":1},{"value":1,"count":3},{"value":2,"count":1}]"#,
+        )
+        .unwrap();
+        assert_eq!(parsed, ECDF::from_counts([(1, 3), (2, 2)]));
+    }
+
+    #[test]
+    fn rebin() {
+        let ecdf = InterpolatedECDF {
+            samples: vec![(0.5, 1.0), (1.5, 2.0), (2.5, 1.0)],
+        };
+        assert_eq!(
+            ecdf.rebin(&[0.0, 1.0, 2.0, 3.0]),
+            vec![(0.0, 0.0), (1.0, 2.0), (2.0, 1.5), (3.0, 0.5)]
+        );
+        // Bounds outside the data count nothing, or everything.
+        assert_eq!(ecdf.rebin(&[-5.0, 10.0]), vec![(-5.0, 0.0), (10.0, 4.0)]);
+        // Samples above the last bound are dropped.
+        assert_eq!(ecdf.rebin(&[1.0]), vec![(1.0, 2.0)]);
+        assert_eq!(
+            ecdf.rebin(&[1.0, f64::INFINITY]),
+            vec![(1.0, 2.0), (f64::INFINITY, 2.0)]
+        );
+        assert!(ecdf.rebin(&[]).is_empty());
+
+        // Including the edges of the interpolated range.
+        assert_eq!(ecdf.rebin(&[-1.0, 0.5]), vec![(-1.0, 0.0), (0.5, 1.0)]);
+        assert_eq!(ecdf.rebin(&[2.5]), vec![(2.5, 4.0)]);
+
+        let single = InterpolatedECDF {
+            samples: vec![(2.0, 3.0)],
+        };
+        assert_eq!(
+            single.rebin(&[1.0, 2.0, 3.0]),
+            vec![(1.0, 0.0), (2.0, 3.0), (3.0, 0.0)]
+        );
+
+        let empty = InterpolatedECDF::<f64>::default();
+        assert_eq!(empty.rebin(&[1.0, 2.0]), vec![(1.0, 0.0), (2.0, 0.0)]);
+    }
 }
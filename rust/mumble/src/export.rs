@@ -0,0 +1,87 @@
+// Exports ECDFs to columnar formats for offline analysis.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::ecdf::ECDF;
+use arrow::array::{ArrayRef, Float64Array, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+
+/// The schema of record batches produced by [ecdfs_to_arrow].
+pub fn ecdf_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("value", DataType::Float64, false),
+        Field::new("count", DataType::UInt64, false),
+    ]))
+}
+
+/// Flattens a collection of ECDFs into a single record batch.
+///
+/// Each distinct sample value becomes one `(id, value, count)` row, so the
+/// result can be written out as Parquet or loaded into a dataframe.
+pub fn ecdfs_to_arrow(ids_and_ecdfs: &[(String, ECDF<f64>)]) -> RecordBatch {
+    let rows = ids_and_ecdfs
+        .iter()
+        .map(|(_, ecdf)| ecdf.samples.len())
+        .sum();
+    let mut ids = Vec::with_capacity(rows);
+    let mut values = Vec::with_capacity(rows);
+    let mut counts = Vec::with_capacity(rows);
+    for (id, ecdf) in ids_and_ecdfs.iter() {
+        for &(v, n) in ecdf.samples.iter() {
+            ids.push(id.as_str());
+            values.push(v);
+            counts.push(n as u64);
+        }
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(ids)),
+        Arc::new(Float64Array::from(values)),
+        Arc::new(UInt64Array::from(counts)),
+    ];
+    RecordBatch::try_new(ecdf_schema(), columns).expect("columns match schema")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_ecdfs() {
+        let batch = ecdfs_to_arrow(&[
+            ("a".to_string(), ECDF::from(vec![1.0, 2.0, 2.0])),
+            ("b".to_string(), ECDF::from(vec![3.0, 4.0, 5.0])),
+        ]);
+        assert_eq!(batch.schema(), ecdf_schema());
+        assert_eq!(batch.num_columns(), 3);
+        assert_eq!(batch.num_rows(), 5);
+
+        let counts = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(counts.values().to_vec(), vec![1, 2, 1, 1, 1]);
+    }
+
+    #[test]
+    fn empty() {
+        let batch = ecdfs_to_arrow(&[]);
+        assert_eq!(batch.num_rows(), 0);
+        assert_eq!(batch.schema(), ecdf_schema());
+    }
+}
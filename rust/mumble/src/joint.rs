@@ -14,10 +14,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::mesh::{Mesh, Point};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Point<P> {
+    pub x: P,
+    pub y: P,
+}
 
 pub struct JointECDF {
-    mesh: Mesh<f64, f64>,
+    /// Sorted by `A`, then `B`.
+    samples: Vec<(Point<f64>, usize)>,
+    /// The same samples, sorted by `B`, then `A`.
+    by_b: Vec<(Point<f64>, usize)>,
+    total: usize,
 }
 
 impl JointECDF {
@@ -28,28 +38,144 @@ impl JointECDF {
         }
     }
 
-    pub fn p<A, B>(&self, _a: A, _b: B) -> f64 {
-        0.0
+    /// Returns the probability that `A` is less than or equal to `a` and
+    /// `B` is less than or equal to `b`.
+    pub fn p<A, B>(&self, a: A, b: B) -> f64
+    where
+        A: Into<f64>,
+        B: Into<f64>,
+    {
+        self.count(a.into(), b.into()) as f64 / self.total as f64
+    }
+
+    /// Counts the samples where `A` is less than or equal to `a` and `B` is
+    /// less than or equal to `b`.
+    fn count(&self, a: f64, b: f64) -> usize {
+        // Samples are sorted by A first, so only a prefix needs to be checked.
+        let end = self.samples.partition_point(|(p, _)| p.x <= a);
+        self.samples[..end]
+            .iter()
+            .filter(|(p, _)| p.y <= b)
+            .map(|&(_, n)| n)
+            .sum()
+    }
+
+    /// Calculates the sample means of `A` and `B`.
+    fn means(&self) -> (f64, f64) {
+        let mut sum_a = 0.0;
+        let mut sum_b = 0.0;
+        for &(p, n) in &self.samples {
+            sum_a += p.x * (n as f64);
+            sum_b += p.y * (n as f64);
+        }
+        let total = self.total as f64;
+        (sum_a / total, sum_b / total)
+    }
+
+    /// Calculates the sample covariance of `A` and `B`.
+    ///
+    /// Returns NaN if there are fewer than two samples.
+    pub fn covariance(&self) -> f64 {
+        if self.total < 2 {
+            return f64::NAN;
+        }
+        let (mean_a, mean_b) = self.means();
+        let mut sum = 0.0;
+        for &(p, n) in &self.samples {
+            sum += (p.x - mean_a) * (p.y - mean_b) * (n as f64);
+        }
+        sum / ((self.total - 1) as f64)
+    }
+
+    /// Calculates the Pearson correlation coefficient of `A` and `B`.
+    ///
+    /// See:
+    /// https://en.wikipedia.org/wiki/Pearson_correlation_coefficient
+    pub fn correlation(&self) -> f64 {
+        let (mean_a, mean_b) = self.means();
+        let mut sum_ab = 0.0;
+        let mut sum_aa = 0.0;
+        let mut sum_bb = 0.0;
+        for &(p, n) in &self.samples {
+            let da = p.x - mean_a;
+            let db = p.y - mean_b;
+            let n = n as f64;
+            sum_ab += da * db * n;
+            sum_aa += da * da * n;
+            sum_bb += db * db * n;
+        }
+        sum_ab / (sum_aa * sum_bb).sqrt()
     }
 
     /// Returns the probability distribution for `B` given that `A` is less than or equal to `a`.
+    ///
+    /// The distribution is NaN everywhere if no samples have `A` less than or equal to `a`.
     pub fn given_a<'a, A, B>(&'a self, a: A) -> impl Fn(B) -> f64 + 'a
     where
         A: Into<f64>,
         B: Into<f64>,
     {
         let aa: f64 = a.into();
-        move |b| self.p(aa, b)
+        let below = self.count(aa, f64::INFINITY) as f64;
+        move |b| self.count(aa, b.into()) as f64 / below
     }
 
     /// Returns the probability distribution `A` given an observed value `b`.
+    ///
+    /// This uses the samples with the value of `B` closest to `b`, so values
+    /// outside the sampled range are clamped to the nearest slice. The
+    /// distribution is NaN everywhere if there are no samples.
     pub fn given_b<'a, A, B>(&'a self, b: B) -> impl Fn(A) -> f64 + 'a
     where
         A: Into<f64>,
         B: Into<f64>,
     {
-        let bb: f64 = b.into();
-        move |a| self.p(a, bb)
+        let slice = self.slice(b.into());
+        let total = slice.iter().map(|&(_, n)| n).sum::<usize>() as f64;
+        move |a| {
+            let a = a.into();
+            // The slice is sorted by A, so only a prefix needs to be counted.
+            let end = slice.partition_point(|(p, _)| p.x <= a);
+            let below: usize = slice[..end].iter().map(|&(_, n)| n).sum();
+            below as f64 / total
+        }
+    }
+
+    /// Estimates the value of `A` at quantile `q`, given an observed value `b`.
+    ///
+    /// Like [JointECDF::given_b], this uses the samples with the value of `B`
+    /// closest to `b`. Returns NaN if there are no samples.
+    pub fn conditional_quantile(&self, b: f64, q: f64) -> f64 {
+        let slice = self.slice(b);
+        let total: usize = slice.iter().map(|&(_, n)| n).sum();
+        let rank = (q * total as f64).ceil().max(1.0) as usize;
+        let mut sum = 0;
+        for &(p, n) in slice {
+            sum += n;
+            if sum >= rank {
+                return p.x;
+            }
+        }
+        slice.last().map_or(f64::NAN, |(p, _)| p.x)
+    }
+
+    /// Returns the samples with the value of `B` closest to `b`, sorted by `A`.
+    ///
+    /// If `b` is exactly halfway between two sampled values, the lower one is used.
+    fn slice(&self, b: f64) -> &[(Point<f64>, usize)] {
+        let samples = &self.by_b;
+        let i = samples.partition_point(|(p, _)| p.y < b);
+        let above = samples.get(i).map(|(p, _)| p.y);
+        let below = i.checked_sub(1).map(|j| samples[j].0.y);
+        let y = match (below, above) {
+            (Some(lo), Some(hi)) if hi - b < b - lo => hi,
+            (Some(lo), _) => lo,
+            (None, Some(hi)) => hi,
+            (None, None) => return &[],
+        };
+        let start = samples.partition_point(|(p, _)| p.y < y);
+        let end = samples.partition_point(|(p, _)| p.y <= y);
+        &samples[start..end]
     }
 }
 
@@ -86,12 +212,184 @@ impl Builder {
         self.add_n(p, 1)
     }
 
+    /// Creates a builder from samples in any order, adding together the
+    /// counts for repeated points.
+    ///
+    /// Returns `None` if any sample is NaN, since they can't be ordered.
+    fn from_samples(mut samples: Vec<(Point<f64>, usize)>) -> Option<Builder> {
+        if samples.iter().any(|(p, _)| p.x.is_nan() || p.y.is_nan()) {
+            return None;
+        }
+        samples.sort_by(|(p, _), (q, _)| p.partial_cmp(q).unwrap());
+        samples.dedup_by(|(p, n), (q, m)| {
+            if p == q {
+                *m += *n;
+                true
+            } else {
+                false
+            }
+        });
+        let total = samples.iter().map(|x| x.1).sum();
+        Some(Builder { samples, total })
+    }
+
     pub fn build(self) -> JointECDF {
-        let t = self.total as f64;
-        let mut m = Mesh::default();
-        for (p, v) in self.samples.into_iter() {
-            m = m.add_vertex(p, (v as f64) / t);
+        let mut by_b = self.samples.clone();
+        by_b.sort_by(|(p, _), (q, _)| (p.y, p.x).partial_cmp(&(q.y, q.x)).unwrap());
+        JointECDF {
+            samples: self.samples,
+            by_b,
+            total: self.total,
         }
-        JointECDF { mesh: m }
+    }
+}
+
+impl Serialize for Builder {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.samples.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Builder {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let samples: Vec<(Point<f64>, usize)> = Vec::deserialize::<D>(deserializer)?;
+        Builder::from_samples(samples).ok_or_else(|| serde::de::Error::custom("sample is NaN"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_builder() {
+        let mut b = JointECDF::builder();
+        b.add(1.0, 2.0);
+        b.add(3.0, 4.0);
+        b.add(1.0, 2.0);
+        b.add(0.5, 8.0);
+
+        let json = serde_json::to_string(&b).unwrap();
+        let c: Builder = serde_json::from_str(&json).unwrap();
+        assert_eq!(b.samples, c.samples);
+        assert_eq!(b.total, c.total);
+        assert_eq!(c.total, 4);
+
+        let (x, y) = (b.build(), c.build());
+        assert_eq!(x.p(1.0, 2.0), y.p(1.0, 2.0));
+        assert_eq!(x.p(2.0, 5.0), y.p(2.0, 5.0));
+
+        // Input written by hand may be out of order and repeat points.
+        let json = r#"[[{"x":3.0,"y":4.0},1],[{"x":1.0,"y":2.0},1],
+            [{"x":0.5,"y":8.0},1],[{"x":1.0,"y":2.0},1]]"#;
+        let d: Builder = serde_json::from_str(json).unwrap();
+        assert_eq!(d.samples, y.samples);
+        assert_eq!(d.total, 4);
+
+        // JSON can't hold NaN, but other formats can.
+        let nan = Point {
+            x: f64::NAN,
+            y: 2.0,
+        };
+        assert!(Builder::from_samples(vec![(nan, 1)]).is_none());
+    }
+
+    #[test]
+    fn correlated() {
+        let mut b = JointECDF::builder();
+        for i in 0..10 {
+            b.add(i as f64, 2.0 * (i as f64) + 1.0);
+        }
+        let j = b.build();
+        assert!((j.correlation() - 1.0).abs() < 1e-10);
+        assert!((j.covariance() - 2.0 * 55.0 / 6.0).abs() < 1e-10);
+
+        let mut b = JointECDF::builder();
+        for i in 0..10 {
+            b.add(i as f64, -(i as f64));
+        }
+        assert!((b.build().correlation() + 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn covariance_too_few_samples() {
+        assert!(JointECDF::builder().build().covariance().is_nan());
+        let mut b = JointECDF::builder();
+        b.add(1.0, 2.0);
+        assert!(b.build().covariance().is_nan());
+    }
+
+    #[test]
+    fn independent() {
+        let mut b = JointECDF::builder();
+        for i in 0..10 {
+            for j in 0..10 {
+                b.add(i as f64, j as f64);
+            }
+        }
+        let j = b.build();
+        assert!(j.correlation().abs() < 1e-10);
+        assert!(j.covariance().abs() < 1e-10);
+    }
+
+    #[test]
+    fn given_a() {
+        let mut b = JointECDF::builder();
+        for i in 0..10 {
+            for j in 0..10 {
+                b.add(i as f64, j as f64);
+            }
+        }
+        let j = b.build();
+        assert_eq!(j.p(4.0, 4.0), 0.25);
+        // A and B are independent, so conditioning on A gives the marginal of B.
+        for a in [0.0, 4.5, 9.0, 100.0] {
+            let cdf = j.given_a(a);
+            assert_eq!(cdf(-1.0), 0.0);
+            assert_eq!(cdf(2.0), 0.3);
+            assert_eq!(cdf(8.5), 0.9);
+            assert_eq!(cdf(9.0), 1.0);
+        }
+        assert!(j.given_a(-1.0)(5.0).is_nan());
+    }
+
+    #[test]
+    fn given_b() {
+        let mut b = JointECDF::builder();
+        // A grows with B, with a spread of 0..10 at each value of B.
+        for rate in 1..=5 {
+            for i in 0..10 {
+                b.add((rate * 100 + i) as f64, rate as f64);
+            }
+        }
+        let j = b.build();
+        let cdf = j.given_b(3.0);
+        assert_eq!(cdf(299.0), 0.0);
+        assert_eq!(cdf(304.0), 0.5);
+        assert_eq!(cdf(309.0), 1.0);
+        assert_eq!(j.conditional_quantile(3.0, 0.5), 304.0);
+        assert_eq!(j.conditional_quantile(3.0, 0.99), 309.0);
+        assert_eq!(j.conditional_quantile(3.0, 0.0), 300.0);
+        // The closest slice is used for values between and outside the samples.
+        assert_eq!(j.conditional_quantile(3.2, 0.5), 304.0);
+        assert_eq!(j.conditional_quantile(3.5, 0.5), 304.0);
+        assert_eq!(j.conditional_quantile(3.6, 0.5), 404.0);
+        assert_eq!(j.conditional_quantile(100.0, 0.5), 504.0);
+        assert_eq!(j.conditional_quantile(-100.0, 0.5), 104.0);
+        assert_eq!(j.given_b(0.0)(104.0), 0.5);
+    }
+
+    #[test]
+    fn empty_conditionals() {
+        let j = JointECDF::builder().build();
+        assert!(j.given_b(1.0)(1.0).is_nan());
+        assert!(j.conditional_quantile(1.0, 0.5).is_nan());
+        assert!(j.given_a(1.0)(1.0).is_nan());
     }
 }
@@ -89,6 +89,184 @@ pub fn kprob(z: f64) -> f64 {
     }
 }
 
+/// Calculates the probability that a one-sided Kolmogorov-Smirnov statistic
+/// (D+ or D-) will exceed the value z = dn*sqrt(n), assuming the null
+/// hypothesis.
+///
+/// This uses the asymptotic distribution, exp(-2z^2), so like [kprob] it
+/// is only valid for "large" n.
+pub fn kprob_one_sided(z: f64) -> f64 {
+    (-2.0 * z * z).exp().min(1.0)
+}
+
+/// Samples at least this large use the asymptotic distribution in [ks_test].
+const EXACT_ONE_SAMPLE_THRESHOLD: usize = 100;
+
+/// Calculates the probability that the one-sample Kolmogorov-Smirnov
+/// statistic will be at least `d`, assuming the null hypothesis, for a
+/// sample of `n` events.
+///
+/// Like R's `ks.test`, small samples use the exact distribution (see
+/// [kolmogorov_cdf]), while larger ones use the asymptotic distribution
+/// calculated by [kprob]. Both assume there are no ties in the sample.
+pub fn ks_test(d: f64, n: usize) -> f64 {
+    if n == 0 {
+        1.0
+    } else if n < EXACT_ONE_SAMPLE_THRESHOLD {
+        (1.0 - kolmogorov_cdf(d, n)).clamp(0.0, 1.0)
+    } else {
+        kprob(d * (n as f64).sqrt())
+    }
+}
+
+/// A square matrix, scaled by a power of ten to avoid overflow.
+struct ScaledMatrix {
+    m: usize,
+    values: Vec<f64>,
+    exponent: i32,
+}
+
+impl ScaledMatrix {
+    fn multiply(&self, other: &ScaledMatrix) -> ScaledMatrix {
+        let m = self.m;
+        let mut values = vec![0.0; m * m];
+        for i in 0..m {
+            for j in 0..m {
+                values[i * m + j] = (0..m)
+                    .map(|k| self.values[i * m + k] * other.values[k * m + j])
+                    .sum();
+            }
+        }
+        ScaledMatrix {
+            m,
+            values,
+            exponent: self.exponent + other.exponent,
+        }
+    }
+
+    fn power(&self, n: usize) -> ScaledMatrix {
+        if n == 1 {
+            return ScaledMatrix {
+                m: self.m,
+                values: self.values.clone(),
+                exponent: self.exponent,
+            };
+        }
+        let half = self.power(n / 2);
+        let squared = half.multiply(&half);
+        let mut result = if n % 2 == 0 {
+            squared
+        } else {
+            self.multiply(&squared)
+        };
+        let center = (self.m / 2) * self.m + self.m / 2;
+        if result.values[center] > 1e140 {
+            result.values.iter_mut().for_each(|v| *v *= 1e-140);
+            result.exponent += 140;
+        }
+        result
+    }
+}
+
+/// The exact probability that the one-sample statistic is less than `d`.
+///
+/// Evaluated as in Marsaglia, Tsang and Wang, "Evaluating Kolmogorov's
+/// Distribution" (2003).
+fn kolmogorov_cdf(d: f64, n: usize) -> f64 {
+    let nd = n as f64 * d;
+    let k = nd as usize + 1;
+    let m = 2 * k - 1;
+    let h = k as f64 - nd;
+    let mut values = vec![0.0; m * m];
+    for i in 0..m {
+        for j in 0..m {
+            if i + 1 >= j {
+                values[i * m + j] = 1.0;
+            }
+        }
+    }
+    for i in 0..m {
+        values[i * m] -= h.powi(i as i32 + 1);
+        values[(m - 1) * m + i] -= h.powi((m - i) as i32);
+    }
+    if 2.0 * h - 1.0 > 0.0 {
+        values[(m - 1) * m] += (2.0 * h - 1.0).powi(m as i32);
+    }
+    for i in 0..m {
+        for j in 0..m {
+            if i + 1 > j {
+                for g in 1..=(i + 1 - j) {
+                    values[i * m + j] /= g as f64;
+                }
+            }
+        }
+    }
+    let q = ScaledMatrix {
+        m,
+        values,
+        exponent: 0,
+    }
+    .power(n);
+    let mut s = q.values[(k - 1) * m + k - 1];
+    let mut exponent = q.exponent;
+    for i in 1..=n {
+        s = s * i as f64 / n as f64;
+        if s < 1e-140 {
+            s *= 1e140;
+            exponent -= 140;
+        }
+    }
+    s * 10f64.powi(exponent)
+}
+
+/// Samples at least this large use the asymptotic distribution in [ks2prob].
+const EXACT_THRESHOLD: usize = 35;
+
+/// Calculates the probability that the two-sample Kolmogorov-Smirnov
+/// statistic will be at least `d`, assuming the null hypothesis, for
+/// samples of `n` and `m` events.
+///
+/// For small samples the exact probability is calculated by counting the
+/// paths through the lattice of merged samples that stay within `d` of the
+/// diagonal (Hodges, 1957), assuming there are no ties. Otherwise this
+/// falls back to the asymptotic distribution calculated by [kprob].
+pub fn ks2prob(d: f64, n: usize, m: usize) -> f64 {
+    if n == 0 || m == 0 {
+        1.0
+    } else if n < EXACT_THRESHOLD && m < EXACT_THRESHOLD {
+        1.0 - smirnov_cdf(d, n, m)
+    } else {
+        kprob(d * ((n * m) as f64 / (n + m) as f64).sqrt())
+    }
+}
+
+/// The exact probability that the two-sample statistic is less than `d`.
+fn smirnov_cdf(d: f64, n: usize, m: usize) -> f64 {
+    let (m, n) = if m > n { (n, m) } else { (m, n) };
+    let (md, nd) = (m as f64, n as f64);
+    // The statistic is always a multiple of 1/mn, so place the limit halfway
+    // between multiples to avoid rounding problems.
+    let q = (0.5 + (d * md * nd - 1e-7).floor()) / (md * nd);
+    // u[j] is the probability of a path reaching (i, j) without leaving the band.
+    let mut u = vec![0.0; n + 1];
+    u[0] = 1.0;
+    for j in 1..=n {
+        u[j] = if j as f64 / nd > q { 0.0 } else { u[j - 1] };
+    }
+    for i in 1..=m {
+        let w = i as f64 / (i + n) as f64;
+        u[0] = if i as f64 / md > q { 0.0 } else { w * u[0] };
+        for j in 1..=n {
+            u[j] = if (i as f64 / md - j as f64 / nd).abs() > q {
+                0.0
+            } else {
+                w * u[j] + u[j - 1]
+            };
+        }
+    }
+    u[n]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,4 +296,56 @@ mod tests {
             assert_eq!(nint(f), i, "nint({}) != {}", f, i);
         }
     }
+
+    #[test]
+    fn exact_one_sample() {
+        // Checked against R's ks.test, which uses the same method.
+        const TEST_CASES: [(f64, usize, f64); 3] = [
+            (0.8413447460685429, 3, 0.007987178),
+            (0.2, 50, 0.031438778),
+            (0.1, 99, 0.257778046),
+        ];
+        for (d, n, want) in TEST_CASES {
+            let got = ks_test(d, n);
+            assert!((got - want).abs() < 1e-8, "ks_test({}, {}) = {}", d, n, got);
+        }
+        assert_eq!(ks_test(0.5, 0), 1.0);
+    }
+
+    #[test]
+    fn exact_two_sample() {
+        // Exact values, from enumerating all orderings of the merged samples.
+        const TEST_CASES: [(f64, usize, usize, f64); 5] = [
+            (1.0, 3, 3, 0.1),
+            (0.6, 5, 5, 5.0 / 14.0),
+            (0.8, 5, 5, 5.0 / 63.0),
+            (0.5, 4, 6, 58.0 / 105.0),
+            (0.75, 8, 8, 8.0 / 429.0),
+        ];
+        for (d, n, m, want) in TEST_CASES {
+            let got = ks2prob(d, n, m);
+            assert!(
+                (got - want).abs() < 1e-9,
+                "ks2prob({}, {}, {}) = {}",
+                d,
+                n,
+                m,
+                got
+            );
+            assert_eq!(ks2prob(d, m, n), got);
+        }
+        // The asymptotic distribution is noticeably off for these.
+        let z = 0.6 * (2.5f64).sqrt();
+        assert!((kprob(z) - 5.0 / 14.0).abs() > 0.02);
+        let z = 0.5 * (2.4f64).sqrt();
+        assert!((kprob(z) - 58.0 / 105.0).abs() > 0.02);
+    }
+
+    #[test]
+    fn large_two_sample() {
+        let d = 0.3;
+        let z = d * (50.0f64 * 40.0 / 90.0).sqrt();
+        assert_eq!(ks2prob(d, 50, 40), kprob(z));
+        assert_eq!(ks2prob(d, 0, 40), 1.0);
+    }
 }
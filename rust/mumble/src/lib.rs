@@ -20,28 +20,48 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+mod adtest;
 pub mod ecdf;
+#[cfg(feature = "arrow")]
+pub mod export;
 pub mod joint;
 mod kstest;
-mod mesh;
+pub mod otlp;
+#[cfg(feature = "sampling")]
+mod sampling;
 mod sse;
+pub mod temporality;
 pub mod ui;
 
-use ecdf::ECDF;
+pub use kstest::ks_test;
+
+use ecdf::{InterpolatedECDF, ECDF};
 use num_traits::{Num, ToPrimitive};
 use serde::Serialize;
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    any::Any,
+    borrow::Cow,
+    collections::{hash_map::Entry, BTreeMap, HashMap},
     fmt::Debug,
     marker::{self, PhantomData},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use temporality::Temporality;
 
 // Open Telemetry SDK Specification:
 // https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/metrics/sdk.md
 
+#[derive(Debug, PartialEq)]
 pub enum AttributeValue {
     String(String),
+    Int(i64),
+    Double(f64),
+    Bool(bool),
+    StringArray(Vec<String>),
 }
 
 impl From<&str> for AttributeValue {
@@ -50,6 +70,36 @@ impl From<&str> for AttributeValue {
     }
 }
 
+impl From<String> for AttributeValue {
+    fn from(value: String) -> AttributeValue {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> AttributeValue {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<f64> for AttributeValue {
+    fn from(value: f64) -> AttributeValue {
+        AttributeValue::Double(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> AttributeValue {
+        AttributeValue::Bool(value)
+    }
+}
+
+impl From<Vec<String>> for AttributeValue {
+    fn from(value: Vec<String>) -> AttributeValue {
+        AttributeValue::StringArray(value)
+    }
+}
+
 impl Serialize for AttributeValue {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -57,12 +107,17 @@ impl Serialize for AttributeValue {
     {
         match self {
             AttributeValue::String(v) => v.serialize(serializer),
+            AttributeValue::Int(v) => v.serialize(serializer),
+            AttributeValue::Double(v) => v.serialize(serializer),
+            AttributeValue::Bool(v) => v.serialize(serializer),
+            AttributeValue::StringArray(v) => v.serialize(serializer),
         }
     }
 }
 
 // TODO: Should this instead be an array of values that map to known attributes?
-pub type Attributes = HashMap<String, AttributeValue>;
+/// Attributes are kept sorted by name, so they always serialize the same way.
+pub type Attributes = BTreeMap<String, AttributeValue>;
 
 /// A compound key that defines a namespace for [Instruments].
 #[derive(Clone, Eq, Hash, PartialEq, Serialize)]
@@ -79,6 +134,8 @@ struct InstrumentationScope {
 #[derive(Default)]
 pub struct MeterProvider {
     map: HashMap<InstrumentationScope, Meter>,
+    /// The ID of the last push cycle, shared with all meters.
+    cycle: Arc<AtomicU64>,
 }
 
 impl MeterProvider {
@@ -104,12 +161,67 @@ impl MeterProvider {
                         Some(attr) => attr,
                         None => Attributes::default(),
                     },
+                    instruments: Vec::new(),
+                    typed: Vec::new(),
+                    cycle: self.cycle.clone(),
                 })
             }
         };
         ui::push("target", &meter.attributes, true);
         meter
     }
+
+    /// Pushes every instrument registered with any of this provider's meters.
+    ///
+    /// All of the measurements are stamped with the same cycle ID.
+    pub fn push_all(&mut self, timestamp: u128) {
+        let cycle = next_cycle(&self.cycle);
+        for meter in self.map.values_mut() {
+            meter.push_cycle(timestamp, cycle);
+        }
+    }
+
+    /// Pushes every instrument one final time, so that nothing recorded since
+    /// the last push is lost.
+    ///
+    /// This should be called before exiting. The UI's event streams are
+    /// separate, and are closed by [ui::shutdown].
+    pub fn shutdown(&mut self) {
+        self.push_all(get_timestamp());
+    }
+
+    /// Serializes the current state of every instrument registered with any
+    /// of this provider's meters as OTLP JSON.
+    ///
+    /// The metrics are grouped by the meter that registered them. Instruments
+    /// that have nothing to report are skipped. Each instrument is locked in
+    /// turn, so this must not be called while holding one of their handles.
+    pub fn export_otlp_json(&self) -> String {
+        let time_unix_nano = get_timestamp();
+        let mut meters: Vec<&Meter> = self.map.values().collect();
+        meters.sort_by(|a, b| a.name().cmp(b.name()));
+        let scope_metrics = meters
+            .into_iter()
+            .map(|meter| otlp::ScopeMetrics {
+                scope: otlp::Scope::new(&meter.key, &meter.attributes),
+                metrics: meter
+                    .instruments
+                    .iter()
+                    .filter_map(|i| i.lock().unwrap().otlp_metric(time_unix_nano))
+                    .collect(),
+                schema_url: meter.key.schema_url.clone(),
+            })
+            .collect();
+        let data = otlp::MetricsData {
+            resource_metrics: vec![otlp::ResourceMetrics {
+                resource: otlp::Resource {
+                    attributes: Vec::new(),
+                },
+                scope_metrics,
+            }],
+        };
+        serde_json::to_string(&data).unwrap()
+    }
 }
 
 /// An implementation of Open Telemetry's Meter.
@@ -119,7 +231,15 @@ impl MeterProvider {
 pub struct Meter {
     key: InstrumentationScope,
     attributes: Attributes,
-    // streams: HashMap<StreamKey, Sender>,
+    instruments: Vec<Arc<Mutex<dyn Instrument + Send>>>,
+    /// The same instruments, so that they can be looked up by their concrete type.
+    typed: Vec<Arc<dyn Any + Send + Sync>>,
+    cycle: Arc<AtomicU64>,
+}
+
+/// Starts a new push cycle, returning its ID.
+fn next_cycle(cycle: &AtomicU64) -> u64 {
+    cycle.fetch_add(1, Ordering::Relaxed) + 1
 }
 
 impl Meter {
@@ -135,6 +255,62 @@ impl Meter {
         self.key.schema_url.as_deref()
     }
 
+    /// Takes ownership of an instrument, so that it is included in [Meter::push_all].
+    ///
+    /// Returns a shared handle that can be used to record values.
+    pub fn register<I>(&mut self, instrument: I) -> Arc<Mutex<I>>
+    where
+        I: Instrument + Send + 'static,
+    {
+        let handle = Arc::new(Mutex::new(instrument));
+        self.instruments.push(handle.clone());
+        self.typed.push(handle.clone());
+        let shared: Arc<Mutex<dyn Instrument + Send>> = handle.clone();
+        REGISTRY.lock().unwrap().push(Arc::downgrade(&shared));
+        handle
+    }
+
+    /// Finds a histogram registered with this meter by its name and attributes.
+    ///
+    /// Returns the same shared handle as [HistogramBuilder::build], so
+    /// values can be recorded into it from anywhere that has the meter. The
+    /// value type is part of the key: if the matching histogram records a
+    /// different type, e.g. `u64` rather than `f64`, this returns `None`.
+    /// Histograms built with [HistogramBuilder::build_detached] aren't found.
+    pub fn histogram<T>(
+        &self,
+        name: &str,
+        attributes: &Attributes,
+    ) -> Option<Arc<Mutex<Histogram<T>>>>
+    where
+        T: Num + ToPrimitive + PartialOrd + Copy + Debug + Send + 'static,
+    {
+        self.typed
+            .iter()
+            .filter_map(|i| i.clone().downcast::<Mutex<Histogram<T>>>().ok())
+            .find(|h| {
+                let h = h.lock().unwrap();
+                h.name == name && h.attributes == *attributes
+            })
+    }
+
+    /// Pushes every instrument registered with this meter.
+    ///
+    /// All of the measurements are stamped with the same cycle ID. IDs are
+    /// shared with the other meters of the same provider, so they are unique.
+    pub fn push_all(&mut self, timestamp: u128) {
+        self.push_cycle(timestamp, next_cycle(&self.cycle));
+    }
+
+    fn push_cycle(&mut self, timestamp: u128, cycle: u64) {
+        for instrument in self.instruments.iter() {
+            instrument
+                .lock()
+                .unwrap()
+                .push_cycle(timestamp, Some(cycle));
+        }
+    }
+
     pub fn create_histogram<'a, T>(&'a mut self, name: &str) -> HistogramBuilder<T>
     where
         T: Num + ToPrimitive + PartialOrd + Copy + Debug + Default,
@@ -143,6 +319,55 @@ impl Meter {
             meter: self,
             name: name.to_string(),
             description: None,
+            unit: None,
+            attributes: Attributes::default(),
+            bounds: None,
+            max_size: None,
+            temporality: Temporality::Delta,
+            live_updates: false,
+            shrink_on_push: false,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn create_counter<'a, T>(&'a mut self, name: &str) -> CounterBuilder<T>
+    where
+        T: Num + PartialOrd + Copy + Debug,
+    {
+        CounterBuilder::<'a, T> {
+            meter: self,
+            name: name.to_string(),
+            description: None,
+            unit: None,
+            attributes: Attributes::default(),
+            cumulative: false,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn create_up_down_counter<'a, T>(&'a mut self, name: &str) -> UpDownCounterBuilder<T>
+    where
+        T: Num + Copy + Debug,
+    {
+        UpDownCounterBuilder::<'a, T> {
+            meter: self,
+            name: name.to_string(),
+            description: None,
+            unit: None,
+            attributes: Attributes::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn create_gauge<'a, T>(&'a mut self, name: &str) -> GaugeBuilder<T>
+    where
+        T: Copy + Debug,
+    {
+        GaugeBuilder::<'a, T> {
+            meter: self,
+            name: name.to_string(),
+            description: None,
+            unit: None,
             attributes: Attributes::default(),
             _marker: PhantomData,
         }
@@ -152,15 +377,99 @@ impl Meter {
 pub trait Instrument {
     fn name(&self) -> &str;
     fn description(&self) -> Option<&str>;
-    fn push(&mut self, timestamp: u128);
+
+    /// The unit that values are measured in, such as `"s"` or `"By"`.
+    fn unit(&self) -> Option<&str> {
+        None
+    }
+
+    /// Pushes the data recorded since the last push.
+    fn push(&mut self, timestamp: u128) {
+        self.push_cycle(timestamp, None);
+    }
+
+    /// Pushes the data recorded since the last push, as part of a push cycle.
+    ///
+    /// Measurements from the same cycle share its ID, so they can be
+    /// grouped into a consistent snapshot.
+    fn push_cycle(&mut self, timestamp: u128, cycle: Option<u64>);
+
+    /// Describes the data recorded since the last push as JSON, in the same
+    /// shape as the measurements that are pushed.
+    ///
+    /// Returns `None` if there is nothing to report.
+    fn measurement(&self, _timestamp: u128) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Describes the data recorded since the last push as an OTLP metric.
+    fn otlp_metric(&self, _time_unix_nano: u128) -> Option<otlp::Metric> {
+        None
+    }
 }
 
 #[derive(Serialize)]
 struct Measurement<'a, T: Serialize> {
     timestamp: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cycle: Option<u64>,
     name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<&'a str>,
     attributes: &'a Attributes,
     value: &'a T,
+    /// Set for histograms built using [HistogramBuilder::with_bounds].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buckets: Option<BucketCounts>,
+}
+
+/// Cumulative sample counts for a histogram with fixed buckets.
+#[derive(Debug, PartialEq, Serialize)]
+struct BucketCounts {
+    bounds: Vec<f64>,
+    /// The number of samples no larger than each bound, followed by the total.
+    counts: Vec<usize>,
+}
+
+impl<'a, T: Serialize> Measurement<'a, T> {
+    /// Describes a measurement that isn't part of a push.
+    fn to_json(
+        timestamp: u128,
+        name: &'a str,
+        unit: Option<&'a str>,
+        attributes: &'a Attributes,
+        value: &'a T,
+    ) -> serde_json::Value {
+        serde_json::to_value(Measurement {
+            timestamp,
+            cycle: None,
+            name,
+            unit,
+            attributes,
+            value,
+            buckets: None,
+        })
+        .expect("serialize measurement")
+    }
+}
+
+lazy_static! {
+    /// Every instrument registered with a meter, so they can be inspected by the UI.
+    static ref REGISTRY: Mutex<Vec<Weak<Mutex<dyn Instrument + Send>>>> = Mutex::new(Vec::new());
+}
+
+/// Describes the current state of every registered instrument that has data.
+///
+/// Instruments are registered using [Meter::register], and are forgotten
+/// once they are dropped.
+pub(crate) fn snapshot(timestamp: u128) -> Vec<serde_json::Value> {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|i| i.strong_count() > 0);
+    registry
+        .iter()
+        .filter_map(|i| i.upgrade())
+        .filter_map(|i| i.lock().unwrap().measurement(timestamp))
+        .collect()
 }
 
 /*
@@ -180,7 +489,13 @@ pub struct HistogramBuilder<'a, T> {
     meter: &'a mut Meter,
     name: String,
     description: Option<String>,
+    unit: Option<String>,
     attributes: Attributes,
+    bounds: Option<Vec<T>>,
+    max_size: Option<usize>,
+    temporality: Temporality,
+    live_updates: bool,
+    shrink_on_push: bool,
     _marker: marker::PhantomData<T>,
 }
 
@@ -193,18 +508,271 @@ where
         self
     }
 
+    /// Sets the unit that values are measured in, using the UCUM case
+    /// sensitive codes that OpenTelemetry recommends, e.g. `"s"` or `"By"`.
+    pub fn set_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
     pub fn add_attribute(mut self, name: &str, value: AttributeValue) -> Self {
         self.attributes.insert(name.to_string(), value);
         self
     }
 
-    pub fn build(self) -> Histogram<T> {
-        Histogram::<T> {
-            name: self.name,
-            description: self.description,
-            attributes: self.attributes,
+    /// Records samples into fixed buckets rather than keeping every distinct value.
+    ///
+    /// Each bound is the inclusive upper edge of a bucket, with one extra
+    /// bucket for values larger than the last bound. This limits memory use
+    /// at the cost of resolution: quantiles are estimated by interpolating
+    /// within the bucket that contains them. Pushed measurements include the
+    /// cumulative count at each bound, for backends that need fixed buckets.
+    ///
+    /// The bounds must be finite. NaN or infinite bounds panic, since they
+    /// can't be sorted or exported.
+    pub fn with_bounds(mut self, bounds: &[T]) -> Self {
+        assert!(
+            bounds
+                .iter()
+                .all(|b| b.to_f64().is_some_and(|b| b.is_finite())),
+            "bounds must be finite, not {:?}",
+            bounds
+        );
+        self.bounds = Some(bounds.to_vec());
+        self
+    }
+
+    /// Limits the number of distinct values kept between pushes.
+    ///
+    /// Once more than `max_size` distinct values have been recorded, the
+    /// samples are compacted by merging the points that contribute least to
+    /// the shape of the distribution. This bounds memory use for high
+    /// cardinality metrics, but the reported distribution becomes an
+    /// approximation: individual values may be shifted up to the next
+    /// retained point. This has no effect on histograms built using
+    /// [HistogramBuilder::with_bounds].
+    ///
+    /// Compaction always keeps at least three points, so `max_size` must be
+    /// at least 4. Smaller values panic, rather than being silently ignored.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        assert!(
+            max_size >= 4,
+            "max_size must be at least 4, not {}",
+            max_size
+        );
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Chooses whether each push reports only the samples recorded since the
+    /// previous push, which is the default, or every sample recorded since
+    /// the histogram was created.
+    pub fn with_temporality(mut self, temporality: Temporality) -> Self {
+        self.temporality = temporality;
+        self
+    }
+
+    /// Chooses whether recorded values are also sent to the UI as they
+    /// arrive, rather than only as part of each push.
+    ///
+    /// This is meant for watching a metric live while debugging. Each update
+    /// is a `record` event carrying a single value, and updates are sent at
+    /// most once every [LIVE_UPDATE_INTERVAL], so values recorded in between
+    /// are only seen in the next push.
+    pub fn with_live_updates(mut self, enabled: bool) -> Self {
+        self.live_updates = enabled;
+        self
+    }
+
+    /// Chooses whether memory used to hold samples is released after each
+    /// push, rather than kept for reuse.
+    ///
+    /// Without this, a burst of distinct values leaves the histogram holding
+    /// enough memory for all of them until it is dropped. Releasing it costs
+    /// a reallocation as samples are recorded again after each push, so this
+    /// suits long-running processes whose metrics are occasionally bursty.
+    /// This has no effect with [Temporality::Cumulative], which never clears
+    /// its samples.
+    pub fn with_shrink_on_push(mut self, enabled: bool) -> Self {
+        self.shrink_on_push = enabled;
+        self
+    }
+
+    /// Builds the instrument and registers it with the meter that created it,
+    /// so that it is included in [Meter::push_all].
+    ///
+    /// See [Meter::register].
+    pub fn build(self) -> Arc<Mutex<Histogram<T>>>
+    where
+        T: Serialize + Send + 'static,
+    {
+        let (meter, instrument) = self.into_parts();
+        meter.register(instrument)
+    }
+
+    /// Builds the instrument without registering it with the meter.
+    ///
+    /// The caller is responsible for pushing it, since [Meter::push_all],
+    /// [MeterProvider::export_otlp_json] and the UI's metrics snapshot
+    /// won't see it.
+    pub fn build_detached(self) -> Histogram<T> {
+        self.into_parts().1
+    }
+
+    fn into_parts(self) -> (&'a mut Meter, Histogram<T>) {
+        let HistogramBuilder {
+            meter,
+            name,
+            description,
+            unit,
+            attributes,
+            bounds,
+            max_size,
+            temporality,
+            live_updates,
+            shrink_on_push,
+            ..
+        } = self;
+        let instrument = Histogram::<T> {
+            name,
+            description,
+            unit,
+            attributes,
             ecdf: ECDF::default(),
+            buckets: bounds.map(Buckets::new),
+            max_size,
+            temporality,
+            live_updates: live_updates.then(|| RateLimiter::new(LIVE_UPDATE_INTERVAL)),
+            shrink_on_push,
+            start_time: get_timestamp(),
+        };
+        (meter, instrument)
+    }
+}
+
+/// The minimum time between live updates from a histogram.
+///
+/// See [HistogramBuilder::with_live_updates].
+pub const LIVE_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits how often something happens.
+struct RateLimiter {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> RateLimiter {
+        RateLimiter {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Whether at least `interval` has passed since this last returned
+    /// `true`. If so, the interval starts again.
+    fn ready(&mut self) -> bool {
+        let now = Instant::now();
+        match self.last {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// Sample counts for a fixed set of buckets.
+struct Buckets<T> {
+    /// The inclusive upper bound of each bucket, in ascending order.
+    bounds: Vec<T>,
+    /// The number of samples in each bucket, plus one for the overflow bucket.
+    counts: Vec<usize>,
+    sum: f64,
+    min: Option<T>,
+    max: Option<T>,
+}
+
+impl<T> Buckets<T>
+where
+    T: ToPrimitive + PartialOrd + Copy,
+{
+    /// Creates empty buckets. The bounds must not include NaN, which is
+    /// checked by [HistogramBuilder::with_bounds].
+    fn new(mut bounds: Vec<T>) -> Buckets<T> {
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Buckets {
+            bounds,
+            counts,
+            sum: 0.0,
+            min: None,
+            max: None,
+        }
+    }
+
+    fn record(&mut self, value: T) {
+        let i = self.bounds.partition_point(|&b| b < value);
+        self.counts[i] += 1;
+        self.sum += value.to_f64().unwrap();
+        match self.min {
+            Some(min) if min <= value => {}
+            _ => self.min = Some(value),
+        }
+        match self.max {
+            Some(max) if max >= value => {}
+            _ => self.max = Some(value),
+        }
+    }
+
+    /// Returns the number of samples no larger than each bound.
+    fn cumulative(&self) -> BucketCounts {
+        BucketCounts {
+            bounds: self.bounds.iter().map(|b| b.to_f64().unwrap()).collect(),
+            counts: self
+                .counts
+                .iter()
+                .scan(0, |total, &n| {
+                    *total += n;
+                    Some(*total)
+                })
+                .collect(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.counts.iter_mut().for_each(|n| *n = 0);
+        self.sum = 0.0;
+        self.min = None;
+        self.max = None;
+    }
+
+    /// Returns the upper edge and count of each non-empty bucket.
+    ///
+    /// The largest observed value is used as the upper edge of the last
+    /// bucket, since the overflow bucket has no upper bound.
+    fn edges(&self) -> Vec<(T, usize)> {
+        let max = match self.max {
+            Some(max) => max,
+            None => return Vec::new(),
+        };
+        let mut out: Vec<(T, usize)> = Vec::new();
+        for (i, &n) in self.counts.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            let edge = match self.bounds.get(i) {
+                Some(&b) if b < max => b,
+                _ => max,
+            };
+            match out.last_mut() {
+                Some(last) if last.0 == edge => last.1 += n,
+                _ => out.push((edge, n)),
+            }
         }
+        out
     }
 }
 
@@ -214,8 +782,18 @@ where
 {
     name: String,
     description: Option<String>,
+    unit: Option<String>,
     attributes: Attributes,
     ecdf: ECDF<T>,
+    buckets: Option<Buckets<T>>,
+    max_size: Option<usize>,
+    temporality: Temporality,
+    /// Set for histograms built using [HistogramBuilder::with_live_updates].
+    live_updates: Option<RateLimiter>,
+    /// Set for histograms built using [HistogramBuilder::with_shrink_on_push].
+    shrink_on_push: bool,
+    /// When the samples being reported started being collected.
+    start_time: u128,
 }
 
 /// Returns the current time, in a format appropriate for reporting.
@@ -238,8 +816,13 @@ where
         self.description.as_deref()
     }
 
-    fn push(&mut self, timestamp: u128) {
-        if self.ecdf.is_empty() {
+    fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    fn push_cycle(&mut self, timestamp: u128, cycle: Option<u64>) {
+        let value = self.current();
+        if value.is_empty() && self.temporality == Temporality::Delta {
             // Nothing to do...
             return;
         }
@@ -247,13 +830,105 @@ where
             "update",
             &Measurement::<ECDF<T>> {
                 timestamp,
+                cycle,
                 name: &self.name,
+                unit: self.unit.as_deref(),
                 attributes: &self.attributes,
-                value: &self.ecdf,
+                value: &value,
+                buckets: self.buckets.as_ref().map(Buckets::cumulative),
             },
             false,
         );
+        if self.temporality == Temporality::Cumulative {
+            // Keep the running total.
+            return;
+        }
         self.ecdf.clear();
+        if self.shrink_on_push {
+            self.ecdf.shrink_to_fit();
+        }
+        if let Some(buckets) = &mut self.buckets {
+            buckets.clear();
+        }
+        self.start_time = timestamp;
+    }
+
+    fn measurement(&self, timestamp: u128) -> Option<serde_json::Value> {
+        let value = self.current();
+        if value.is_empty() {
+            return None;
+        }
+        let measurement = Measurement::<ECDF<T>> {
+            timestamp,
+            cycle: None,
+            name: &self.name,
+            unit: self.unit.as_deref(),
+            attributes: &self.attributes,
+            value: &value,
+            buckets: self.buckets.as_ref().map(Buckets::cumulative),
+        };
+        Some(serde_json::to_value(measurement).expect("serialize measurement"))
+    }
+
+    fn otlp_metric(&self, time_unix_nano: u128) -> Option<otlp::Metric> {
+        let to_f64 = |v: T| v.to_f64().unwrap();
+        let data_point = match &self.buckets {
+            Some(buckets) => otlp::HistogramDataPoint {
+                attributes: otlp::key_values(&self.attributes),
+                start_time_unix_nano: self.start_time,
+                time_unix_nano,
+                count: buckets.counts.iter().sum(),
+                sum: buckets.sum,
+                bucket_counts: buckets.counts.clone(),
+                explicit_bounds: buckets.bounds.iter().copied().map(to_f64).collect(),
+                min: buckets.min.map(to_f64),
+                max: buckets.max.map(to_f64),
+            },
+            None => {
+                // Every distinct value is the upper bound of its own bucket.
+                let mut bucket_counts: Vec<usize> = self.ecdf.samples.iter().map(|x| x.1).collect();
+                bucket_counts.push(0);
+                otlp::HistogramDataPoint {
+                    attributes: otlp::key_values(&self.attributes),
+                    start_time_unix_nano: self.start_time,
+                    time_unix_nano,
+                    count: self.ecdf.len(),
+                    sum: self.ecdf.sum(),
+                    bucket_counts,
+                    explicit_bounds: self.ecdf.samples.iter().map(|x| to_f64(x.0)).collect(),
+                    min: self.ecdf.min().map(to_f64),
+                    max: self.ecdf.max().map(to_f64),
+                }
+            }
+        };
+        if data_point.count == 0 {
+            // Nothing was recorded, so there's nothing to report.
+            return None;
+        }
+        Some(otlp::Metric {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            unit: self.unit.clone(),
+            data: otlp::Data::Histogram(match self.temporality {
+                Temporality::Delta => otlp::Histogram::delta(data_point),
+                Temporality::Cumulative => otlp::Histogram::cumulative(data_point),
+            }),
+        })
+    }
+}
+
+impl<T> Histogram<T>
+where
+    T: Num + ToPrimitive + PartialOrd + Copy + Debug,
+{
+    /// The samples recorded since the last push, including any recorded into buckets.
+    fn current(&self) -> Cow<'_, ECDF<T>> {
+        match &self.buckets {
+            Some(buckets) => Cow::Owned(ECDF {
+                samples: buckets.edges(),
+            }),
+            None => Cow::Borrowed(&self.ecdf),
+        }
     }
 }
 
@@ -261,7 +936,1416 @@ impl<T> Histogram<T>
 where
     T: Num + ToPrimitive + PartialOrd + Copy + Debug + Default,
 {
+    /// Adds or replaces an attribute after the histogram has been built.
+    ///
+    /// Attributes are part of the identity of the reported series, so
+    /// changing them splits it: measurements pushed afterwards are treated as
+    /// belonging to a different series than those pushed before.
+    pub fn set_attribute(&mut self, name: &str, value: AttributeValue) {
+        self.attributes.insert(name.to_string(), value);
+    }
+
+    /// Removes an attribute, returning its value if it was set.
+    ///
+    /// Like [Histogram::set_attribute], this splits the reported series.
+    pub fn remove_attribute(&mut self, name: &str) -> Option<AttributeValue> {
+        self.attributes.remove(name)
+    }
+
     pub fn record(&mut self, value: T) {
-        self.ecdf.add(value)
+        match &mut self.buckets {
+            Some(buckets) => buckets.record(value),
+            None => {
+                self.ecdf.add(value);
+                if let Some(max_size) = self.max_size {
+                    // Compact below the limit, so the cost is amortized over several records.
+                    self.ecdf.compact_if(max_size, max_size * 3 / 4);
+                }
+            }
+        }
+        if let Some(limiter) = &mut self.live_updates {
+            if limiter.ready() {
+                ui::push(
+                    "record",
+                    &Measurement::<f64> {
+                        timestamp: get_timestamp(),
+                        cycle: None,
+                        name: &self.name,
+                        unit: self.unit.as_deref(),
+                        attributes: &self.attributes,
+                        value: &value.to_f64().unwrap(),
+                        buckets: None,
+                    },
+                    false,
+                );
+            }
+        }
+    }
+
+    /// The samples recorded since the last push, or since the histogram was
+    /// created if it has [Temporality::Cumulative].
+    ///
+    /// This is always empty for histograms built using
+    /// [HistogramBuilder::with_bounds].
+    pub fn ecdf(&self) -> &ECDF<T> {
+        &self.ecdf
+    }
+
+    /// Estimates the value at quantile `q` of the samples recorded since the last push.
+    ///
+    /// When recording into fixed buckets, this interpolates within the bucket
+    /// that contains the quantile, using the smallest and largest observed
+    /// values as the outer edges of the first and last buckets.
+    pub fn quantile(&self, q: f64) -> f64 {
+        let samples = match &self.buckets {
+            Some(buckets) => {
+                let mut samples = Vec::with_capacity(buckets.counts.len() + 1);
+                if let Some(min) = buckets.min {
+                    samples.push((min.to_f64().unwrap(), 0.0));
+                }
+                for (v, n) in buckets.edges() {
+                    let v = v.to_f64().unwrap();
+                    match samples.last_mut() {
+                        Some(last) if last.0 == v => last.1 += n as f64,
+                        _ => samples.push((v, n as f64)),
+                    }
+                }
+                samples
+            }
+            None => self
+                .ecdf
+                .samples
+                .iter()
+                .map(|&(v, n)| (v.to_f64().unwrap(), n as f64))
+                .collect(),
+        };
+        InterpolatedECDF { samples }.quantile(q)
+    }
+}
+
+/// Records the rate of change of a cumulative counter, such as CPU ticks.
+///
+/// Counters are expected to only increase. A reading that is smaller than
+/// the previous one means that the counter was reset, so the reading itself
+/// is taken as the increase since the reset.
+pub struct MonotonicSource {
+    histogram: Arc<Mutex<Histogram<f64>>>,
+    last: Option<u64>,
+}
+
+impl MonotonicSource {
+    pub fn new(histogram: Arc<Mutex<Histogram<f64>>>) -> MonotonicSource {
+        MonotonicSource {
+            histogram,
+            last: None,
+        }
+    }
+
+    pub fn histogram(&self) -> &Arc<Mutex<Histogram<f64>>> {
+        &self.histogram
+    }
+
+    /// The increase from `last` to `value`, allowing for counter resets.
+    pub fn increase(last: u64, value: u64) -> u64 {
+        if value < last {
+            value
+        } else {
+            value - last
+        }
+    }
+
+    /// Takes a new reading of the counter, recording its increase since the
+    /// previous reading divided by `interval`.
+    ///
+    /// Nothing is recorded for the first reading. Returns the recorded rate.
+    pub fn observe(&mut self, value: u64, interval: f64) -> Option<f64> {
+        let rate = self
+            .last
+            .map(|last| Self::increase(last, value) as f64 / interval);
+        if let Some(rate) = rate {
+            self.histogram.lock().unwrap().record(rate);
+        }
+        self.last = Some(value);
+        rate
+    }
+}
+
+pub struct CounterBuilder<'a, T> {
+    meter: &'a mut Meter,
+    name: String,
+    description: Option<String>,
+    unit: Option<String>,
+    attributes: Attributes,
+    cumulative: bool,
+    _marker: marker::PhantomData<T>,
+}
+
+impl<'a, T> CounterBuilder<'a, T>
+where
+    T: Num + PartialOrd + Copy + Debug,
+{
+    pub fn set_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Sets the unit that values are measured in, using the UCUM case
+    /// sensitive codes that OpenTelemetry recommends, e.g. `"s"` or `"By"`.
+    pub fn set_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    pub fn add_attribute(mut self, name: &str, value: AttributeValue) -> Self {
+        self.attributes.insert(name.to_string(), value);
+        self
+    }
+
+    /// Reports the running total since the counter was created, rather than
+    /// the amount added since the last push.
+    pub fn with_cumulative_temporality(mut self) -> Self {
+        self.cumulative = true;
+        self
+    }
+
+    /// Builds the instrument and registers it with the meter that created it,
+    /// so that it is included in [Meter::push_all].
+    ///
+    /// See [Meter::register].
+    pub fn build(self) -> Arc<Mutex<Counter<T>>>
+    where
+        T: Serialize + Send + 'static,
+    {
+        let (meter, instrument) = self.into_parts();
+        meter.register(instrument)
+    }
+
+    /// Builds the instrument without registering it with the meter.
+    ///
+    /// The caller is responsible for pushing it, since [Meter::push_all],
+    /// [MeterProvider::export_otlp_json] and the UI's metrics snapshot
+    /// won't see it.
+    pub fn build_detached(self) -> Counter<T> {
+        self.into_parts().1
+    }
+
+    fn into_parts(self) -> (&'a mut Meter, Counter<T>) {
+        let CounterBuilder {
+            meter,
+            name,
+            description,
+            unit,
+            attributes,
+            cumulative,
+            ..
+        } = self;
+        let instrument = Counter::<T> {
+            name,
+            description,
+            unit,
+            attributes,
+            total: T::zero(),
+            cumulative,
+            updated: false,
+            start_time: get_timestamp(),
+        };
+        (meter, instrument)
+    }
+}
+
+/// A monotonic counter.
+///
+/// For more information, see the
+/// [Open Telemetry specification](https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/metrics/api.md#counter).
+pub struct Counter<T>
+where
+    T: Num + PartialOrd + Copy + Debug,
+{
+    name: String,
+    description: Option<String>,
+    unit: Option<String>,
+    attributes: Attributes,
+    total: T,
+    cumulative: bool,
+    updated: bool,
+    /// When the total being reported started being collected.
+    start_time: u128,
+}
+
+impl<T> Instrument for Counter<T>
+where
+    T: Num + PartialOrd + Copy + Debug + Serialize,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    fn measurement(&self, timestamp: u128) -> Option<serde_json::Value> {
+        if !self.updated {
+            return None;
+        }
+        Some(Measurement::<T>::to_json(
+            timestamp,
+            &self.name,
+            self.unit.as_deref(),
+            &self.attributes,
+            &self.total,
+        ))
+    }
+
+    fn push_cycle(&mut self, timestamp: u128, cycle: Option<u64>) {
+        if !self.updated {
+            // Nothing to do...
+            return;
+        }
+        ui::push(
+            "counter",
+            &Measurement::<T> {
+                timestamp,
+                cycle,
+                name: &self.name,
+                unit: self.unit.as_deref(),
+                attributes: &self.attributes,
+                value: &self.total,
+                buckets: None,
+            },
+            false,
+        );
+        if !self.cumulative {
+            self.total = T::zero();
+            self.updated = false;
+            self.start_time = timestamp;
+        }
+    }
+
+    fn otlp_metric(&self, time_unix_nano: u128) -> Option<otlp::Metric> {
+        if !self.updated {
+            return None;
+        }
+        let data_point = otlp::NumberDataPoint {
+            attributes: otlp::key_values(&self.attributes),
+            start_time_unix_nano: Some(self.start_time),
+            time_unix_nano,
+            value: otlp::NumberValue::new(&self.total)?,
+        };
+        Some(otlp::Metric {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            unit: self.unit.clone(),
+            data: otlp::Data::Sum(otlp::Sum::new(data_point, self.cumulative, true)),
+        })
+    }
+}
+
+impl<T> Counter<T>
+where
+    T: Num + PartialOrd + Copy + Debug,
+{
+    /// Increments the counter. Negative values are ignored.
+    pub fn add(&mut self, value: T) {
+        if value < T::zero() {
+            warn!(
+                "Ignoring negative value {:?} for counter {}",
+                value, self.name
+            );
+            return;
+        }
+        self.total = self.total + value;
+        self.updated = true;
+    }
+
+    /// The value that will be reported on the next push.
+    pub fn total(&self) -> T {
+        self.total
+    }
+}
+
+pub struct UpDownCounterBuilder<'a, T> {
+    meter: &'a mut Meter,
+    name: String,
+    description: Option<String>,
+    unit: Option<String>,
+    attributes: Attributes,
+    _marker: marker::PhantomData<T>,
+}
+
+impl<'a, T> UpDownCounterBuilder<'a, T>
+where
+    T: Num + Copy + Debug,
+{
+    pub fn set_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Sets the unit that values are measured in, using the UCUM case
+    /// sensitive codes that OpenTelemetry recommends, e.g. `"s"` or `"By"`.
+    pub fn set_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    pub fn add_attribute(mut self, name: &str, value: AttributeValue) -> Self {
+        self.attributes.insert(name.to_string(), value);
+        self
+    }
+
+    /// Builds the instrument and registers it with the meter that created it,
+    /// so that it is included in [Meter::push_all].
+    ///
+    /// See [Meter::register].
+    pub fn build(self) -> Arc<Mutex<UpDownCounter<T>>>
+    where
+        T: Serialize + Send + 'static,
+    {
+        let (meter, instrument) = self.into_parts();
+        meter.register(instrument)
+    }
+
+    /// Builds the instrument without registering it with the meter.
+    ///
+    /// The caller is responsible for pushing it, since [Meter::push_all],
+    /// [MeterProvider::export_otlp_json] and the UI's metrics snapshot
+    /// won't see it.
+    pub fn build_detached(self) -> UpDownCounter<T> {
+        self.into_parts().1
+    }
+
+    fn into_parts(self) -> (&'a mut Meter, UpDownCounter<T>) {
+        let UpDownCounterBuilder {
+            meter,
+            name,
+            description,
+            unit,
+            attributes,
+            ..
+        } = self;
+        let instrument = UpDownCounter::<T> {
+            name,
+            description,
+            unit,
+            attributes,
+            total: T::zero(),
+            updated: false,
+            start_time: get_timestamp(),
+        };
+        (meter, instrument)
+    }
+}
+
+/// A counter that can be both incremented and decremented.
+///
+/// For more information, see the
+/// [Open Telemetry specification](https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/metrics/api.md#updowncounter).
+pub struct UpDownCounter<T>
+where
+    T: Num + Copy + Debug,
+{
+    name: String,
+    description: Option<String>,
+    unit: Option<String>,
+    attributes: Attributes,
+    total: T,
+    updated: bool,
+    /// When the counter was created, since its total is never reset.
+    start_time: u128,
+}
+
+impl<T> Instrument for UpDownCounter<T>
+where
+    T: Num + Copy + Debug + Serialize,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    fn measurement(&self, timestamp: u128) -> Option<serde_json::Value> {
+        if !self.updated {
+            return None;
+        }
+        Some(Measurement::<T>::to_json(
+            timestamp,
+            &self.name,
+            self.unit.as_deref(),
+            &self.attributes,
+            &self.total,
+        ))
+    }
+
+    fn push_cycle(&mut self, timestamp: u128, cycle: Option<u64>) {
+        if !self.updated {
+            // Nothing to do...
+            return;
+        }
+        ui::push(
+            "up_down_counter",
+            &Measurement::<T> {
+                timestamp,
+                cycle,
+                name: &self.name,
+                unit: self.unit.as_deref(),
+                attributes: &self.attributes,
+                value: &self.total,
+                buckets: None,
+            },
+            false,
+        );
+    }
+
+    fn otlp_metric(&self, time_unix_nano: u128) -> Option<otlp::Metric> {
+        if !self.updated {
+            return None;
+        }
+        let data_point = otlp::NumberDataPoint {
+            attributes: otlp::key_values(&self.attributes),
+            start_time_unix_nano: Some(self.start_time),
+            time_unix_nano,
+            value: otlp::NumberValue::new(&self.total)?,
+        };
+        Some(otlp::Metric {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            unit: self.unit.clone(),
+            data: otlp::Data::Sum(otlp::Sum::new(data_point, true, false)),
+        })
+    }
+}
+
+impl<T> UpDownCounter<T>
+where
+    T: Num + Copy + Debug,
+{
+    /// Adds `value` to the running total. Use a negative value to decrement it.
+    pub fn add(&mut self, value: T) {
+        self.total = self.total + value;
+        self.updated = true;
+    }
+
+    /// The running total.
+    pub fn total(&self) -> T {
+        self.total
+    }
+}
+
+pub struct GaugeBuilder<'a, T> {
+    meter: &'a mut Meter,
+    name: String,
+    description: Option<String>,
+    unit: Option<String>,
+    attributes: Attributes,
+    _marker: marker::PhantomData<T>,
+}
+
+impl<'a, T> GaugeBuilder<'a, T>
+where
+    T: Copy + Debug,
+{
+    pub fn set_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Sets the unit that values are measured in, using the UCUM case
+    /// sensitive codes that OpenTelemetry recommends, e.g. `"s"` or `"By"`.
+    pub fn set_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    pub fn add_attribute(mut self, name: &str, value: AttributeValue) -> Self {
+        self.attributes.insert(name.to_string(), value);
+        self
+    }
+
+    /// Builds the instrument and registers it with the meter that created it,
+    /// so that it is included in [Meter::push_all].
+    ///
+    /// See [Meter::register].
+    pub fn build(self) -> Arc<Mutex<Gauge<T>>>
+    where
+        T: Serialize + Send + 'static,
+    {
+        let (meter, instrument) = self.into_parts();
+        meter.register(instrument)
+    }
+
+    /// Builds the instrument without registering it with the meter.
+    ///
+    /// The caller is responsible for pushing it, since [Meter::push_all],
+    /// [MeterProvider::export_otlp_json] and the UI's metrics snapshot
+    /// won't see it.
+    pub fn build_detached(self) -> Gauge<T> {
+        self.into_parts().1
+    }
+
+    fn into_parts(self) -> (&'a mut Meter, Gauge<T>) {
+        let GaugeBuilder {
+            meter,
+            name,
+            description,
+            unit,
+            attributes,
+            ..
+        } = self;
+        let instrument = Gauge::<T> {
+            name,
+            description,
+            unit,
+            attributes,
+            value: None,
+        };
+        (meter, instrument)
+    }
+}
+
+/// A synchronous gauge, which reports the last value it was set to.
+///
+/// The value is kept after each push, so it is reported every cycle until
+/// it is set again.
+///
+/// For more information, see the
+/// [Open Telemetry specification](https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/metrics/api.md#gauge).
+pub struct Gauge<T>
+where
+    T: Copy + Debug,
+{
+    name: String,
+    description: Option<String>,
+    unit: Option<String>,
+    attributes: Attributes,
+    value: Option<T>,
+}
+
+impl<T> Instrument for Gauge<T>
+where
+    T: Copy + Debug + Serialize,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    fn measurement(&self, timestamp: u128) -> Option<serde_json::Value> {
+        let value = self.value.as_ref()?;
+        Some(Measurement::<T>::to_json(
+            timestamp,
+            &self.name,
+            self.unit.as_deref(),
+            &self.attributes,
+            value,
+        ))
+    }
+
+    fn push_cycle(&mut self, timestamp: u128, cycle: Option<u64>) {
+        // Unlike counters, gauges keep reporting their last value.
+        let value = match self.value {
+            Some(value) => value,
+            None => {
+                // Nothing to do...
+                return;
+            }
+        };
+        ui::push(
+            "gauge",
+            &Measurement::<T> {
+                timestamp,
+                cycle,
+                name: &self.name,
+                unit: self.unit.as_deref(),
+                attributes: &self.attributes,
+                value: &value,
+                buckets: None,
+            },
+            false,
+        );
+    }
+
+    fn otlp_metric(&self, time_unix_nano: u128) -> Option<otlp::Metric> {
+        let data_point = otlp::NumberDataPoint {
+            attributes: otlp::key_values(&self.attributes),
+            start_time_unix_nano: None,
+            time_unix_nano,
+            value: otlp::NumberValue::new(self.value.as_ref()?)?,
+        };
+        Some(otlp::Metric {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            unit: self.unit.clone(),
+            data: otlp::Data::Gauge(otlp::Gauge::new(data_point)),
+        })
+    }
+}
+
+impl<T> Gauge<T>
+where
+    T: Copy + Debug,
+{
+    /// Replaces the value reported on every push from now on.
+    pub fn set(&mut self, value: T) {
+        self.value = Some(value);
+    }
+
+    /// The value that will be reported on the next push, if any.
+    pub fn value(&self) -> Option<T> {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use http::Request;
+    use http_body_util::BodyExt;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn bucketed_quantile() {
+        let mut mp = MeterProvider::default();
+        let bounds: Vec<f64> = (1..=10).map(|b| b as f64).collect();
+        let mut h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("latency")
+            .with_bounds(&bounds)
+            .build_detached();
+        for i in 0..100 {
+            h.record(i as f64 * 0.1 + 0.05);
+        }
+        let buckets = h.buckets.as_ref().unwrap();
+        assert_eq!(buckets.counts.len(), 11);
+        assert!(h.ecdf().is_empty());
+
+        // The 90th sample is 8.95, in the bucket (8, 9].
+        let p90 = h.quantile(0.9);
+        assert!((8.0..=9.0).contains(&p90), "p90 = {}", p90);
+        // Within the first bucket, interpolate from the smallest value.
+        let p5 = h.quantile(0.05);
+        assert!((0.05..=1.0).contains(&p5), "p5 = {}", p5);
+        assert_eq!(h.quantile(1.0), 9.95);
+    }
+
+    #[test]
+    #[should_panic(expected = "bounds must be finite")]
+    fn nan_bounds() {
+        let mut mp = MeterProvider::default();
+        mp.get_meter("test".into(), None, None, None)
+            .create_histogram::<f64>("nan")
+            .with_bounds(&[1.0, f64::NAN, 5.0]);
+    }
+
+    #[test]
+    fn bucketed_overflow() {
+        let mut b = Buckets::new(vec![10, 1, 5]);
+        assert_eq!(b.bounds, vec![1, 5, 10]);
+        for v in [0, 1, 2, 5, 7, 20, 30] {
+            b.record(v);
+        }
+        assert_eq!(b.counts, vec![2, 2, 1, 2]);
+        assert_eq!(b.edges(), vec![(1, 2), (5, 2), (10, 1), (30, 2)]);
+        b.clear();
+        assert!(b.edges().is_empty());
+    }
+
+    #[test]
+    fn bucketed_measurement() {
+        let mut mp = MeterProvider::default();
+        let mut h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("latency")
+            .with_bounds(&[1.0, 5.0, 10.0])
+            .build_detached();
+        for v in [0.5, 1.0, 2.0, 5.0, 7.0, 10.0, 20.0] {
+            h.record(v);
+        }
+        let json = h.measurement(get_timestamp()).unwrap();
+        assert_eq!(
+            json["buckets"],
+            serde_json::json!({"bounds": [1.0, 5.0, 10.0], "counts": [2, 4, 6, 7]})
+        );
+
+        // Histograms without bounds only report their samples.
+        let mut h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("latency")
+            .build_detached();
+        h.record(1.0);
+        assert!(h
+            .measurement(get_timestamp())
+            .unwrap()
+            .get("buckets")
+            .is_none());
+    }
+
+    #[test]
+    fn max_size() {
+        let mut mp = MeterProvider::default();
+        let mut h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("latency")
+            .with_max_size(256)
+            .build_detached();
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..100_000 {
+            h.record(rng.gen::<f64>());
+            assert!(h.ecdf().samples.len() <= 256);
+        }
+        assert_eq!(h.ecdf().len(), 100_000);
+        let median = h.ecdf().interpolate().median();
+        assert!((0.45..=0.55).contains(&median), "median = {}", median);
+    }
+
+    #[test]
+    fn small_max_size() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut h: Histogram<f64> = meter
+            .create_histogram("tiny")
+            .with_max_size(4)
+            .build_detached();
+        for i in 0..100 {
+            h.record(i as f64);
+            assert!(h.ecdf().samples.len() <= 4);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "max_size must be at least 4")]
+    fn max_size_too_small() {
+        let mut mp = MeterProvider::default();
+        mp.get_meter("test".into(), None, None, None)
+            .create_histogram::<f64>("tinier")
+            .with_max_size(3);
+    }
+
+    #[test]
+    fn monotonic_source() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut source = MonotonicSource::new(meter.create_histogram("rate").build());
+        assert_eq!(source.observe(100, 2.0), None);
+        assert_eq!(source.observe(120, 2.0), Some(10.0));
+        // The counter was reset, and has since counted up to 6.
+        assert_eq!(source.observe(6, 2.0), Some(3.0));
+        assert_eq!(source.observe(10, 2.0), Some(2.0));
+        let h = source.histogram().lock().unwrap();
+        assert_eq!(h.ecdf().point_iter().next().unwrap().0, 2.0);
+        assert_eq!(h.ecdf().len(), 3);
+    }
+
+    #[test]
+    fn delta_counter() {
+        let mut mp = MeterProvider::default();
+        let mut c: Counter<u64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_counter("context_switches")
+            .build_detached();
+        c.add(3);
+        c.add(4);
+        assert_eq!(c.total(), 7);
+        c.push(get_timestamp());
+        assert_eq!(c.total(), 0);
+        c.add(1);
+        assert_eq!(c.total(), 1);
+    }
+
+    #[test]
+    fn cumulative_counter() {
+        let mut mp = MeterProvider::default();
+        let mut c: Counter<i64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_counter("context_switches")
+            .with_cumulative_temporality()
+            .build_detached();
+        c.add(3);
+        c.add(-2);
+        assert_eq!(c.total(), 3);
+        c.push(get_timestamp());
+        c.add(4);
+        assert_eq!(c.total(), 7);
+    }
+
+    #[test]
+    fn up_down_counter() {
+        let mut mp = MeterProvider::default();
+        let mut c: UpDownCounter<i64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_up_down_counter("queue_length")
+            .build_detached();
+        c.add(3);
+        c.add(-5);
+        assert_eq!(c.total(), -2);
+        c.push(get_timestamp());
+        assert_eq!(c.total(), -2);
+    }
+
+    #[test]
+    fn gauge() {
+        let mut mp = MeterProvider::default();
+        let mut g: Gauge<u64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_gauge("resident_memory")
+            .build_detached();
+        assert_eq!(g.value(), None);
+        g.set(100);
+        g.set(42);
+        assert_eq!(g.value(), Some(42));
+        g.push(get_timestamp());
+        assert_eq!(g.value(), Some(42));
+    }
+
+    #[test]
+    fn attribute_order() {
+        let names = [
+            "host", "cpu", "zone", "mode", "arch", "pool", "rack", "kind",
+        ];
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let forward = names
+            .iter()
+            .fold(meter.create_histogram::<f64>("ordered"), |b, name| {
+                b.add_attribute(name, (*name).into())
+            })
+            .build_detached();
+        let backward = names
+            .iter()
+            .rev()
+            .fold(meter.create_histogram::<f64>("ordered"), |b, name| {
+                b.add_attribute(name, (*name).into())
+            })
+            .build_detached();
+        assert_eq!(forward.attributes, backward.attributes);
+
+        let json = |h: &Histogram<f64>| {
+            serde_json::to_string(&Measurement {
+                timestamp: 0,
+                cycle: None,
+                name: &h.name,
+                unit: None,
+                attributes: &h.attributes,
+                value: h.ecdf(),
+                buckets: None,
+            })
+            .unwrap()
+        };
+        assert_eq!(json(&forward), json(&backward));
+        assert!(json(&forward).contains(r#""attributes":{"arch":"arch","cpu":"cpu","#));
+    }
+
+    #[test]
+    fn find_histogram() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let h: Arc<Mutex<Histogram<f64>>> = meter
+            .create_histogram("latency")
+            .add_attribute("path", "/a".into())
+            .build();
+        let _other: Arc<Mutex<Histogram<f64>>> = meter
+            .create_histogram("latency")
+            .add_attribute("path", "/b".into())
+            .build();
+
+        let attributes = Attributes::from([("path".to_string(), "/a".into())]);
+        let found = meter.histogram::<f64>("latency", &attributes).unwrap();
+        found.lock().unwrap().record(1.5);
+        assert_eq!(h.lock().unwrap().ecdf().len(), 1);
+
+        // The value type has to match too.
+        assert!(meter.histogram::<u64>("latency", &attributes).is_none());
+        assert!(meter
+            .histogram::<f64>("latency", &Attributes::default())
+            .is_none());
+        assert!(meter.histogram::<f64>("missing", &attributes).is_none());
+    }
+
+    #[test]
+    fn delta_histogram() {
+        let mut mp = MeterProvider::default();
+        let mut h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("delta")
+            .build_detached();
+        h.record(1.0);
+        h.record(2.0);
+        h.push(get_timestamp());
+        assert!(h.ecdf().is_empty());
+        h.record(3.0);
+        h.push(get_timestamp());
+        assert!(h.ecdf().is_empty());
+    }
+
+    #[test]
+    fn cumulative_histogram() {
+        let mut mp = MeterProvider::default();
+        let mut h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("cumulative")
+            .with_temporality(Temporality::Cumulative)
+            .build_detached();
+        let start = h.start_time;
+        h.record(1.0);
+        h.record(2.0);
+        h.push(get_timestamp());
+        assert_eq!(h.ecdf().len(), 2);
+        h.record(3.0);
+        h.push(get_timestamp());
+        assert_eq!(h.ecdf().len(), 3);
+        assert_eq!(h.start_time, start);
+
+        let metric = serde_json::to_value(h.otlp_metric(get_timestamp()).unwrap()).unwrap();
+        assert_eq!(metric["histogram"]["aggregationTemporality"], 2);
+        assert_eq!(metric["histogram"]["dataPoints"][0]["count"], "3");
+    }
+
+    #[test]
+    fn unit() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut h: Histogram<f64> = meter
+            .create_histogram("latency")
+            .set_unit("s")
+            .build_detached();
+        assert_eq!(h.unit(), Some("s"));
+        h.record(0.25);
+        let json = h.measurement(get_timestamp()).unwrap();
+        assert_eq!(json["unit"], "s");
+
+        let mut c: Counter<u64> = meter
+            .create_counter("bytes")
+            .set_unit("By")
+            .build_detached();
+        assert_eq!(c.unit(), Some("By"));
+        c.add(1);
+        assert_eq!(c.measurement(get_timestamp()).unwrap()["unit"], "By");
+        assert_eq!(
+            serde_json::to_value(c.otlp_metric(get_timestamp()).unwrap()).unwrap()["unit"],
+            "By"
+        );
+        let mut u: UpDownCounter<i64> = meter
+            .create_up_down_counter("open")
+            .set_unit("1")
+            .build_detached();
+        u.add(1);
+        assert_eq!(u.measurement(get_timestamp()).unwrap()["unit"], "1");
+        let mut g: Gauge<u64> = meter.create_gauge("rss").set_unit("By").build_detached();
+        g.set(1);
+        assert_eq!(g.measurement(get_timestamp()).unwrap()["unit"], "By");
+
+        let mut plain: Histogram<f64> = meter.create_histogram("plain").build_detached();
+        assert_eq!(plain.unit(), None);
+        plain.record(1.0);
+        let json = plain.measurement(get_timestamp()).unwrap();
+        assert!(json.get("unit").is_none());
+    }
+
+    #[test]
+    fn otlp_json() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), Some("1.0".into()), None, None);
+        let h = meter
+            .create_histogram::<f64>("latency")
+            .set_description("Request latency")
+            .set_unit("s")
+            .add_attribute("host", "localhost".into())
+            .build();
+        let c = meter.create_counter::<u64>("requests").build();
+        let u = meter.create_up_down_counter::<i64>("in_flight").build();
+        let g = meter.create_gauge::<f64>("temperature").build();
+        // Never updated, so these have nothing to export.
+        meter.create_counter::<u64>("idle").build();
+        meter.create_histogram::<f64>("unused").build();
+        meter
+            .create_histogram::<f64>("unused_buckets")
+            .with_bounds(&[1.0, 5.0])
+            .build();
+        for v in [0.5, 1.0, 1.0, 2.5] {
+            h.lock().unwrap().record(v);
+            c.lock().unwrap().add(1);
+        }
+        u.lock().unwrap().add(-2);
+        g.lock().unwrap().set(21.5);
+        let h_start = h.lock().unwrap().start_time;
+        let c_start = c.lock().unwrap().start_time;
+        let u_start = u.lock().unwrap().start_time;
+
+        let mut actual: serde_json::Value = serde_json::from_str(&mp.export_otlp_json()).unwrap();
+        // The export is stamped with the current time, so check it separately.
+        let metrics = actual["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array_mut()
+            .unwrap();
+        assert_eq!(metrics.len(), 4);
+        for (metric, kind) in metrics.iter_mut().zip(["histogram", "sum", "sum", "gauge"]) {
+            let data_point = &mut metric[kind]["dataPoints"][0];
+            let time: u128 = data_point["timeUnixNano"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(time >= h_start);
+            data_point["timeUnixNano"] = "0".into();
+        }
+        let expected = serde_json::json!({
+            "resourceMetrics": [{
+                "resource": { "attributes": [] },
+                "scopeMetrics": [{
+                    "scope": { "name": "test", "version": "1.0", "attributes": [] },
+                    "metrics": [{
+                        "name": "latency",
+                        "description": "Request latency",
+                        "unit": "s",
+                        "histogram": {
+                            "dataPoints": [{
+                                "attributes": [
+                                    { "key": "host", "value": { "stringValue": "localhost" } }
+                                ],
+                                "startTimeUnixNano": h_start.to_string(),
+                                "timeUnixNano": "0",
+                                "count": "4",
+                                "sum": 5.0,
+                                "bucketCounts": ["1", "2", "1", "0"],
+                                "explicitBounds": [0.5, 1.0, 2.5],
+                                "min": 0.5,
+                                "max": 2.5
+                            }],
+                            "aggregationTemporality": 1
+                        }
+                    }, {
+                        "name": "requests",
+                        "sum": {
+                            "dataPoints": [{
+                                "attributes": [],
+                                "startTimeUnixNano": c_start.to_string(),
+                                "timeUnixNano": "0",
+                                "asInt": "4"
+                            }],
+                            "aggregationTemporality": 1,
+                            "isMonotonic": true
+                        }
+                    }, {
+                        "name": "in_flight",
+                        "sum": {
+                            "dataPoints": [{
+                                "attributes": [],
+                                "startTimeUnixNano": u_start.to_string(),
+                                "timeUnixNano": "0",
+                                "asInt": "-2"
+                            }],
+                            "aggregationTemporality": 2,
+                            "isMonotonic": false
+                        }
+                    }, {
+                        "name": "temperature",
+                        "gauge": {
+                            "dataPoints": [{
+                                "attributes": [],
+                                "timeUnixNano": "0",
+                                "asDouble": 21.5
+                            }]
+                        }
+                    }]
+                }]
+            }]
+        });
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn set_attribute() {
+        let response = block_on(ui::serve(Request::get("/push").body(()).unwrap())).unwrap();
+        let mut body = response.into_body();
+
+        let mut mp = MeterProvider::default();
+        let mut h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("set_attribute")
+            .add_attribute("stale", "yes".into())
+            .build_detached();
+        h.record(1.0);
+        h.set_attribute("host", "example.com".into());
+        assert!(h.remove_attribute("stale").is_some());
+        assert!(h.remove_attribute("missing").is_none());
+        h.push(get_timestamp());
+
+        // Other tests push to the same server, so look for this histogram's update.
+        loop {
+            let frame = block_on(body.frame()).unwrap().unwrap();
+            let data = match frame.into_data() {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let event = String::from_utf8_lossy(&data);
+            if event.contains(r#""name":"set_attribute""#) {
+                assert!(event.contains(r#""attributes":{"host":"example.com"}"#));
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn attribute_types() {
+        let mut attributes = Attributes::default();
+        attributes.insert("host".into(), "localhost".into());
+        attributes.insert("cpu".into(), 3i64.into());
+        attributes.insert("load".into(), 0.5.into());
+        attributes.insert("primary".into(), true.into());
+        attributes.insert("tags".into(), vec!["a".to_string(), "b".to_string()].into());
+        let json = serde_json::to_value(&attributes).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "host": "localhost",
+                "cpu": 3,
+                "load": 0.5,
+                "primary": true,
+                "tags": ["a", "b"],
+            })
+        );
+
+        // The builder accepts every kind of value.
+        let mut mp = MeterProvider::default();
+        let h: Histogram<f64> = mp
+            .get_meter("test".into(), None, None, None)
+            .create_histogram("latency")
+            .add_attribute("cpu", AttributeValue::Int(3))
+            .add_attribute("primary", AttributeValue::Bool(false))
+            .build_detached();
+        assert_eq!(h.attributes.len(), 2);
+    }
+
+    #[test]
+    fn push_all() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let h: Arc<Mutex<Histogram<f64>>> = meter.create_histogram("latency").build();
+        let c: Arc<Mutex<Counter<u64>>> = meter.create_counter("requests").build();
+        h.lock().unwrap().record(1.0);
+        c.lock().unwrap().add(1);
+
+        meter.push_all(get_timestamp());
+        assert!(h.lock().unwrap().ecdf().is_empty());
+        assert_eq!(c.lock().unwrap().total(), 0);
+
+        h.lock().unwrap().record(2.0);
+        mp.push_all(get_timestamp());
+        assert!(h.lock().unwrap().ecdf().is_empty());
+    }
+
+    #[test]
+    fn shutdown() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let h: Arc<Mutex<Histogram<f64>>> = meter.create_histogram("shutdown").build();
+        h.lock().unwrap().record(1.0);
+        mp.shutdown();
+        // Pushing a delta histogram clears it.
+        assert!(h.lock().unwrap().ecdf().is_empty());
+    }
+
+    #[test]
+    fn push_cycle() {
+        let response = block_on(ui::serve(Request::get("/push").body(()).unwrap())).unwrap();
+        let mut body = response.into_body();
+
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let h: Arc<Mutex<Histogram<f64>>> = meter.create_histogram("cycle_histogram").build();
+        let c: Arc<Mutex<Counter<u64>>> = meter.create_counter("cycle_counter").build();
+        for _ in 0..2 {
+            h.lock().unwrap().record(1.0);
+            c.lock().unwrap().add(1);
+            mp.push_all(get_timestamp());
+        }
+
+        // Other tests push to the same server, so look for these instruments' updates.
+        let mut cycles = Vec::new();
+        while cycles.len() < 4 {
+            let frame = block_on(body.frame()).unwrap().unwrap();
+            let data = match frame.into_data() {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let event = String::from_utf8_lossy(&data);
+            if !event.contains(r#""name":"cycle_"#) {
+                continue;
+            }
+            let json = event
+                .lines()
+                .find_map(|l| l.strip_prefix("data: "))
+                .unwrap();
+            let measurement: serde_json::Value = serde_json::from_str(json).unwrap();
+            cycles.push(measurement["cycle"].as_u64().unwrap());
+        }
+        assert_eq!(cycles[0], cycles[1]);
+        assert_eq!(cycles[2], cycles[3]);
+        assert!(cycles[2] > cycles[0]);
+    }
+
+    #[test]
+    fn gauge_push_cycles() {
+        let response = block_on(ui::serve(Request::get("/push").body(()).unwrap())).unwrap();
+        let mut body = response.into_body();
+
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let g: Arc<Mutex<Gauge<u64>>> = meter.create_gauge("held_gauge").build();
+        let u: Arc<Mutex<UpDownCounter<i64>>> =
+            meter.create_up_down_counter("held_up_down").build();
+        g.lock().unwrap().set(42);
+        u.lock().unwrap().add(-1);
+        // The gauge isn't set again, so both cycles report the same value.
+        mp.push_all(get_timestamp());
+        mp.push_all(get_timestamp());
+
+        // Other tests push to the same server, so look for these instruments' updates.
+        let mut events = Vec::new();
+        while events.len() < 4 {
+            let frame = block_on(body.frame()).unwrap().unwrap();
+            let data = match frame.into_data() {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let event = String::from_utf8_lossy(&data);
+            if !event.contains(r#""name":"held_"#) {
+                continue;
+            }
+            let kind = event
+                .lines()
+                .find_map(|l| l.strip_prefix("event: "))
+                .unwrap()
+                .to_string();
+            let json = event
+                .lines()
+                .find_map(|l| l.strip_prefix("data: "))
+                .unwrap();
+            let measurement: serde_json::Value = serde_json::from_str(json).unwrap();
+            events.push((kind, measurement["value"].as_i64().unwrap()));
+        }
+        let gauges: Vec<i64> = events
+            .iter()
+            .filter(|(kind, _)| kind == "gauge")
+            .map(|&(_, value)| value)
+            .collect();
+        assert_eq!(gauges, [42, 42]);
+        let up_downs: Vec<i64> = events
+            .iter()
+            .filter(|(kind, _)| kind == "up_down_counter")
+            .map(|&(_, value)| value)
+            .collect();
+        assert_eq!(up_downs, [-1, -1]);
+    }
+
+    #[test]
+    fn shrink_on_push() {
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut kept: Histogram<f64> = meter.create_histogram("kept").build_detached();
+        let mut shrunk: Histogram<f64> = meter
+            .create_histogram("shrunk")
+            .with_shrink_on_push(true)
+            .build_detached();
+        for i in 0..10_000 {
+            kept.record(i as f64);
+            shrunk.record(i as f64);
+        }
+        assert!(shrunk.ecdf.samples.capacity() >= 10_000);
+
+        let timestamp = get_timestamp();
+        kept.push_cycle(timestamp, None);
+        shrunk.push_cycle(timestamp, None);
+        assert!(kept.ecdf().is_empty());
+        assert!(shrunk.ecdf().is_empty());
+        assert!(kept.ecdf.samples.capacity() >= 10_000);
+        assert_eq!(shrunk.ecdf.samples.capacity(), 0);
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let mut limiter = RateLimiter::new(Duration::from_millis(20));
+        assert!(limiter.ready());
+        assert!(!limiter.ready());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(limiter.ready());
+        assert!(!limiter.ready());
+
+        let mut unlimited = RateLimiter::new(Duration::ZERO);
+        assert!(unlimited.ready());
+        assert!(unlimited.ready());
+    }
+
+    #[test]
+    fn live_updates() {
+        let response = block_on(ui::serve(Request::get("/push").body(()).unwrap())).unwrap();
+        let mut body = response.into_body();
+
+        let mut mp = MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let mut off: Histogram<f64> = meter.create_histogram("live_off").build_detached();
+        let mut on: Histogram<f64> = meter
+            .create_histogram("live_on")
+            .with_live_updates(true)
+            .build_detached();
+        let mut end: Histogram<f64> = meter
+            .create_histogram("live_end")
+            .with_live_updates(true)
+            .build_detached();
+        off.record(1.0);
+        on.record(2.0);
+        // This is coalesced with the previous record.
+        on.record(3.0);
+        end.record(4.0);
+
+        // Other tests push to the same server, so look for these histograms'
+        // events, which arrive in the order they were sent.
+        let mut records = Vec::new();
+        loop {
+            let frame = block_on(body.frame()).unwrap().unwrap();
+            let data = match frame.into_data() {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let event = String::from_utf8_lossy(&data);
+            if !event.starts_with("event: record\n") || !event.contains(r#""name":"live_"#) {
+                continue;
+            }
+            let json = event
+                .lines()
+                .find_map(|l| l.strip_prefix("data: "))
+                .unwrap();
+            let measurement: serde_json::Value = serde_json::from_str(json).unwrap();
+            let name = measurement["name"].as_str().unwrap().to_string();
+            records.push((name, measurement["value"].as_f64().unwrap()));
+            if records.last().unwrap().0 == "live_end" {
+                break;
+            }
+        }
+        assert_eq!(
+            records,
+            vec![("live_on".to_string(), 2.0), ("live_end".to_string(), 4.0)]
+        );
+        // Live updates don't take the place of pushes.
+        assert_eq!(on.ecdf().len(), 2);
     }
 }
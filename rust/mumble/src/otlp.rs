@@ -0,0 +1,287 @@
+// Serialization of instruments using the OTLP JSON encoding.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// These types mirror the OTLP protobuf messages, using the JSON mapping:
+// https://github.com/open-telemetry/opentelemetry-proto/blob/main/opentelemetry/proto/metrics/v1/metrics.proto
+// https://opentelemetry.io/docs/specs/otlp/#json-protobuf-encoding
+
+use crate::{AttributeValue, Attributes, InstrumentationScope};
+use serde::Serialize;
+
+/// AGGREGATION_TEMPORALITY_DELTA
+const AGGREGATION_TEMPORALITY_DELTA: u32 = 1;
+/// AGGREGATION_TEMPORALITY_CUMULATIVE
+const AGGREGATION_TEMPORALITY_CUMULATIVE: u32 = 2;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MetricsData {
+    pub resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResourceMetrics {
+    pub resource: Resource,
+    pub scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Resource {
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScopeMetrics {
+    pub scope: Scope,
+    pub metrics: Vec<Metric>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_url: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Scope {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub attributes: Vec<KeyValue>,
+}
+
+impl Scope {
+    pub fn new(key: &InstrumentationScope, attributes: &Attributes) -> Scope {
+        Scope {
+            name: key.name.clone(),
+            version: key.version.clone(),
+            attributes: key_values(attributes),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct KeyValue {
+    pub key: String,
+    pub value: AnyValue,
+}
+
+#[derive(Serialize)]
+pub(crate) enum AnyValue {
+    #[serde(rename = "stringValue")]
+    String(String),
+    #[serde(rename = "intValue", serialize_with = "to_string")]
+    Int(i64),
+    #[serde(rename = "doubleValue")]
+    Double(f64),
+    #[serde(rename = "boolValue")]
+    Bool(bool),
+    #[serde(rename = "arrayValue")]
+    Array(ArrayValue),
+}
+
+#[derive(Serialize)]
+pub(crate) struct ArrayValue {
+    pub values: Vec<AnyValue>,
+}
+
+impl From<&AttributeValue> for AnyValue {
+    fn from(value: &AttributeValue) -> AnyValue {
+        match value {
+            AttributeValue::String(s) => AnyValue::String(s.clone()),
+            AttributeValue::Int(i) => AnyValue::Int(*i),
+            AttributeValue::Double(d) => AnyValue::Double(*d),
+            AttributeValue::Bool(b) => AnyValue::Bool(*b),
+            AttributeValue::StringArray(a) => AnyValue::Array(ArrayValue {
+                values: a.iter().cloned().map(AnyValue::String).collect(),
+            }),
+        }
+    }
+}
+
+/// Converts attributes to OTLP key/value pairs, sorted by key.
+pub(crate) fn key_values(attributes: &Attributes) -> Vec<KeyValue> {
+    attributes
+        .iter()
+        .map(|(k, v)| KeyValue {
+            key: k.clone(),
+            value: v.into(),
+        })
+        .collect()
+}
+
+/// A single metric, as reported by an [crate::Instrument].
+#[derive(Serialize)]
+pub struct Metric {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) unit: Option<String>,
+    #[serde(flatten)]
+    pub(crate) data: Data,
+}
+
+/// The data points of a [Metric], which are named after their type.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Data {
+    Histogram(Histogram),
+    Sum(Sum),
+    Gauge(Gauge),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Histogram {
+    pub data_points: Vec<HistogramDataPoint>,
+    pub aggregation_temporality: u32,
+}
+
+impl Histogram {
+    pub fn delta(data_point: HistogramDataPoint) -> Histogram {
+        Histogram {
+            data_points: vec![data_point],
+            aggregation_temporality: AGGREGATION_TEMPORALITY_DELTA,
+        }
+    }
+
+    pub fn cumulative(data_point: HistogramDataPoint) -> Histogram {
+        Histogram {
+            data_points: vec![data_point],
+            aggregation_temporality: AGGREGATION_TEMPORALITY_CUMULATIVE,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Sum {
+    pub data_points: Vec<NumberDataPoint>,
+    pub aggregation_temporality: u32,
+    pub is_monotonic: bool,
+}
+
+impl Sum {
+    pub fn new(data_point: NumberDataPoint, cumulative: bool, is_monotonic: bool) -> Sum {
+        Sum {
+            data_points: vec![data_point],
+            aggregation_temporality: if cumulative {
+                AGGREGATION_TEMPORALITY_CUMULATIVE
+            } else {
+                AGGREGATION_TEMPORALITY_DELTA
+            },
+            is_monotonic,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Gauge {
+    pub data_points: Vec<NumberDataPoint>,
+}
+
+impl Gauge {
+    pub fn new(data_point: NumberDataPoint) -> Gauge {
+        Gauge {
+            data_points: vec![data_point],
+        }
+    }
+}
+
+/// 64-bit integers are encoded as strings in OTLP JSON.
+fn to_string<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: ToString,
+{
+    serializer.serialize_str(&value.to_string())
+}
+
+fn to_optional_string<S>(value: &Option<u128>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match value {
+        Some(value) => to_string(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn to_strings<S>(values: &[usize], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_seq(values.iter().map(|v| v.to_string()))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HistogramDataPoint {
+    pub attributes: Vec<KeyValue>,
+    #[serde(serialize_with = "to_string")]
+    pub start_time_unix_nano: u128,
+    #[serde(serialize_with = "to_string")]
+    pub time_unix_nano: u128,
+    #[serde(serialize_with = "to_string")]
+    pub count: usize,
+    pub sum: f64,
+    #[serde(serialize_with = "to_strings")]
+    pub bucket_counts: Vec<usize>,
+    pub explicit_bounds: Vec<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct NumberDataPoint {
+    pub attributes: Vec<KeyValue>,
+    /// Not set for gauges, which report a value at a single point in time.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "to_optional_string"
+    )]
+    pub start_time_unix_nano: Option<u128>,
+    #[serde(serialize_with = "to_string")]
+    pub time_unix_nano: u128,
+    #[serde(flatten)]
+    pub value: NumberValue,
+}
+
+#[derive(Serialize)]
+pub(crate) enum NumberValue {
+    #[serde(rename = "asInt", serialize_with = "to_string")]
+    Int(i64),
+    #[serde(rename = "asDouble")]
+    Double(f64),
+}
+
+impl NumberValue {
+    /// Converts a value to the most precise representation available.
+    ///
+    /// Returns `None` if the value isn't a number.
+    pub fn new<T: Serialize>(value: &T) -> Option<NumberValue> {
+        match serde_json::to_value(value).ok()? {
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Some(NumberValue::Int(i)),
+                None => n.as_f64().map(NumberValue::Double),
+            },
+            _ => None,
+        }
+    }
+}
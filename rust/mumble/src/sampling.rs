@@ -0,0 +1,83 @@
+// Draws synthetic observations from a recorded distribution.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::ecdf::InterpolatedECDF;
+use num_traits::Float;
+use rand::Rng;
+use std::fmt::Debug;
+
+impl<V> InterpolatedECDF<V>
+where
+    V: Float + Debug,
+{
+    /// Draws a random value that follows this distribution.
+    ///
+    /// This is inverse transform sampling: a uniformly distributed quantile
+    /// is converted to a value with [InterpolatedECDF::quantile]. If there is
+    /// only one distinct value, there is nothing to interpolate between, so
+    /// that value is always returned. Returns NaN if the ECDF is empty.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> V {
+        if let [(v, _)] = self.samples[..] {
+            return v;
+        }
+        self.quantile(rng.gen())
+    }
+
+    /// Returns an endless iterator of random values that follow this
+    /// distribution, as if by calling [InterpolatedECDF::sample] repeatedly.
+    pub fn sample_iter<'a, R>(&'a self, mut rng: R) -> impl Iterator<Item = V> + 'a
+    where
+        R: Rng + 'a,
+    {
+        std::iter::repeat_with(move || self.sample(&mut rng))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecdf::ECDF;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn resample() {
+        let original = ECDF::from(
+            (0..=100)
+                .map(|x| (x as f64 / 100.0).powi(2))
+                .collect::<Vec<_>>(),
+        )
+        .interpolate();
+        let rng = SmallRng::seed_from_u64(1);
+        let resampled =
+            ECDF::from(original.sample_iter(rng).take(10_000).collect::<Vec<f64>>()).interpolate();
+        let diff = original.area_difference(&resampled);
+        assert!(diff < 0.01, "area difference was {}", diff);
+    }
+
+    #[test]
+    fn sample_empty() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        assert!(InterpolatedECDF::<f64>::default().sample(&mut rng).is_nan());
+    }
+
+    #[test]
+    fn sample_single_value() {
+        let ecdf = ECDF::from(vec![2.5, 2.5, 2.5]).interpolate();
+        let rng = SmallRng::seed_from_u64(1);
+        assert!(ecdf.sample_iter(rng).take(100).all(|v| v == 2.5));
+    }
+}
@@ -15,33 +15,110 @@
 // limitations under the License.
 
 use bytes::Bytes;
-use futures::channel::mpsc::{Receiver, Sender};
-use http::{Request, Response};
+use futures::future::{join_all, JoinAll};
+use futures::{Future, Stream};
+use futures_timer::Delay;
+use http::{Request, Response, StatusCode};
 use http_body::Frame;
 use http_body_util::StreamBody;
 use serde::Serialize;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::Infallible;
-use std::sync::Mutex;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 use std::time::{Duration, Instant};
 
 type Chunk = Result<Frame<Bytes>, Infallible>;
 
+/// The maximum number of events buffered for each client.
+const CLIENT_BUFFER_SIZE: usize = 100;
+
+/// How long a client may keep failing before it is removed.
+const STALE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum number of events kept for replay on each channel.
+const MAX_REPLAY_EVENTS: usize = 1000;
+
+/// How often maintenance should be performed.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Settings for a [Server].
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// The maximum number of events buffered for each client.
+    pub channel_capacity: usize,
+    /// How long a client may keep failing before it is removed.
+    ///
+    /// Stale clients are only removed by [Server::perform_maintenance], so
+    /// this should be tuned together with
+    /// [ServerConfig::heartbeat_interval].
+    pub stale_timeout: Duration,
+    /// The maximum number of events kept for replay on each channel.
+    ///
+    /// Once exceeded, the oldest events are dropped.
+    pub max_replay_events: usize,
+    /// How long clients should wait before reconnecting after losing the
+    /// connection.
+    ///
+    /// When set, this is sent to each client when it first connects.
+    /// Otherwise browsers use their own default, typically a few seconds.
+    pub retry: Option<Duration>,
+    /// A shared secret that clients must present to subscribe.
+    ///
+    /// When set, streams are only created for requests with an
+    /// `Authorization: Bearer <token>` header that matches it. The UI applies
+    /// the same check to its other data endpoints.
+    pub token: Option<String>,
+    /// How long events are kept for replay.
+    ///
+    /// Older events are dropped by [Server::perform_maintenance], so new
+    /// clients don't see data that is no longer valid. By default, events
+    /// are kept until [ServerConfig::max_replay_events] is exceeded.
+    pub replay_max_age: Option<Duration>,
+    /// How often the server's owner should call
+    /// [Server::perform_maintenance].
+    ///
+    /// Shorter intervals notice failing clients sooner, at the cost of
+    /// checking every client more often.
+    pub heartbeat_interval: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            channel_capacity: CLIENT_BUFFER_SIZE,
+            stale_timeout: STALE_TIMEOUT,
+            max_replay_events: MAX_REPLAY_EVENTS,
+            retry: None,
+            token: None,
+            replay_max_age: None,
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+        }
+    }
+}
+
 /// Push server implementing Server-Sent Events (SSE).
 pub struct Server {
     channels: Mutex<HashMap<String, Channel>>,
+    config: ServerConfig,
 }
 
 impl Default for Server {
     fn default() -> Self {
+        Server::new(ServerConfig::default())
+    }
+}
+
+impl Server {
+    pub fn new(config: ServerConfig) -> Self {
         Server {
             channels: Mutex::new(HashMap::new()),
+            config,
         }
     }
-}
 
-impl Server {
     /// Push an event to all clients subscribed to a channel.
     ///
     /// `message` is first serialized as JSON and then sent to all registered
@@ -62,7 +139,7 @@ impl Server {
         let mut channels = self.channels.lock().unwrap();
         let c = match channels.entry(channel.to_string()) {
             Entry::Occupied(o) => o.into_mut(),
-            Entry::Vacant(v) => v.insert(Channel::default()),
+            Entry::Vacant(v) => v.insert(Channel::new(self.config.max_replay_events)),
         };
         if replay {
             c.send_replayable_event(message);
@@ -72,12 +149,54 @@ impl Server {
         Ok(())
     }
 
+    /// Push an event to all clients subscribed to a channel, waiting for
+    /// slow clients to make room for it.
+    ///
+    /// This behaves like [Server::push], except that rather than dropping
+    /// the oldest buffered event when a client falls behind, it waits up to
+    /// `timeout` for the client to catch up. Clients that don't are marked as
+    /// stale, and are removed by the next round of maintenance.
+    pub async fn push_async<S: Serialize>(
+        &self,
+        channel: &str,
+        event: &str,
+        message: &S,
+        replay: bool,
+        timeout: Duration,
+    ) -> Result<(), serde_json::error::Error> {
+        let payload = serde_json::to_string(message)?;
+        let mut message = format!("event: {}\ndata: {}\n\n", event, payload);
+        let send = {
+            // Don't hold the lock while waiting for clients.
+            let mut channels = self.channels.lock().unwrap();
+            let c = match channels.entry(channel.to_string()) {
+                Entry::Occupied(o) => o.into_mut(),
+                Entry::Vacant(v) => v.insert(Channel::new(self.config.max_replay_events)),
+            };
+            if replay {
+                message = c.add_replayable_event(message);
+            }
+            c.send_event_async(message, timeout)
+        };
+        send.await;
+        Ok(())
+    }
+
     /// Initiate a new SSE stream for the given request.
+    ///
+    /// If the server requires a token and the request doesn't carry it, the
+    /// response is `401 Unauthorized` instead.
     pub fn create_stream<R>(
         &self,
         channel: &str,
         request: Request<R>,
-    ) -> http::Result<Response<StreamBody<Receiver<Chunk>>>> {
+    ) -> http::Result<Response<StreamBody<EventStream>>> {
+        if !self.authorized(&request) {
+            return Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header("WWW-Authenticate", "Bearer")
+                .body(StreamBody::new(EventStream::once(Bytes::default())));
+        }
         let last_id: usize = match request.headers().get("Last-Event-ID") {
             None => 0,
             Some(header) => header
@@ -86,15 +205,16 @@ impl Server {
                 .unwrap_or(0),
         };
 
-        let (tx, rx) = futures::channel::mpsc::channel(100);
-        let client = Client {
-            tx,
-            first_error: None,
-        };
+        let (rx, queue) = EventStream::new(self.config.channel_capacity);
+        let mut client = Client { queue };
+        if let Some(retry) = self.config.retry {
+            // This has no data, so it isn't dispatched as an event.
+            client.send_event(format!("retry: {}\n\n", retry.as_millis()));
+        }
 
         match self.channels.lock().unwrap().entry(channel.to_string()) {
             Entry::Occupied(o) => o.into_mut(),
-            Entry::Vacant(v) => v.insert(Channel::default()),
+            Entry::Vacant(v) => v.insert(Channel::new(self.config.max_replay_events)),
         }
         .add_client(client, last_id);
 
@@ -106,30 +226,175 @@ impl Server {
             .body(StreamBody::new(rx))
     }
 
+    /// Whether the request carries the token this server requires, if any.
+    pub(crate) fn authorized<R>(&self, request: &Request<R>) -> bool {
+        let token = match &self.config.token {
+            Some(token) => token,
+            None => return true,
+        };
+        request
+            .headers()
+            .get_all("Authorization")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.strip_prefix("Bearer "))
+            .any(|presented| constant_time_eq(presented.trim().as_bytes(), token.as_bytes()))
+    }
+
+    /// The number of events kept for replay on a channel.
+    #[cfg(test)]
+    pub fn replay_len(&self, channel: &str) -> usize {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map_or(0, |c| c.replayable_events.len())
+    }
+
+    /// The events that would be replayed to a new client on a channel.
+    #[cfg(test)]
+    pub fn replay_events(&self, channel: &str) -> Vec<String> {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map_or_else(Vec::new, |c| {
+                c.replayable_events
+                    .iter()
+                    .map(|(_, event)| event.clone())
+                    .collect()
+            })
+    }
+
+    /// The names of all channels, sorted.
+    pub fn channel_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.channels.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The number of clients subscribed to a channel.
+    ///
+    /// Clients that have disconnected are counted until they are removed
+    /// by [Server::perform_maintenance].
+    pub fn client_count(&self, channel: &str) -> usize {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map_or(0, |c| c.clients.len())
+    }
+
+    /// The number of clients subscribed to any channel.
+    pub fn total_clients(&self) -> usize {
+        self.channels
+            .lock()
+            .unwrap()
+            .values()
+            .map(|c| c.clients.len())
+            .sum()
+    }
+
+    /// Removes a channel, disconnecting its clients and forgetting its replay log.
+    pub fn remove_channel(&self, channel: &str) {
+        self.channels.lock().unwrap().remove(channel);
+    }
+
+    /// Forgets the events kept for replay on a channel.
+    ///
+    /// Clients that connect afterwards only receive events pushed from now
+    /// on. Event IDs keep counting, so reconnecting clients aren't confused.
+    pub fn clear_replay(&self, channel: &str) {
+        if let Some(c) = self.channels.lock().unwrap().get_mut(channel) {
+            c.clear_replay();
+        }
+    }
+
     pub fn perform_maintenance(&self) {
         for channel in self.channels.lock().unwrap().values_mut() {
-            channel.perform_maintenance();
+            channel.perform_maintenance(self.config.stale_timeout, self.config.replay_max_age);
         }
     }
+
+    /// Disconnects every client and forgets every channel.
+    ///
+    /// Events already buffered for a client are still delivered, after which
+    /// its stream ends, so the response finishes cleanly rather than being
+    /// cut off.
+    pub fn shutdown(&self) {
+        let channels = std::mem::take(&mut *self.channels.lock().unwrap());
+        let clients: usize = channels.values().map(|c| c.clients.len()).sum();
+        info!("Closing {} client streams", clients);
+    }
+}
+
+/// Compares secrets without revealing how much of them matched through timing.
+///
+/// Every byte of the secret `b` is compared, however long `a` is, and a
+/// length mismatch is folded into the result rather than returning early, so
+/// that the time taken doesn't reveal the secret's length either.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let acc = b.iter().enumerate().fold(a.len() ^ b.len(), |acc, (i, y)| {
+        acc | usize::from(a.get(i).copied().unwrap_or(0) ^ y)
+    });
+    acc == 0
 }
 
-#[derive(Default)]
 struct Channel {
     clients: Vec<Client>,
-    replayable_events: Vec<String>,
+    /// Events kept for replay, and when they were added.
+    replayable_events: VecDeque<(Instant, String)>,
+    /// The number of replayable events that have been dropped.
+    replay_base: usize,
+    max_replay_events: usize,
 }
 
 impl Channel {
+    pub fn new(max_replay_events: usize) -> Self {
+        Channel {
+            clients: Vec::new(),
+            replayable_events: VecDeque::new(),
+            replay_base: 0,
+            max_replay_events,
+        }
+    }
+
     pub fn add_client(&mut self, mut client: Client, last_event: usize) {
-        for chunk in self.replayable_events.iter().skip(last_event) {
+        // Clients that missed events which are no longer kept get all that remain.
+        let skip = last_event.saturating_sub(self.replay_base);
+        for (_, chunk) in self.replayable_events.iter().skip(skip) {
             client.send_event(chunk.clone());
         }
         self.clients.push(client);
     }
 
-    pub fn perform_maintenance(&mut self) {
+    pub fn perform_maintenance(
+        &mut self,
+        stale_timeout: Duration,
+        replay_max_age: Option<Duration>,
+    ) {
         self.send_heartbeats();
-        self.remove_stale_clients();
+        self.remove_stale_clients(stale_timeout);
+        if let Some(max_age) = replay_max_age {
+            self.expire_replay(max_age);
+        }
+    }
+
+    /// Drops all replayable events.
+    fn clear_replay(&mut self) {
+        self.replay_base += self.replayable_events.len();
+        self.replayable_events.clear();
+    }
+
+    /// Drops replayable events that were added more than `max_age` ago.
+    fn expire_replay(&mut self, max_age: Duration) {
+        while let Some((added, _)) = self.replayable_events.front() {
+            if added.elapsed() <= max_age {
+                break;
+            }
+            self.replayable_events.pop_front();
+            self.replay_base += 1;
+        }
     }
 
     /// Send hearbeat to all clients.
@@ -148,10 +413,10 @@ impl Channel {
     ///
     /// This function should be called regularly (e.g. together with
     /// `send_heartbeats`) to keep the memory usage low.
-    fn remove_stale_clients(&mut self) {
+    fn remove_stale_clients(&mut self, stale_timeout: Duration) {
         self.clients.retain(|client| {
-            if let Some(first_error) = client.first_error {
-                if first_error.elapsed() > Duration::from_secs(5) {
+            if let Some(first_error) = client.first_error() {
+                if first_error.elapsed() > stale_timeout {
                     info!("Removing stale client");
                     return false;
                 }
@@ -162,12 +427,23 @@ impl Channel {
 
     /// Send an event to all clients.
     pub fn send_replayable_event(&mut self, chunk: String) {
-        let id = self.replayable_events.len() + 1;
-        let new_chunk = format!("id: {}\n{}", id, &chunk);
-        self.replayable_events.push(new_chunk.clone());
+        let new_chunk = self.add_replayable_event(chunk);
         self.send_event(new_chunk);
     }
 
+    /// Keeps an event for replay, returning it with its ID attached.
+    fn add_replayable_event(&mut self, chunk: String) -> String {
+        let id = self.replay_base + self.replayable_events.len() + 1;
+        let new_chunk = format!("id: {}\n{}", id, &chunk);
+        self.replayable_events
+            .push_back((Instant::now(), new_chunk.clone()));
+        while self.replayable_events.len() > self.max_replay_events {
+            self.replayable_events.pop_front();
+            self.replay_base += 1;
+        }
+        new_chunk
+    }
+
     /// Send an event to all clients.
     pub fn send_event(&mut self, chunk: String) {
         debug!("Sending: {}", &chunk);
@@ -175,30 +451,557 @@ impl Channel {
             client.send_event(chunk.clone());
         }
     }
+
+    /// Send an event to all clients, waiting up to `timeout` for each one
+    /// to make room for it.
+    pub fn send_event_async(&self, chunk: String, timeout: Duration) -> JoinAll<SendEvent> {
+        debug!("Sending: {}", &chunk);
+        let data = Bytes::from(chunk);
+        join_all(
+            self.clients
+                .iter()
+                .map(|client| client.send_event_async(data.clone(), timeout)),
+        )
+    }
 }
 
+/// Events waiting to be sent to a client.
 #[derive(Debug)]
-struct Client {
-    tx: Sender<Chunk>,
+struct Queue {
+    events: VecDeque<Bytes>,
+    capacity: usize,
+    waker: Option<Waker>,
+    /// Set when the client disconnects.
+    disconnected: bool,
+    /// Set when no more events will be added.
+    finished: bool,
+    /// When sending to the client first failed.
     first_error: Option<Instant>,
+    /// Senders waiting for room in the queue.
+    senders: Vec<Waker>,
+}
+
+impl Queue {
+    fn push(&mut self, data: Bytes) {
+        self.events.push_back(data);
+        // The client is keeping up again, so it shouldn't be reaped as stale.
+        if self.first_error.take().is_some() {
+            info!("Sending to client recovered");
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn set_error(&mut self, reason: &str) {
+        if self.first_error.is_none() {
+            error!("Unable to send event to client: {}", reason);
+            // Store time when an error was first seen
+            self.first_error = Some(Instant::now());
+        }
+    }
+}
+
+/// The stream of events sent to a single client.
+pub struct EventStream {
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl EventStream {
+    fn new(capacity: usize) -> (EventStream, Arc<Mutex<Queue>>) {
+        let queue = Arc::new(Mutex::new(Queue {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            waker: None,
+            disconnected: false,
+            finished: false,
+            first_error: None,
+            senders: Vec::new(),
+        }));
+        (
+            EventStream {
+                queue: queue.clone(),
+            },
+            queue,
+        )
+    }
+
+    /// Creates a stream that yields a single chunk of data.
+    pub fn once(data: Bytes) -> EventStream {
+        let (stream, queue) = EventStream::new(1);
+        let mut q = queue.lock().unwrap();
+        q.events.push_back(data);
+        q.finished = true;
+        drop(q);
+        stream
+    }
 }
 
-// TODO: Figure out how to implement a blocking send
+impl Stream for EventStream {
+    type Item = Chunk;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut q = self.queue.lock().unwrap();
+        match q.events.pop_front() {
+            Some(data) => {
+                // Let any waiting senders know there's room.
+                for waker in q.senders.drain(..) {
+                    waker.wake();
+                }
+                Poll::Ready(Some(Ok(Frame::data(data))))
+            }
+            None if q.finished => Poll::Ready(None),
+            None => {
+                q.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        let mut q = self.queue.lock().unwrap();
+        q.disconnected = true;
+        // Don't leave senders waiting on a reader that's gone.
+        for waker in q.senders.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    queue: Arc<Mutex<Queue>>,
+}
 
 impl Client {
+    /// When sending to this client first failed, if ever.
+    fn first_error(&self) -> Option<Instant> {
+        self.queue.lock().unwrap().first_error
+    }
+
     fn send_event(&mut self, chunk: String) {
-        let result = self.tx.try_send(Ok(Frame::data(Bytes::from(chunk))));
-        match (&result, self.first_error) {
-            (Err(e), None) => {
-                error!("Unable to send event to client: {}", e);
-                // Store time when an error was first seen
-                self.first_error = Some(Instant::now());
+        let mut q = self.queue.lock().unwrap();
+        if q.disconnected {
+            q.set_error("disconnected");
+            return;
+        }
+        if q.events.len() >= q.capacity {
+            // The client isn't keeping up. Rather than give up on it, make
+            // room by dropping the oldest event, since the latest matters most.
+            debug!("Client buffer full, dropping oldest event");
+            q.events.pop_front();
+        }
+        q.push(Bytes::from(chunk));
+    }
+
+    /// Sends an event, waiting up to `timeout` for room in the client's buffer.
+    fn send_event_async(&self, data: Bytes, timeout: Duration) -> SendEvent {
+        SendEvent {
+            queue: self.queue.clone(),
+            data: Some(data),
+            deadline: Instant::now() + timeout,
+            timer: None,
+        }
+    }
+}
+
+/// A future that adds an event to a client's queue once there is room.
+pub struct SendEvent {
+    queue: Arc<Mutex<Queue>>,
+    data: Option<Bytes>,
+    deadline: Instant,
+    /// Wakes this future at the deadline, once it has had to wait.
+    timer: Option<Delay>,
+}
+
+impl Future for SendEvent {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let mut q = this.queue.lock().unwrap();
+        if q.disconnected {
+            q.set_error("disconnected");
+            return Poll::Ready(());
+        }
+        if q.events.len() < q.capacity {
+            if let Some(data) = this.data.take() {
+                q.push(data);
             }
-            (Ok(_), Some(_)) => {
-                // Clear error when write succeeds
-                self.first_error = None;
+            return Poll::Ready(());
+        }
+        if Instant::now() >= this.deadline {
+            q.set_error("timed out waiting for room in buffer");
+            return Poll::Ready(());
+        }
+        q.senders.push(cx.waker().clone());
+        // There's no runtime to provide a timer, so use one that shares a
+        // single background thread between all pending sends.
+        let deadline = this.deadline;
+        let timer = this
+            .timer
+            .get_or_insert_with(|| Delay::new(deadline.saturating_duration_since(Instant::now())));
+        if Pin::new(timer).poll(cx).is_ready() {
+            q.set_error("timed out waiting for room in buffer");
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let mut q = self.queue.lock().unwrap();
+        q.finished = true;
+        if let Some(waker) = q.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::StreamExt;
+    use http_body_util::BodyExt;
+    use std::thread;
+
+    fn collect(stream: EventStream) -> Vec<Bytes> {
+        block_on(
+            stream
+                .map(|chunk| chunk.unwrap().data_ref().unwrap().clone())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn slow_client_receives_latest() {
+        let (stream, queue) = EventStream::new(CLIENT_BUFFER_SIZE);
+        let mut client = Client { queue };
+        for i in 0..CLIENT_BUFFER_SIZE + 10 {
+            client.send_event(i.to_string());
+        }
+        assert!(client.first_error().is_none());
+
+        let mut channel = Channel::new(MAX_REPLAY_EVENTS);
+        channel.add_client(client, 0);
+        channel.perform_maintenance(STALE_TIMEOUT, None);
+        assert_eq!(channel.clients.len(), 1);
+
+        // Dropping the channel ends the stream.
+        drop(channel);
+        let events = collect(stream);
+        assert_eq!(events.len(), CLIENT_BUFFER_SIZE);
+        // The oldest events (plus one for the heartbeat) were dropped.
+        assert_eq!(events[0], Bytes::from("11"));
+        assert_eq!(events[CLIENT_BUFFER_SIZE - 2], Bytes::from("109"));
+        assert_eq!(events[CLIENT_BUFFER_SIZE - 1], Bytes::from(":\n\n"));
+    }
+
+    #[test]
+    fn disconnected_client() {
+        let (stream, queue) = EventStream::new(CLIENT_BUFFER_SIZE);
+        let mut client = Client { queue };
+        client.send_event("hello".into());
+        assert!(client.first_error().is_none());
+        drop(stream);
+        client.send_event("goodbye".into());
+        assert!(client.first_error().is_some());
+    }
+
+    #[test]
+    fn once() {
+        assert_eq!(
+            collect(EventStream::once(Bytes::from("data"))),
+            vec![Bytes::from("data")]
+        );
+    }
+
+    fn connect(server: &Server, last_id: Option<&str>) -> StreamBody<EventStream> {
+        let mut request = Request::builder();
+        if let Some(id) = last_id {
+            request = request.header("Last-Event-ID", id);
+        }
+        server
+            .create_stream("test", request.body(()).unwrap())
+            .unwrap()
+            .into_body()
+    }
+
+    #[test]
+    fn replay_log() {
+        let server = Server::default();
+        assert_eq!(server.replay_len("test"), 0);
+        for i in 0..3 {
+            server.push("test", "target", &i, true).unwrap();
+            server.push("test", "update", &i, false).unwrap();
+            assert_eq!(server.replay_len("test"), i + 1);
+        }
+        assert_eq!(server.replay_len("other"), 0);
+        let replay = server.replay_events("test");
+        assert_eq!(replay[0], "id: 1\nevent: target\ndata: 0\n\n");
+
+        let first = connect(&server, None);
+        let second = connect(&server, Some("1"));
+        // Dropping the server ends the streams.
+        drop(server);
+        let first = block_on(BodyExt::collect(first)).unwrap().to_bytes();
+        let second = block_on(BodyExt::collect(second)).unwrap().to_bytes();
+        assert_eq!(first, Bytes::from(replay.concat()));
+        assert_eq!(second, Bytes::from(replay[1..].concat()));
+    }
+
+    #[test]
+    fn client_counts() {
+        let server = Server::new(ServerConfig {
+            stale_timeout: Duration::ZERO,
+            ..Default::default()
+        });
+        assert!(server.channel_names().is_empty());
+        let first = connect(&server, None);
+        let _second = connect(&server, None);
+        let _other = server.create_stream("other", Request::new(())).unwrap();
+        assert_eq!(server.channel_names(), vec!["other", "test"]);
+        assert_eq!(server.client_count("test"), 2);
+        assert_eq!(server.client_count("missing"), 0);
+        assert_eq!(server.total_clients(), 3);
+
+        // Disconnected clients are only noticed by maintenance.
+        drop(first);
+        assert_eq!(server.client_count("test"), 2);
+        server.perform_maintenance();
+        thread::sleep(Duration::from_millis(1));
+        server.perform_maintenance();
+        assert_eq!(server.client_count("test"), 1);
+        assert_eq!(server.total_clients(), 2);
+
+        server.remove_channel("test");
+        assert_eq!(server.channel_names(), vec!["other"]);
+        assert_eq!(server.total_clients(), 1);
+    }
+
+    #[test]
+    fn shutdown() {
+        let server = Server::default();
+        let stream = connect(&server, None);
+        server.push("test", "update", &1, false).unwrap();
+        server.shutdown();
+        assert_eq!(server.total_clients(), 0);
+        assert!(server.channel_names().is_empty());
+
+        // Buffered events are delivered before the stream ends.
+        let events = block_on(BodyExt::collect(stream)).unwrap().to_bytes();
+        assert_eq!(events, Bytes::from("event: update\ndata: 1\n\n"));
+    }
+
+    #[test]
+    fn replay_overflow() {
+        let server = Server::new(ServerConfig {
+            max_replay_events: 2,
+            ..Default::default()
+        });
+        for i in 0..5 {
+            server.push("test", "target", &i, true).unwrap();
+        }
+        assert_eq!(server.replay_len("test"), 2);
+        let replay = server.replay_events("test");
+        // IDs keep counting from the dropped events.
+        assert_eq!(replay[0], "id: 4\nevent: target\ndata: 3\n\n");
+        assert_eq!(replay[1], "id: 5\nevent: target\ndata: 4\n\n");
+
+        let current = connect(&server, Some("4"));
+        let stale = connect(&server, Some("1"));
+        drop(server);
+        let current = block_on(BodyExt::collect(current)).unwrap().to_bytes();
+        let stale = block_on(BodyExt::collect(stale)).unwrap().to_bytes();
+        assert_eq!(current, Bytes::from(replay[1].clone()));
+        // Events before the retained window are lost, so all retained are sent.
+        assert_eq!(stale, Bytes::from(replay.concat()));
+    }
+
+    #[test]
+    fn clear_replay() {
+        let server = Server::default();
+        for i in 0..3 {
+            server.push("test", "target", &i, true).unwrap();
+        }
+        server.clear_replay("test");
+        server.clear_replay("missing");
+        assert_eq!(server.replay_len("test"), 0);
+
+        let cleared = connect(&server, None);
+        server.push("test", "target", &3, true).unwrap();
+        let after = connect(&server, None);
+        drop(server);
+        let cleared = block_on(BodyExt::collect(cleared)).unwrap().to_bytes();
+        let after = block_on(BodyExt::collect(after)).unwrap().to_bytes();
+        // Nothing is replayed, but the new event still goes out, and IDs
+        // carry on from the cleared events.
+        assert_eq!(cleared, Bytes::from("id: 4\nevent: target\ndata: 3\n\n"));
+        assert_eq!(after, cleared);
+    }
+
+    #[test]
+    fn replay_max_age() {
+        let server = Server::new(ServerConfig {
+            replay_max_age: Some(Duration::from_millis(50)),
+            ..Default::default()
+        });
+        server.push("test", "target", &0, true).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        server.push("test", "target", &1, true).unwrap();
+        server.perform_maintenance();
+        assert_eq!(
+            server.replay_events("test"),
+            vec!["id: 2\nevent: target\ndata: 1\n\n"]
+        );
+
+        // Without a maximum age, events are kept.
+        let server = Server::default();
+        server.push("test", "target", &0, true).unwrap();
+        server.perform_maintenance();
+        assert_eq!(server.replay_len("test"), 1);
+    }
+
+    #[test]
+    fn retry() {
+        let server = Server::new(ServerConfig {
+            retry: Some(Duration::from_millis(2500)),
+            ..Default::default()
+        });
+        server.push("test", "target", &0, true).unwrap();
+        let stream = connect(&server, None);
+        server.push("test", "update", &1, false).unwrap();
+        drop(server);
+        let events = block_on(BodyExt::collect(stream)).unwrap().to_bytes();
+        let events = String::from_utf8(events.to_vec()).unwrap();
+        assert!(events.starts_with("retry: 2500\n\n"), "{}", events);
+        assert_eq!(events.matches("retry:").count(), 1);
+
+        // Without a setting, nothing is sent.
+        let server = Server::default();
+        let stream = connect(&server, None);
+        drop(server);
+        let events = block_on(BodyExt::collect(stream)).unwrap().to_bytes();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn token() {
+        let server = Server::new(ServerConfig {
+            token: Some("secret".into()),
+            ..Default::default()
+        });
+        let subscribe = |authorization: Option<&str>| {
+            let mut request = Request::builder();
+            if let Some(value) = authorization {
+                request = request.header("Authorization", value);
             }
-            _ => {}
+            server
+                .create_stream("test", request.body(()).unwrap())
+                .unwrap()
+                .status()
+        };
+        assert_eq!(subscribe(None), StatusCode::UNAUTHORIZED);
+        assert_eq!(subscribe(Some("Bearer wrong")), StatusCode::UNAUTHORIZED);
+        assert_eq!(subscribe(Some("Basic secret")), StatusCode::UNAUTHORIZED);
+        assert_eq!(server.total_clients(), 0);
+        assert_eq!(subscribe(Some("Bearer secret")), StatusCode::OK);
+        assert_eq!(server.total_clients(), 1);
+
+        // Without a token, anyone may subscribe.
+        let server = Server::default();
+        let response = server.create_stream("test", Request::new(())).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn constant_time_eq_lengths() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secreT", b"secret"));
+        // Prefixes and extensions of the secret don't match.
+        assert!(!constant_time_eq(b"secre", b"secret"));
+        assert!(!constant_time_eq(b"secrets", b"secret"));
+        assert!(!constant_time_eq(b"", b"secret"));
+        assert!(!constant_time_eq(b"secret\0", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn channel_capacity() {
+        let server = Server::new(ServerConfig {
+            channel_capacity: 1,
+            ..Default::default()
+        });
+        let stream = connect(&server, None);
+        for i in 0..3 {
+            server.push("test", "target", &i, false).unwrap();
         }
+        drop(server);
+        let events = block_on(BodyExt::collect(stream)).unwrap().to_bytes();
+        // Only the latest event fits in the buffer.
+        assert_eq!(events, Bytes::from("event: target\ndata: 2\n\n"));
+    }
+
+    #[test]
+    fn send_waits_for_room() {
+        let (mut stream, queue) = EventStream::new(2);
+        let client = Client { queue };
+        block_on(client.send_event_async(Bytes::from("1"), Duration::from_secs(1)));
+        block_on(client.send_event_async(Bytes::from("2"), Duration::from_secs(1)));
+        let reader = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let first = block_on(stream.next()).unwrap().unwrap();
+            (first.data_ref().unwrap().clone(), stream)
+        });
+        block_on(client.send_event_async(Bytes::from("3"), Duration::from_secs(10)));
+        assert!(client.first_error().is_none());
+
+        let (first, stream) = reader.join().unwrap();
+        assert_eq!(first, Bytes::from("1"));
+        drop(client);
+        assert_eq!(collect(stream), vec![Bytes::from("2"), Bytes::from("3")]);
+    }
+
+    #[test]
+    fn send_timeout() {
+        let (stream, queue) = EventStream::new(1);
+        let client = Client { queue };
+        block_on(client.send_event_async(Bytes::from("1"), Duration::from_millis(10)));
+        assert!(client.first_error().is_none());
+        // Nobody is reading, so this one times out and is dropped.
+        block_on(client.send_event_async(Bytes::from("2"), Duration::from_millis(10)));
+        assert!(client.first_error().is_some());
+        drop(client);
+        assert_eq!(collect(stream), vec![Bytes::from("1")]);
+    }
+
+    #[test]
+    fn send_timeout_recovers() {
+        let (mut stream, queue) = EventStream::new(1);
+        let client = Client { queue };
+        block_on(client.send_event_async(Bytes::from("1"), Duration::from_millis(10)));
+        block_on(client.send_event_async(Bytes::from("2"), Duration::from_millis(10)));
+        assert!(client.first_error().is_some());
+
+        // Once the client drains its queue, the next send succeeds.
+        assert_eq!(
+            block_on(stream.next())
+                .unwrap()
+                .unwrap()
+                .data_ref()
+                .unwrap(),
+            &Bytes::from("1")
+        );
+        block_on(client.send_event_async(Bytes::from("3"), Duration::from_millis(10)));
+        assert!(client.first_error().is_none());
+
+        let mut channel = Channel::new(MAX_REPLAY_EVENTS);
+        channel.add_client(client, 0);
+        channel.perform_maintenance(Duration::ZERO, None);
+        assert_eq!(channel.clients.len(), 1);
     }
 }
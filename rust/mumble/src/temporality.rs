@@ -0,0 +1,161 @@
+// Conversion between delta and cumulative temporality.
+// Copyright (C) 2023, Tony Rippy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::ecdf::ECDF;
+use num_traits::{Num, ToPrimitive};
+use std::cmp::Ordering;
+use std::fmt::Debug;
+
+/// How successive pushes of an instrument relate to each other.
+///
+/// For more information, see the
+/// [Open Telemetry specification](https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/metrics/data-model.md#temporality).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Temporality {
+    /// Each push only contains samples recorded since the previous push.
+    Delta,
+    /// Each push contains all samples recorded since the instrument was created.
+    Cumulative,
+}
+
+/// Converts a series of pushed ECDFs from one [Temporality] to the other.
+pub struct TemporalityConverter<V> {
+    target: Temporality,
+    previous: ECDF<V>,
+}
+
+impl<V> TemporalityConverter<V>
+where
+    V: Num + ToPrimitive + PartialOrd + Copy + Debug,
+{
+    /// Creates a converter that outputs ECDFs with the `target` temporality.
+    ///
+    /// The inputs are expected to have the opposite temporality.
+    pub fn new(target: Temporality) -> Self {
+        TemporalityConverter {
+            target,
+            previous: ECDF {
+                samples: Vec::new(),
+            },
+        }
+    }
+
+    /// The temporality of the converted ECDFs.
+    pub fn target(&self) -> Temporality {
+        self.target
+    }
+
+    /// Converts the next ECDF in the series.
+    ///
+    /// Delta inputs are merged into a running total. Cumulative inputs have
+    /// the previous input subtracted from them. If a cumulative input is
+    /// missing samples from the previous one, the source is assumed to have
+    /// been reset, and the input is returned as the delta.
+    pub fn convert(&mut self, ecdf: &ECDF<V>) -> ECDF<V> {
+        match self.target {
+            Temporality::Cumulative => {
+                self.previous = self.previous.merge(ecdf);
+                self.previous.clone()
+            }
+            Temporality::Delta => {
+                let delta = subtract(ecdf, &self.previous).unwrap_or_else(|| ecdf.clone());
+                self.previous = ecdf.clone();
+                delta
+            }
+        }
+    }
+
+    /// Forgets the state accumulated so far.
+    pub fn reset(&mut self) {
+        self.previous.clear();
+    }
+}
+
+/// Removes the samples in `b` from `a`, or returns `None` if `a` doesn't contain them all.
+fn subtract<V>(a: &ECDF<V>, b: &ECDF<V>) -> Option<ECDF<V>>
+where
+    V: PartialOrd + Copy,
+{
+    let mut samples = Vec::with_capacity(a.samples.len());
+    let mut a_iter = a.samples.iter();
+    for &(bv, bn) in b.samples.iter() {
+        loop {
+            let &(av, an) = a_iter.next()?;
+            match av.partial_cmp(&bv)? {
+                Ordering::Less => samples.push((av, an)),
+                Ordering::Equal => {
+                    match an.checked_sub(bn)? {
+                        0 => {}
+                        n => samples.push((av, n)),
+                    }
+                    break;
+                }
+                Ordering::Greater => return None,
+            }
+        }
+    }
+    samples.extend(a_iter);
+    Some(ECDF { samples })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deltas() -> Vec<ECDF<i32>> {
+        vec![
+            ECDF::from(vec![1, 2]),
+            ECDF::from(vec![2, 3]),
+            ECDF::default(),
+            ECDF::from(vec![0, 5, 5]),
+        ]
+    }
+
+    #[test]
+    fn delta_to_cumulative() {
+        let mut converter = TemporalityConverter::new(Temporality::Cumulative);
+        let mut running = ECDF::default();
+        for delta in deltas() {
+            running = running.merge(&delta);
+            assert_eq!(converter.convert(&delta).samples, running.samples);
+        }
+        assert_eq!(
+            running.samples,
+            vec![(0, 1), (1, 1), (2, 2), (3, 1), (5, 2)]
+        );
+    }
+
+    #[test]
+    fn cumulative_to_delta() {
+        let mut to_cumulative = TemporalityConverter::new(Temporality::Cumulative);
+        let mut to_delta = TemporalityConverter::new(Temporality::Delta);
+        for delta in deltas() {
+            let cumulative = to_cumulative.convert(&delta);
+            assert_eq!(to_delta.convert(&cumulative).samples, delta.samples);
+        }
+    }
+
+    #[test]
+    fn cumulative_reset() {
+        let mut converter = TemporalityConverter::new(Temporality::Delta);
+        converter.convert(&ECDF::from(vec![1, 2, 2]));
+        // The source restarted, so this doesn't contain the earlier samples.
+        let restarted = ECDF::from(vec![2, 3]);
+        assert_eq!(converter.convert(&restarted).samples, restarted.samples);
+        let next = ECDF::from(vec![2, 3, 4]);
+        assert_eq!(converter.convert(&next).samples, vec![(4, 1)]);
+    }
+}
@@ -13,31 +13,140 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::sse::EventStream;
 use bytes::Bytes;
-use futures::channel::mpsc::Receiver;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use http::{Request, Response, StatusCode};
-use http_body::{Body, Frame};
+use http_body::Body;
 use http_body_util::StreamBody;
 use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::sync::OnceLock;
 use std::time::Duration;
 
+pub use crate::sse::ServerConfig;
+
 const INDEX_HTML: &[u8] = include_bytes!("../ui/dist/index.html");
 const INDEX_JS: &[u8] = include_bytes!("../ui/dist/main.min.js");
 
-pub const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(15);
+static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
 
 lazy_static! {
-    static ref PUSH_SERVER: crate::sse::Server = crate::sse::Server::default();
+    static ref INDEX_HTML_ASSET: StaticAsset =
+        StaticAsset::new("text/html; charset=utf-8", INDEX_HTML);
+    static ref INDEX_JS_ASSET: StaticAsset =
+        StaticAsset::new("text/javascript; charset=utf-8", INDEX_JS);
+    static ref PUSH_SERVER: crate::sse::Server =
+        crate::sse::Server::new(CONFIG.get_or_init(ServerConfig::default).clone());
+}
+
+/// Configures the push server.
+///
+/// This must be called before the UI is first used, and only once.
+/// Otherwise the config is returned as an error.
+pub fn configure(config: ServerConfig) -> Result<(), ServerConfig> {
+    CONFIG.set(config)
+}
+
+fn oneshot_send(data: Bytes) -> StreamBody<EventStream> {
+    StreamBody::new(EventStream::once(data))
+}
+
+fn gzip(data: &[u8]) -> Bytes {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).expect("compress asset");
+    Bytes::from(encoder.finish().expect("compress asset"))
 }
 
-type Chunk = Result<Frame<Bytes>, Infallible>;
+/// Whether the client listed gzip in its `Accept-Encoding` header.
+fn accepts_gzip<R>(req: &Request<R>) -> bool {
+    req.headers()
+        .get_all("Accept-Encoding")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            // A quality of zero means the coding is not acceptable.
+            let refused = params.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f64>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
 
-fn oneshot_send(data: Bytes) -> StreamBody<Receiver<Chunk>> {
-    let (mut tx, rx) = futures::channel::mpsc::channel::<Chunk>(0);
-    tx.try_send(Ok(Frame::data(data)))
-        .expect("Failed to send oneshot data.");
-    StreamBody::new(rx)
+/// A file bundled into the binary, prepared once so it can be served cheaply.
+struct StaticAsset {
+    content_type: &'static str,
+    data: &'static [u8],
+    compressed: Bytes,
+    /// Identifies this version of the file, for conditional requests.
+    etag: String,
+}
+
+impl StaticAsset {
+    fn new(content_type: &'static str, data: &'static [u8]) -> StaticAsset {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        StaticAsset {
+            content_type,
+            data,
+            compressed: gzip(data),
+            etag: format!("{:016x}", hasher.finish()),
+        }
+    }
+
+    /// Serves the asset, compressed if the client supports it.
+    ///
+    /// If the client already has the same version, the body is left out.
+    fn serve<R>(&self, req: &Request<R>) -> http::Result<Response<StreamBody<EventStream>>> {
+        let gzip = accepts_gzip(req);
+        // The compressed and uncompressed bodies are different representations,
+        // so they need different tags.
+        let etag = if gzip {
+            format!("\"{}-gzip\"", self.etag)
+        } else {
+            format!("\"{}\"", self.etag)
+        };
+        let response = Response::builder()
+            .header("Vary", "Accept-Encoding")
+            .header("Cache-Control", "no-cache")
+            .header("ETag", &etag);
+        if matches_etag(req, &etag) {
+            return response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(oneshot_send(Bytes::default()));
+        }
+        let response = response
+            .header("Content-Type", self.content_type)
+            .status(StatusCode::OK);
+        if gzip {
+            response
+                .header("Content-Encoding", "gzip")
+                .body(oneshot_send(self.compressed.clone()))
+        } else {
+            response.body(oneshot_send(Bytes::from_static(self.data)))
+        }
+    }
+}
+
+/// Whether the request's `If-None-Match` header lists `etag`.
+fn matches_etag<R>(req: &Request<R>, etag: &str) -> bool {
+    req.headers()
+        .get_all("If-None-Match")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
 }
 
 // TODO: Box<dyn Body>
@@ -45,22 +154,68 @@ fn oneshot_send(data: Bytes) -> StreamBody<Receiver<Chunk>> {
 pub async fn serve<R>(
     req: Request<R>,
 ) -> http::Result<Response<impl Body<Data = Bytes, Error = Infallible>>> {
-    match req.uri().path() {
-        "/" => Response::builder()
-            .header("Content-Type", "text/html; charset=utf-8")
+    route(&PUSH_SERVER, req)
+}
+
+/// Dispatches a request, using `server` for the push stream and its stats.
+///
+/// The page and its script are public, but everything else exposes data and
+/// needs the server's token, if it has one.
+fn route<R>(
+    server: &crate::sse::Server,
+    req: Request<R>,
+) -> http::Result<Response<StreamBody<EventStream>>> {
+    let path = req.uri().path();
+    if path != "/" && path != "/js" && !server.authorized(&req) {
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("WWW-Authenticate", "Bearer")
+            .body(oneshot_send(Bytes::default()));
+    }
+    match path {
+        "/" => INDEX_HTML_ASSET.serve(&req),
+        "/js" => INDEX_JS_ASSET.serve(&req),
+        "/push" => server.create_stream("push", req),
+        "/metrics.json" => Response::builder()
+            .header("Content-Type", "application/json")
             .status(StatusCode::OK)
-            .body(oneshot_send(Bytes::from_static(INDEX_HTML))),
-        "/js" => Response::builder()
-            .header("Content-Type", "text/javascript; charset=utf-8")
+            .body(oneshot_send(Bytes::from(
+                serde_json::to_string(&crate::snapshot(crate::get_timestamp()))
+                    .expect("serialize metrics"),
+            ))),
+        "/stats" => Response::builder()
+            .header("Content-Type", "application/json")
             .status(StatusCode::OK)
-            .body(oneshot_send(Bytes::from_static(INDEX_JS))),
-        "/push" => PUSH_SERVER.create_stream("push", req),
+            .body(oneshot_send(Bytes::from(stats(server)))),
         _ => Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(oneshot_send(Bytes::default())),
     }
 }
 
+/// Health of the push server, served as JSON on `/stats`.
+#[derive(Serialize)]
+struct Stats {
+    total_clients: usize,
+    /// The number of clients on each channel.
+    channels: BTreeMap<String, usize>,
+}
+
+fn stats(server: &crate::sse::Server) -> String {
+    let stats = Stats {
+        total_clients: server.total_clients(),
+        channels: server
+            .channel_names()
+            .into_iter()
+            .map(|name| {
+                let count = server.client_count(&name);
+                (name, count)
+            })
+            .collect(),
+    };
+    serde_json::to_string(&stats).expect("serialize stats")
+}
+
 pub fn push<S: Serialize>(
     event: &str,
     message: &S,
@@ -69,6 +224,164 @@ pub fn push<S: Serialize>(
     PUSH_SERVER.push("push", event, message, permanent)
 }
 
+/// Like [push], but waits up to `timeout` for slow clients rather than
+/// dropping their oldest events.
+pub async fn push_async<S: Serialize>(
+    event: &str,
+    message: &S,
+    permanent: bool,
+    timeout: Duration,
+) -> Result<(), serde_json::error::Error> {
+    PUSH_SERVER
+        .push_async("push", event, message, permanent, timeout)
+        .await
+}
+
+/// How often [perform_maintenance] should be called, as configured.
+pub fn heartbeat_interval() -> Duration {
+    CONFIG.get_or_init(ServerConfig::default).heartbeat_interval
+}
+
 pub fn perform_maintenance() {
     PUSH_SERVER.perform_maintenance();
 }
+
+/// Forgets the permanent events pushed so far, so that clients connecting
+/// afterwards don't see data that is no longer valid.
+pub fn clear_replay() {
+    PUSH_SERVER.clear_replay("push");
+}
+
+/// Ends every client's event stream, once it has received what was pushed.
+pub fn shutdown() {
+    PUSH_SERVER.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecdf::ECDF;
+    use flate2::read::GzDecoder;
+    use futures::executor::block_on;
+    use http_body_util::BodyExt;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    fn get(accept_encoding: Option<&str>) -> (Option<String>, Bytes) {
+        let mut request = Request::get("/");
+        if let Some(encoding) = accept_encoding {
+            request = request.header("Accept-Encoding", encoding);
+        }
+        let response = block_on(serve(request.body(()).unwrap())).unwrap();
+        let encoding = response
+            .headers()
+            .get("Content-Encoding")
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = block_on(BodyExt::collect(response.into_body()))
+            .unwrap()
+            .to_bytes();
+        (encoding, body)
+    }
+
+    #[test]
+    fn gzip_assets() {
+        let (encoding, body) = get(Some("deflate, gzip;q=0.8"));
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&body[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, INDEX_HTML);
+
+        let (encoding, body) = get(None);
+        assert_eq!(encoding, None);
+        assert_eq!(body, INDEX_HTML);
+
+        let (encoding, _) = get(Some("gzip;q=0"));
+        assert_eq!(encoding, None);
+    }
+
+    #[test]
+    fn etag() {
+        let response = block_on(serve(Request::get("/js").body(()).unwrap())).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get("ETag").unwrap().clone();
+        assert!(response.headers().contains_key("Cache-Control"));
+
+        let request = Request::get("/js")
+            .header("If-None-Match", etag)
+            .body(())
+            .unwrap();
+        let response = block_on(serve(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let body = block_on(BodyExt::collect(response.into_body()))
+            .unwrap()
+            .to_bytes();
+        assert!(body.is_empty());
+
+        // A different version has to be sent in full.
+        let request = Request::get("/js")
+            .header("If-None-Match", "\"stale\"")
+            .body(())
+            .unwrap();
+        let response = block_on(serve(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn metrics_json() {
+        let mut mp = crate::MeterProvider::default();
+        let meter = mp.get_meter("test".into(), None, None, None);
+        let h: Arc<Mutex<crate::Histogram<f64>>> = meter
+            .create_histogram("metrics_json")
+            .add_attribute("host", "example.com".into())
+            .build();
+        h.lock().unwrap().record(2.0);
+        h.lock().unwrap().record(1.0);
+
+        let response = block_on(serve(Request::get("/metrics.json").body(()).unwrap())).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = block_on(BodyExt::collect(response.into_body()))
+            .unwrap()
+            .to_bytes();
+        let metrics: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        // Other tests register instruments too, so look for this one.
+        let metric = metrics
+            .iter()
+            .find(|m| m["name"] == "metrics_json")
+            .unwrap();
+        assert_eq!(metric["attributes"]["host"], "example.com");
+        let ecdf: ECDF<f64> = serde_json::from_value(metric["value"].clone()).unwrap();
+        assert_eq!(ecdf.len(), 2);
+        assert_eq!(ecdf.min(), Some(1.0));
+    }
+
+    #[test]
+    fn token() {
+        let server = crate::sse::Server::new(ServerConfig {
+            token: Some("secret".into()),
+            ..Default::default()
+        });
+        let status = |path: &str, token: Option<&str>| {
+            let mut request = Request::get(path);
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            route(&server, request.body(()).unwrap()).unwrap().status()
+        };
+
+        for path in ["/push", "/metrics.json", "/stats"] {
+            assert_eq!(status(path, None), StatusCode::UNAUTHORIZED, "{}", path);
+            assert_eq!(
+                status(path, Some("wrong")),
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                path
+            );
+            assert_eq!(status(path, Some("secret")), StatusCode::OK, "{}", path);
+        }
+        // The page itself is public, so that it can ask for the token.
+        assert_eq!(status("/", None), StatusCode::OK);
+        assert_eq!(status("/js", None), StatusCode::OK);
+    }
+}